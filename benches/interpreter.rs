@@ -0,0 +1,70 @@
+use std::io::{self, Cursor};
+
+use brainfart::expr::Expr;
+use brainfart::lexer::lex_string;
+use brainfart::parser::parse_tokens;
+use brainfart::progstate::ProgState;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const HELLO: &str = include_str!("../examples/hello.bf");
+
+/// Multiply 5 by 3 into the next cell and clear it, a thousand times over: mostly linear loops.
+fn mul_loops() -> String {
+    "+++++[>+++<-]>[-]<".repeat(1000)
+}
+
+/// Four nested loops of ten iterations with output in the innermost, so none of them can be
+/// turned into a linear loop.
+const NESTED: &str = "++++++++++[>++++++++++[>++++++++++[>++++++++++[>+.-<-]<-]<-]<-]";
+
+/// Echo the first character of each line until a line starting with a NUL.
+const CAT: &str = ",[.,]";
+
+fn parse(source: &str) -> Vec<Expr> {
+    parse_tokens(lex_string(source.to_string()).unwrap()).unwrap()
+}
+
+fn run(exprs: &[Expr], input: &[u8]) {
+    let mut state = ProgState::with_output(io::sink());
+    state.set_input(Cursor::new(input.to_vec()));
+    state.run(exprs).unwrap();
+}
+
+fn lex_and_parse(c: &mut Criterion) {
+    let source = HELLO.repeat(200);
+    c.bench_function("lex hello x200", |b| {
+        b.iter(|| lex_string(black_box(source.clone())).unwrap())
+    });
+    let tokens = lex_string(source).unwrap();
+    c.bench_function("parse hello x200", |b| {
+        b.iter(|| parse_tokens(black_box(tokens.clone())).unwrap())
+    });
+}
+
+fn run_mul_loops(c: &mut Criterion) {
+    let tokens = lex_string(mul_loops()).unwrap();
+    c.bench_function("parse mul loops", |b| {
+        b.iter(|| parse_tokens(black_box(tokens.clone())).unwrap())
+    });
+    let exprs = parse(&mul_loops());
+    c.bench_function("run mul loops", |b| b.iter(|| run(black_box(&exprs), b"")));
+}
+
+fn run_nested(c: &mut Criterion) {
+    let exprs = parse(NESTED);
+    c.bench_function("run nested loops", |b| {
+        b.iter(|| run(black_box(&exprs), b""))
+    });
+}
+
+fn run_cat(c: &mut Criterion) {
+    let exprs = parse(CAT);
+    let mut input = "a\n".repeat(10_000).into_bytes();
+    input.extend_from_slice(b"\0\n");
+    c.bench_function("run cat 10000 lines", |b| {
+        b.iter(|| run(black_box(&exprs), &input))
+    });
+}
+
+criterion_group!(benches, lex_and_parse, run_mul_loops, run_nested, run_cat);
+criterion_main!(benches);
@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, HashMap};
+use std::slice;
+
+use crate::error::{BrainfartError, BrainfartResult, BrainfartWarning};
+use crate::expr::{Expr, ExprType, LoopBlock};
+use crate::memo::LoopMemo;
+
+/// A LinearEffect describes what a single pass over a sequence of Exprs does to the tape when the
+/// sequence only moves the pointer and adds to or subtracts from cells. Offsets are relative to the
+/// cell the pointer was on before the sequence ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinearEffect {
+    /// The net change applied to each touched cell, keyed by offset
+    pub deltas: BTreeMap<i64, i64>,
+    /// The net pointer movement after the sequence has run
+    pub shift: i64,
+}
+
+/// Compute the LinearEffect of the given Exprs, or None if they contain anything other than pointer
+/// moves and cell additions/subtractions (input, output, sets or nested loops).
+pub fn linear_effect(exprs: &[Expr]) -> Option<LinearEffect> {
+    effect_of(exprs.iter())
+}
+
+/// Compute the LinearEffect of the given Exprs on the tape, like `linear_effect` but also allowing
+/// output, which leaves the tape as it was. A loop body such as `+.-` that only outputs in between
+/// canceling changes still never changes its condition cell.
+fn tape_effect(exprs: &[Expr]) -> Option<LinearEffect> {
+    effect_of(
+        exprs
+            .iter()
+            .filter(|expr| !matches!(expr.ty, ExprType::Output(_) | ExprType::OutputAt { .. })),
+    )
+}
+
+/// Compute the LinearEffect of the given Exprs, or None if any is not a pointer move or a cell
+/// addition/subtraction.
+fn effect_of<'a>(exprs: impl Iterator<Item = &'a Expr>) -> Option<LinearEffect> {
+    let mut deltas: BTreeMap<i64, i64> = BTreeMap::new();
+    let mut shift: i64 = 0;
+
+    for expr in exprs {
+        match expr.ty {
+            ExprType::Add(val) => *deltas.entry(shift).or_insert(0) += val as i64,
+            ExprType::Sub(val) => *deltas.entry(shift).or_insert(0) -= val as i64,
+            ExprType::MoveRight(val) => shift += val as i64,
+            ExprType::MoveLeft(val) => shift -= val as i64,
+            _ => return None,
+        }
+    }
+
+    deltas.retain(|_, delta| *delta != 0);
+    Some(LinearEffect { deltas, shift })
+}
+
+/// Check the program for loops that can never terminate once entered: loops whose body leaves the
+/// pointer where it started and never decreases the condition cell, such as `[+-]` or `[+.-]`,
+/// whatever it outputs along the way. This assumes strict arithmetic: with ArithMode::Wrapping
+/// such a loop terminates once the cell wraps.
+///
+/// Such a loop is an error if it is certain to be reached with its condition cell known to be
+/// nonzero: at the top level, or in the body of a loop that is certain to be entered. Otherwise it
+/// is a warning. A loop that may be skipped, such as one on a cell that was read from input, may
+/// never run its body, so the loops in that body are at most warned about.
+///
+/// Dead code after such a loop is not reported separately. A loop that is an error rejects the
+/// program outright, and the warning for any other loop already marks the point past which the
+/// program may never get. The Exprs after a loop that is skipped still run, so they are kept.
+///
+/// The program is walked with an explicit stack of the loop bodies being walked, each with what is
+/// known about the tape there and whether it is certain to be reached, so deeply nested loops do not
+/// grow the call stack. The LinearEffect
+/// of each distinct loop body is only computed once.
+pub fn check_infinite_loops(exprs: &[Expr]) -> BrainfartResult<Vec<BrainfartWarning>> {
+    let mut warnings: Vec<BrainfartWarning> = vec![];
+    let mut memo = LoopMemo::default();
+    let mut frames: Vec<(slice::Iter<Expr>, KnownTape, bool)> =
+        vec![(exprs.iter(), KnownTape::zeroed(), true)];
+    while let Some((body, tape, reached)) = frames.last_mut() {
+        let Some(expr) = body.next() else {
+            frames.pop();
+            continue;
+        };
+        match &expr.ty {
+            ExprType::LoopBlock(lb) => {
+                let reached = *reached;
+                let checked = check_loop_block(expr, lb, tape, reached, &mut memo, &mut warnings)?;
+                if let Some((inner, entered)) = checked {
+                    frames.push((inner.iter(), KnownTape::unknown(), entered));
+                }
+            }
+            _ => tape.track(expr),
+        }
+    }
+    Ok(warnings)
+}
+
+/// Check a single LoopBlock reached with the given tape, then update the tape to reflect that the
+/// loop has exited. `reached` is whether the loop is certain to be reached. Returns the loop's body
+/// if it still has to be walked, with nothing known about the tape, because it is not a simple
+/// enough loop to be checked as a whole, along with whether the body is certain to be reached.
+fn check_loop_block<'a>(
+    expr: &Expr,
+    lb: &'a LoopBlock,
+    tape: &mut KnownTape,
+    reached: bool,
+    memo: &mut LoopMemo<'a, Option<LinearEffect>>,
+    warnings: &mut Vec<BrainfartWarning>,
+) -> BrainfartResult<Option<(&'a [Expr], bool)>> {
+    let entry = tape.current();
+    if entry == Some(0) {
+        return Ok(None);
+    }
+
+    *tape = KnownTape::exited_loop();
+    // A body holding a nested loop never has a LinearEffect, so it is not hashed for the memo,
+    // which for deeply nested loops would take time in the depth of nesting at every level
+    let nested = lb
+        .exprs
+        .iter()
+        .any(|e| matches!(e.ty, ExprType::LoopBlock(_)));
+    let effect = match nested {
+        true => None,
+        false => memo.get_or_compute(&lb.exprs, tape_effect),
+    };
+    let Some(effect) = effect else {
+        return Ok(Some((&lb.exprs, reached && entry.is_some())));
+    };
+    let origin_delta = effect.deltas.get(&0).copied().unwrap_or(0);
+    if effect.shift == 0 && origin_delta >= 0 {
+        let token = expr.tokens[0];
+        match entry {
+            Some(_) if reached => return Err(BrainfartError::InfiniteLoop(token)),
+            _ => warnings.push(BrainfartWarning::InfiniteLoop(token)),
+        }
+    }
+    Ok(None)
+}
+
+/// Statically bound the highest cell index, relative to the starting cell, that running the
+/// program can reach, so `>>>` gives 3. Returns None if it is unbounded: the program scans right
+/// for a zero cell or contains a loop whose body moves the pointer by a net nonzero amount, so it
+/// could walk arbitrarily far. A program that switches tapes is also treated as unbounded, since
+/// each tape has its own pointer.
+pub fn max_tape_index(exprs: &[Expr]) -> Option<usize> {
+    let (_, max) = pointer_reach(exprs)?;
+    Some(max as usize)
+}
+
+/// Walk the given Exprs from offset 0, returning the offset they leave the pointer at and the
+/// highest offset they reach, or None if either is unbounded.
+fn pointer_reach(exprs: &[Expr]) -> Option<(i64, i64)> {
+    let mut pointer: i64 = 0;
+    let mut max: i64 = 0;
+
+    for expr in exprs {
+        match &expr.ty {
+            ExprType::MoveRight(val) => pointer += *val as i64,
+            ExprType::MoveLeft(val) => pointer -= *val as i64,
+            ExprType::ScanRight(_) | ExprType::SwitchTape(_) => return None,
+            ExprType::LinearLoop(targets) => {
+                let furthest = targets.iter().map(|(offset, _)| *offset).max();
+                max = max.max(pointer + furthest.unwrap_or(0));
+            }
+            ExprType::OutputAt { offset }
+            | ExprType::InputAt { offset }
+            | ExprType::MoveCell { offset } => {
+                max = max.max(pointer + offset);
+            }
+            ExprType::LoopBlock(lb) => {
+                let (shift, body_max) = pointer_reach(&lb.exprs)?;
+                if shift != 0 {
+                    return None;
+                }
+                max = max.max(pointer + body_max);
+            }
+            ExprType::EmitConst(prefix) => {
+                max = max.max(pointer + prefix.cells.len() as i64 - 1);
+                pointer += prefix.pointer as i64;
+            }
+            ExprType::Set(_)
+            | ExprType::Add(_)
+            | ExprType::Sub(_)
+            | ExprType::Output(_)
+            | ExprType::Input(_)
+            | ExprType::ScanLeft(_) => (),
+        }
+        max = max.max(pointer);
+    }
+
+    Some((pointer, max))
+}
+
+/// A KnownTape is the statically known portion of the tape while walking a program. Cells mapped to
+/// None hold an unknown value; cells that are not mapped are zero if `rest_zero` is set and unknown
+/// otherwise.
+struct KnownTape {
+    pointer: i64,
+    cells: HashMap<i64, Option<u32>>,
+    rest_zero: bool,
+}
+
+impl KnownTape {
+    /// The tape at the start of the program, where every cell is zero.
+    fn zeroed() -> Self {
+        KnownTape {
+            pointer: 0,
+            cells: HashMap::new(),
+            rest_zero: true,
+        }
+    }
+
+    /// A tape about which nothing is known.
+    fn unknown() -> Self {
+        KnownTape {
+            pointer: 0,
+            cells: HashMap::new(),
+            rest_zero: false,
+        }
+    }
+
+    /// The tape directly after a loop exits, where only the current cell is known to be zero.
+    fn exited_loop() -> Self {
+        let mut tape = KnownTape::unknown();
+        tape.set_current(Some(0));
+        tape
+    }
+
+    /// The known value of the cell under the pointer, if any.
+    fn current(&self) -> Option<u32> {
+        match self.cells.get(&self.pointer) {
+            Some(val) => *val,
+            None if self.rest_zero => Some(0),
+            None => None,
+        }
+    }
+
+    /// Record the value of the cell under the pointer.
+    fn set_current(&mut self, val: Option<u32>) {
+        self.cells.insert(self.pointer, val);
+    }
+
+    /// Update what is known about the tape after the given Expr runs. Only the exit of a LoopBlock
+    /// is tracked, not what its body does.
+    fn track(&mut self, expr: &Expr) {
+        match &expr.ty {
+            ExprType::Set(val) => self.set_current(Some(*val)),
+            ExprType::Add(val) => {
+                self.set_current(self.current().and_then(|x| x.checked_add(*val)))
+            }
+            ExprType::Sub(val) => {
+                self.set_current(self.current().and_then(|x| x.checked_sub(*val)))
+            }
+            ExprType::MoveRight(val) => self.pointer += *val as i64,
+            ExprType::MoveLeft(val) => self.pointer -= *val as i64,
+            ExprType::Output(_) | ExprType::OutputAt { .. } => (),
+            ExprType::Input(_) => self.set_current(None),
+            ExprType::InputAt { offset } => {
+                self.cells.insert(self.pointer + offset, None);
+            }
+            ExprType::ScanRight(_) | ExprType::ScanLeft(_) | ExprType::LoopBlock(_) => {
+                *self = KnownTape::exited_loop()
+            }
+            ExprType::LinearLoop(targets) => {
+                for (offset, _) in targets {
+                    self.cells.insert(self.pointer + offset, None);
+                }
+                self.set_current(Some(0));
+            }
+            ExprType::MoveCell { offset } => {
+                self.cells.insert(self.pointer + offset, None);
+                self.set_current(Some(0));
+            }
+            ExprType::EmitConst(prefix) => {
+                for (i, cell) in prefix.cells.iter().enumerate() {
+                    self.cells.insert(self.pointer + i as i64, Some(*cell));
+                }
+                self.pointer += prefix.pointer as i64;
+            }
+            ExprType::SwitchTape(_) => *self = KnownTape::unknown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io;
+
+    use crate::analysis::{check_infinite_loops, linear_effect, max_tape_index, LinearEffect};
+    use crate::error::{BrainfartError, BrainfartWarning};
+    use crate::expr::Expr;
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+    use crate::progstate::ProgState;
+    use crate::token::{Token, TokenType};
+
+    fn parse(source: &str) -> Vec<Expr> {
+        parse_tokens(lex_string(source.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn linear_effect_transfer() {
+        let exprs = parse("->>+++<<");
+        assert_eq!(
+            linear_effect(&exprs),
+            Some(LinearEffect {
+                deltas: BTreeMap::from([(0, -1), (2, 3)]),
+                shift: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn linear_effect_non_linear() {
+        assert_eq!(linear_effect(&parse("-.")), None);
+        assert_eq!(linear_effect(&parse("-[>]")), None);
+    }
+
+    #[test]
+    fn infinite_loop_known_entry() {
+        match check_infinite_loops(&parse("+[+]")) {
+            Ok(_) => panic!("infinite_loop_known_entry had Ok result"),
+            Err(BrainfartError::InfiniteLoop(tok)) => assert_eq!(
+                tok,
+                Token {
+                    ty: TokenType::IfZero,
+                    line: 1,
+                    col: 2,
+                }
+            ),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn infinite_loop_unchanged_cell() {
+        assert!(check_infinite_loops(&parse("+[>+<]")).is_err());
+    }
+
+    #[test]
+    fn infinite_loop_terminating() {
+        assert_eq!(check_infinite_loops(&parse("+[-]")).unwrap(), vec![]);
+        assert_eq!(check_infinite_loops(&parse("+[->+<]")).unwrap(), vec![]);
+        assert_eq!(check_infinite_loops(&parse("+[>]")).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn infinite_loop_self_canceling() {
+        assert!(check_infinite_loops(&parse("+[+-]")).is_err());
+        assert!(check_infinite_loops(&parse("+[-+]")).is_err());
+        assert!(check_infinite_loops(&parse("+[+.-]")).is_err());
+        assert!(check_infinite_loops(&parse("+[>.<+>-<-]")).is_err());
+        assert_eq!(check_infinite_loops(&parse("+[-]")).unwrap(), vec![]);
+        assert_eq!(check_infinite_loops(&parse("+[.-]")).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn infinite_loop_skipped() {
+        assert_eq!(check_infinite_loops(&parse("[+]")).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn infinite_loop_deeply_nested() {
+        let depth = 100000;
+        let source = format!("+{}->+<{}", "[".repeat(depth), "]".repeat(depth));
+        assert_eq!(check_infinite_loops(&parse(&source)).unwrap(), vec![]);
+        assert!(check_infinite_loops(&parse(&format!("{}+[+]", source))).is_err());
+    }
+
+    #[test]
+    fn infinite_loop_unknown_entry() {
+        assert_eq!(
+            check_infinite_loops(&parse(",[+]")).unwrap(),
+            vec![BrainfartWarning::InfiniteLoop(Token {
+                ty: TokenType::IfZero,
+                line: 1,
+                col: 2,
+            })]
+        );
+    }
+
+    #[test]
+    fn code_after_conditional_loop_kept() {
+        let exprs = parse(",[+]+.");
+        assert_eq!(check_infinite_loops(&exprs).unwrap().len(), 1);
+        assert_eq!(exprs.len(), 4);
+
+        let mut state = ProgState::with_output(vec![]);
+        state.set_input(io::empty());
+        state.run(&exprs).unwrap();
+        assert_eq!(state.output(), &[1]);
+    }
+
+    #[test]
+    fn infinite_loop_many_identical() {
+        let exprs = parse(&"+[[-]+>[->+<]<-]>".repeat(5000));
+        assert_eq!(check_infinite_loops(&exprs).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn infinite_loop_nested() {
+        assert!(check_infinite_loops(&parse("+[>[-]+[+]<-]")).is_err());
+        assert_eq!(check_infinite_loops(&parse("+[[+]-]")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn infinite_loop_in_skippable_loop() {
+        let source = format!(",[>[-]+[+]<-]{}.", "+".repeat(49));
+        assert_eq!(
+            check_infinite_loops(&parse(&source)).unwrap(),
+            vec![BrainfartWarning::InfiniteLoop(Token {
+                ty: TokenType::IfZero,
+                line: 1,
+                col: 8,
+            })]
+        );
+    }
+
+    #[test]
+    fn tape_index_bounded() {
+        assert_eq!(max_tape_index(&parse(">>>")), Some(3));
+        assert_eq!(max_tape_index(&parse("+[>>+.<<-]>")), Some(2));
+        assert_eq!(max_tape_index(&parse("+[->+>+<<]")), Some(2));
+    }
+
+    #[test]
+    fn tape_index_unbounded() {
+        assert_eq!(max_tape_index(&parse("[>]")), None);
+        assert_eq!(max_tape_index(&parse("+[>+.]")), None);
+    }
+}
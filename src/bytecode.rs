@@ -0,0 +1,118 @@
+use crate::error::BrainfartResult;
+use crate::expr::{ConstPrefix, Expr, ExprType};
+use crate::lexer::lex_runs;
+use crate::parser::parse_token_runs;
+
+/// An Instr is a single instruction of a program lowered to a flat sequence, for backends that
+/// would rather follow jumps than walk nested LoopBlocks. Each instruction other than a jump
+/// behaves like the ExprType of the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instr {
+    Set(u32),
+    Add(u32),
+    Sub(u32),
+    MoveRight(u32),
+    MoveLeft(u32),
+    Output(u32),
+    Input(u32),
+    OutputAt {
+        offset: i64,
+    },
+    InputAt {
+        offset: i64,
+    },
+    ScanRight(u32),
+    ScanLeft(u32),
+    LinearLoop(Vec<(i64, i64)>),
+    MoveCell {
+        offset: i64,
+    },
+    EmitConst(Box<ConstPrefix>),
+    SwitchTape(usize),
+    /// Jump to the instruction after the given index, the matching JumpIfNonZero, if the current
+    /// cell is zero
+    JumpIfZero(usize),
+    /// Jump to the instruction after the given index, the matching JumpIfZero, if the current cell
+    /// is nonzero
+    JumpIfNonZero(usize),
+}
+
+/// Lex, parse and lower the given source to Instrs. Loops the parser folds into a single Expr, such
+/// as `[-]`, become a single Instr without jumps.
+pub fn compile(source: &str) -> BrainfartResult<Vec<Instr>> {
+    let exprs = parse_token_runs(lex_runs(source.to_string())?)?;
+    Ok(lower(&exprs))
+}
+
+/// Lower the given Exprs to Instrs, replacing each LoopBlock with its body between a pair of
+/// matching jumps.
+pub fn lower(exprs: &[Expr]) -> Vec<Instr> {
+    let mut instrs: Vec<Instr> = vec![];
+    // The bodies being lowered, the position reached in each, and where each loop's JumpIfZero is
+    let mut frames: Vec<(&[Expr], usize, Option<usize>)> = vec![(exprs, 0, None)];
+
+    while let Some((body, position, open)) = frames.pop() {
+        if position == body.len() {
+            if let Some(open) = open {
+                instrs[open] = Instr::JumpIfZero(instrs.len());
+                instrs.push(Instr::JumpIfNonZero(open));
+            }
+            continue;
+        }
+        frames.push((body, position + 1, open));
+
+        let instr = match &body[position].ty {
+            ExprType::Set(val) => Instr::Set(*val),
+            ExprType::Add(val) => Instr::Add(*val),
+            ExprType::Sub(val) => Instr::Sub(*val),
+            ExprType::MoveRight(val) => Instr::MoveRight(*val),
+            ExprType::MoveLeft(val) => Instr::MoveLeft(*val),
+            ExprType::Output(val) => Instr::Output(*val),
+            ExprType::Input(val) => Instr::Input(*val),
+            ExprType::OutputAt { offset } => Instr::OutputAt { offset: *offset },
+            ExprType::InputAt { offset } => Instr::InputAt { offset: *offset },
+            ExprType::ScanRight(val) => Instr::ScanRight(*val),
+            ExprType::ScanLeft(val) => Instr::ScanLeft(*val),
+            ExprType::LinearLoop(targets) => Instr::LinearLoop(targets.clone()),
+            ExprType::MoveCell { offset } => Instr::MoveCell { offset: *offset },
+            ExprType::EmitConst(prefix) => Instr::EmitConst(prefix.clone()),
+            ExprType::SwitchTape(tape) => Instr::SwitchTape(*tape),
+            ExprType::LoopBlock(lb) => {
+                // Patched with the index of the matching JumpIfNonZero once the body is lowered
+                frames.push((&lb.exprs, 0, Some(instrs.len())));
+                Instr::JumpIfZero(0)
+            }
+        };
+        instrs.push(instr);
+    }
+
+    instrs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::{compile, Instr};
+
+    #[test]
+    fn compile_clear() {
+        assert_eq!(compile("[-]").unwrap(), vec![Instr::Set(0)]);
+    }
+
+    #[test]
+    fn compile_nested_jumps() {
+        assert_eq!(
+            compile("+[>[.]<-]").unwrap(),
+            vec![
+                Instr::Add(1),
+                Instr::JumpIfZero(8),
+                Instr::MoveRight(1),
+                Instr::JumpIfZero(5),
+                Instr::Output(1),
+                Instr::JumpIfNonZero(3),
+                Instr::MoveLeft(1),
+                Instr::Sub(1),
+                Instr::JumpIfNonZero(1),
+            ]
+        );
+    }
+}
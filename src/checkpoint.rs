@@ -0,0 +1,124 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::config::Cell;
+
+/// The first line of every saved Checkpoint, naming the format and its version.
+const HEADER: &str = "brainfart checkpoint 2";
+
+/// A Checkpoint is the state of a run partway through, taken with `ProgState::checkpoint` and
+/// picked back up with `ProgState::restore_checkpoint`: the cells up to the last nonzero one or the
+/// data pointer, the data pointer, the position reached in each loop body being run, and the steps
+/// and output bytes counted towards the limits so far. In the two-tape language it also holds which
+/// tape is active and the other tape's cells and data pointer; `other_cells` is empty if the second
+/// tape has not been used yet.
+///
+/// A Checkpoint only makes sense for the program it was taken from. It is saved as plain text, one
+/// field per line, with `to_string` and read back with `parse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub cells: Vec<Cell>,
+    pub pointer: usize,
+    pub tape: usize,
+    pub other_cells: Vec<Cell>,
+    pub other_pointer: usize,
+    pub positions: Vec<usize>,
+    pub steps: u64,
+    pub output_count: u64,
+}
+
+impl Display for Checkpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "pointer {}", self.pointer)?;
+        writeln!(f, "tape {}", self.tape)?;
+        writeln!(f, "steps {}", self.steps)?;
+        writeln!(f, "output {}", self.output_count)?;
+        write!(f, "positions")?;
+        for position in &self.positions {
+            write!(f, " {}", position)?;
+        }
+        write!(f, "\ncells")?;
+        for cell in &self.cells {
+            write!(f, " {}", cell)?;
+        }
+        write!(f, "\nother_pointer {}\nother_cells", self.other_pointer)?;
+        for cell in &self.other_cells {
+            write!(f, " {}", cell)?;
+        }
+        writeln!(f)
+    }
+}
+
+impl FromStr for Checkpoint {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(());
+        }
+        let mut field = |name: &str| -> Result<Vec<&str>, ()> {
+            let mut words = lines.next().ok_or(())?.split_whitespace();
+            if words.next() != Some(name) {
+                return Err(());
+            }
+            Ok(words.collect())
+        };
+        Ok(Checkpoint {
+            pointer: parse_single(field("pointer")?)?,
+            tape: parse_single(field("tape")?)?,
+            steps: parse_single(field("steps")?)?,
+            output_count: parse_single(field("output")?)?,
+            positions: parse_all(field("positions")?)?,
+            cells: parse_all(field("cells")?)?,
+            other_pointer: parse_single(field("other_pointer")?)?,
+            other_cells: parse_all(field("other_cells")?)?,
+        })
+    }
+}
+
+/// Parse the only value of a field.
+fn parse_single<T: FromStr>(words: Vec<&str>) -> Result<T, ()> {
+    match words[..] {
+        [word] => word.parse().map_err(|_| ()),
+        _ => Err(()),
+    }
+}
+
+/// Parse every value of a field.
+fn parse_all<T: FromStr>(words: Vec<&str>) -> Result<Vec<T>, ()> {
+    words
+        .into_iter()
+        .map(|word| word.parse().map_err(|_| ()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::Checkpoint;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let checkpoint = Checkpoint {
+            cells: vec![3, 0, 7],
+            pointer: 4,
+            tape: 1,
+            other_cells: vec![5],
+            other_pointer: 0,
+            positions: vec![2, 1],
+            steps: 120,
+            output_count: 5,
+        };
+        let text = checkpoint.to_string();
+        assert_eq!(
+            text,
+            concat!(
+                "brainfart checkpoint 2\npointer 4\ntape 1\nsteps 120\noutput 5\n",
+                "positions 2 1\ncells 3 0 7\nother_pointer 0\nother_cells 5\n"
+            )
+        );
+        assert_eq!(text.parse(), Ok(checkpoint));
+        assert_eq!("pointer 4\n".parse::<Checkpoint>(), Err(()));
+    }
+}
@@ -0,0 +1,299 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The value held by a cell. Cells are stored as a u32 unless the `unbounded` feature is enabled,
+/// which widens them to a u128 so that they can go past u32::MAX with `unbounded_cells`.
+#[cfg(not(feature = "unbounded"))]
+pub type Cell = u32;
+#[cfg(feature = "unbounded")]
+pub type Cell = u128;
+
+/// A Config holds the policies that change how a ProgState runs a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// What to write when a cell that is not a valid character is output
+    pub invalid_output: InvalidOutput,
+    /// What to do when a cell is incremented past its maximum or decremented below zero
+    pub arith: ArithMode,
+    /// Whether cells can hold values up to u128::MAX instead of u32::MAX. Cells only go past
+    /// u32::MAX when the `unbounded` feature is enabled.
+    pub unbounded_cells: bool,
+    /// How wide cells are when they are not unbounded
+    pub cell_size: CellWidth,
+    /// The number of cells the tape can reach before a warning is given
+    pub tape_report: usize,
+    /// Whether the tape is fixed at `tape_size` cells instead of growing as the pointer moves right
+    pub no_grow: bool,
+    /// The number of cells in the tape when it cannot grow
+    pub tape_size: usize,
+    /// The cell to set to 1 after each input that hits the end of input, and to 0 otherwise
+    pub eof_flag_cell: Option<usize>,
+    /// Whether to warn the first time a control character other than a newline or tab is output
+    pub warn_control_output: bool,
+    /// Whether to warn the first time a cell the program never wrote is output or tested by a loop
+    pub warn_uninit: bool,
+    /// Whether to stop a loop that does no input or output with an error once the tape is in the
+    /// same state at the top of two of its iterations
+    pub detect_hangs: bool,
+    /// The cells that may not be written once the program reaches a given point
+    pub readonly: Option<ReadOnly>,
+    /// The limits the program is stopped at
+    pub limits: Limits,
+    /// The pause after output, if any, so that it appears progressively
+    pub output_delay: Option<OutputDelay>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            invalid_output: InvalidOutput::default(),
+            arith: ArithMode::default(),
+            unbounded_cells: false,
+            cell_size: CellWidth::default(),
+            tape_report: 30000,
+            no_grow: false,
+            tape_size: 30000,
+            eof_flag_cell: None,
+            warn_control_output: false,
+            warn_uninit: false,
+            detect_hangs: false,
+            readonly: None,
+            limits: Limits::default(),
+            output_delay: None,
+        }
+    }
+}
+
+impl Config {
+    /// The policies closest to canonical brainfuck: cells that wrap around, a fixed tape of 30000
+    /// cells, and `,` leaving the cell unchanged at the end of input. None of them depend on the
+    /// environment the program runs in.
+    pub fn canonical() -> Self {
+        Config {
+            invalid_output: InvalidOutput::Space,
+            arith: ArithMode::Wrapping,
+            unbounded_cells: false,
+            cell_size: CellWidth::default(),
+            tape_report: 30000,
+            no_grow: true,
+            tape_size: 30000,
+            eof_flag_cell: None,
+            warn_control_output: false,
+            warn_uninit: false,
+            detect_hangs: false,
+            readonly: None,
+            limits: Limits::default(),
+            output_delay: None,
+        }
+    }
+
+    /// Canonical brainfuck as most implementations and programs expect it: the policies of
+    /// `canonical` with 8-bit cells, so that `-` on a zero cell gives 255 and moving left of the
+    /// first cell or past the 30000th is an error.
+    pub fn strict() -> Self {
+        Config {
+            cell_size: CellWidth::Bits8,
+            ..Config::canonical()
+        }
+    }
+
+    /// The largest value a cell can hold.
+    pub fn cell_max(&self) -> Cell {
+        match self.unbounded_cells {
+            true => Cell::MAX,
+            false => self.cell_size.max(),
+        }
+    }
+
+    /// The width of a cell in bits.
+    pub fn cell_width(&self) -> u32 {
+        Cell::BITS - self.cell_max().leading_zeros()
+    }
+
+    /// The policies for running programs that cannot be trusted: the tape is fixed at
+    /// `tape_size` cells and every Limit is set to its sandbox default unless already set.
+    pub fn sandboxed(self) -> Self {
+        Config {
+            no_grow: true,
+            limits: Limits {
+                steps: self.limits.steps.or(Limits::SANDBOX.steps),
+                output: self.limits.output.or(Limits::SANDBOX.output),
+                time: self.limits.time.or(Limits::SANDBOX.time),
+                source_bytes: self.limits.source_bytes.or(Limits::SANDBOX.source_bytes),
+                tokens: self.limits.tokens.or(Limits::SANDBOX.tokens),
+            },
+            ..self
+        }
+    }
+}
+
+/// Limits on how much a program may do before it is stopped with an error. None means unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The number of Exprs and loop iterations that may run
+    pub steps: Option<u64>,
+    /// The number of bytes that may be output
+    pub output: Option<u64>,
+    /// How long the program may run for
+    pub time: Option<Duration>,
+    /// The number of bytes of source that may be read
+    pub source_bytes: Option<u64>,
+    /// The number of commands the source may contain
+    pub tokens: Option<u64>,
+}
+
+impl Limits {
+    /// The limits used for programs that cannot be trusted: enough for typical programs to finish,
+    /// while keeping a runaway one from using more than a few seconds or a megabyte of output, and
+    /// an oversized one from being read in full.
+    pub const SANDBOX: Limits = Limits {
+        steps: Some(100_000_000),
+        output: Some(1 << 20),
+        time: Some(Duration::from_secs(10)),
+        source_bytes: Some(1 << 20),
+        tokens: Some(1 << 20),
+    };
+}
+
+/// An OutputDelay is a pause after each byte or each line of output, so that output renders
+/// progressively, such as for animations in a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputDelay {
+    pub duration: Duration,
+    pub per_line: bool,
+}
+
+impl OutputDelay {
+    /// How long to pause after writing the given bytes: the duration once for each byte, or once
+    /// for each newline if `per_line` is set.
+    pub fn after(&self, bytes: &[u8]) -> Duration {
+        let pauses = match self.per_line {
+            true => bytes.iter().filter(|&&byte| byte == b'\n').count(),
+            false => bytes.len(),
+        };
+        self.duration * pauses as u32
+    }
+}
+
+/// A ReadOnly region is the cells below `below`, which the program may set up at the start but
+/// which are an error to write once it reaches the point `from`. This catches a program clobbering
+/// the constants it keeps at the start of the tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOnly {
+    pub below: usize,
+    pub from: ReadOnlyStart,
+}
+
+/// The point from which a ReadOnly region can no longer be written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReadOnlyStart {
+    /// Once the program has output anything
+    #[default]
+    FirstOutput,
+    /// Once the program has run more than the given number of steps
+    Step(u64),
+}
+
+impl FromStr for ReadOnlyStart {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "output" => Ok(ReadOnlyStart::FirstOutput),
+            _ => s.parse().map(ReadOnlyStart::Step).map_err(|_| ()),
+        }
+    }
+}
+
+/// How cell arithmetic behaves at the edges of the range a cell can hold.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ArithMode {
+    /// Stop the program with an error
+    #[default]
+    Strict,
+    /// Wrap around to the other end of the range
+    Wrapping,
+    /// Stay at the end of the range that was passed. This applies to each optimized Expr as a
+    /// whole, so a run such as `++--` that cancels out never saturates
+    Saturating,
+}
+
+/// The number of bits a cell holds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CellWidth {
+    Bits8,
+    Bits16,
+    #[default]
+    Bits32,
+}
+
+impl CellWidth {
+    /// The number of bits a cell holds.
+    pub fn bits(&self) -> u32 {
+        match self {
+            CellWidth::Bits8 => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32,
+        }
+    }
+
+    /// The largest value a cell can hold.
+    pub fn max(&self) -> Cell {
+        Cell::MAX >> (Cell::BITS - self.bits())
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellWidth::Bits8),
+            "16" => Ok(CellWidth::Bits16),
+            "32" => Ok(CellWidth::Bits32),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What to do when outputting a cell whose value is not a valid Unicode scalar value, such as a
+/// surrogate code point.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidOutput {
+    /// Write a single space
+    #[default]
+    Space,
+    /// Write the Unicode replacement character U+FFFD
+    Replace,
+    /// Write the cell's raw value as four little-endian bytes
+    Bytes,
+    /// Stop the program with an error
+    Error,
+}
+
+impl FromStr for InvalidOutput {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "space" => Ok(InvalidOutput::Space),
+            "replace" => Ok(InvalidOutput::Replace),
+            "bytes" => Ok(InvalidOutput::Bytes),
+            "error" => Ok(InvalidOutput::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for ArithMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(ArithMode::Strict),
+            "wrapping" => Ok(ArithMode::Wrapping),
+            "saturating" => Ok(ArithMode::Saturating),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+use crate::expr::{Expr, ExprType};
+
+/// The nominal cost of a single cell or pointer update: Set, Add, Sub, MoveRight and MoveLeft. A
+/// LinearLoop costs this much for the cell it zeroes and for each cell it adds to.
+pub const BASIC_COST: u64 = 1;
+/// The nominal cost of each character read or written by Input and Output.
+pub const IO_COST: u64 = 1;
+/// The nominal cost of a scan for a zero cell, which may visit several cells.
+pub const SCAN_COST: u64 = 2;
+/// The nominal cost of entering a LoopBlock, checking its condition and jumping back.
+pub const LOOP_OVERHEAD: u64 = 2;
+
+/// A CostTable gives the nominal cost of each kind of Expr, so that estimates can reflect a
+/// particular target. The default is the costs above.
+///
+/// It can be parsed from comma-separated `name=cost` pairs, such as `io=10,loop=4`, with the names
+/// `basic`, `io`, `scan` and `loop`. Costs that are not given keep their default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostTable {
+    pub basic: u64,
+    pub io: u64,
+    pub scan: u64,
+    pub loop_overhead: u64,
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        CostTable {
+            basic: BASIC_COST,
+            io: IO_COST,
+            scan: SCAN_COST,
+            loop_overhead: LOOP_OVERHEAD,
+        }
+    }
+}
+
+impl FromStr for CostTable {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut table = CostTable::default();
+        for pair in s.split(',') {
+            let (name, cost) = pair.split_once('=').ok_or(())?;
+            let cost = cost.parse().map_err(|_| ())?;
+            match name {
+                "basic" => table.basic = cost,
+                "io" => table.io = cost,
+                "scan" => table.scan = cost,
+                "loop" => table.loop_overhead = cost,
+                _ => return Err(()),
+            }
+        }
+        Ok(table)
+    }
+}
+
+impl CostTable {
+    /// Statically estimate the cost of running the given Exprs with this table, assuming that
+    /// every LoopBlock body runs exactly once.
+    pub fn estimate(&self, exprs: &[Expr]) -> u64 {
+        exprs.iter().map(|expr| self.expr_cost(&expr.ty)).sum()
+    }
+
+    /// The cost of running a single Expr with this table, including the body of a LoopBlock.
+    pub fn expr_cost(&self, ty: &ExprType) -> u64 {
+        match ty {
+            ExprType::Set(_)
+            | ExprType::Add(_)
+            | ExprType::Sub(_)
+            | ExprType::MoveRight(_)
+            | ExprType::MoveLeft(_)
+            | ExprType::SwitchTape(_) => self.basic,
+            ExprType::Output(val) | ExprType::Input(val) => self.io * *val as u64,
+            ExprType::OutputAt { .. } | ExprType::InputAt { .. } => self.io,
+            ExprType::ScanRight(_) | ExprType::ScanLeft(_) => self.scan,
+            ExprType::LinearLoop(targets) => self.basic * (targets.len() as u64 + 1),
+            ExprType::MoveCell { .. } => self.basic * 2,
+            ExprType::LoopBlock(lb) => self.loop_overhead + self.estimate(&lb.exprs),
+            ExprType::EmitConst(prefix) => {
+                self.io * prefix.bytes.len() as u64 + self.basic * prefix.cells.len() as u64
+            }
+        }
+    }
+}
+
+/// Statically estimate the cost of running the given Exprs, assuming that every LoopBlock body
+/// runs exactly once. This is a rough metric for comparing optimizations without running the
+/// program, not a prediction of running time.
+pub fn estimate_cost(exprs: &[Expr]) -> u64 {
+    CostTable::default().estimate(exprs)
+}
+
+/// The nominal cost of running a single Expr, including the body of a LoopBlock.
+pub fn expr_cost(ty: &ExprType) -> u64 {
+    CostTable::default().expr_cost(ty)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cost::{estimate_cost, CostTable};
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+
+    fn estimate(source: &str) -> u64 {
+        estimate_cost(&parse_tokens(lex_string(source.to_string()).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn estimate_straight_line() {
+        // Add(3), MoveRight(2), Sub(1), Output(2)
+        assert_eq!(estimate("+++>>-.."), 5);
+    }
+
+    #[test]
+    fn estimate_loops() {
+        // Add(2) + (overhead + Sub, MoveRight, Add, MoveLeft, Output) + Output(1)
+        assert_eq!(estimate("++[->+<.]."), 9);
+        // Add(2) + LinearLoop with one target + Output(1)
+        assert_eq!(estimate("++[->+<]."), 4);
+        // Set(0), MoveRight(1), ScanRight(1), and a nested loop with overhead counted once per level
+        assert_eq!(
+            estimate("[-]>[>],[>[-]<-]"),
+            1 + 1 + 2 + 1 + (2 + 1 + 1 + 1 + 1)
+        );
+    }
+
+    #[test]
+    fn custom_loop_overhead() {
+        let exprs = parse_tokens(lex_string("+[>+[>.<-]<-]".to_string()).unwrap()).unwrap();
+        let table: CostTable = "loop=10".parse().unwrap();
+        assert_eq!(
+            table,
+            CostTable {
+                loop_overhead: 10,
+                ..CostTable::default()
+            }
+        );
+        // Two loops, each now costing 8 more to enter
+        assert_eq!(table.estimate(&exprs), estimate_cost(&exprs) + 2 * 8);
+        assert!("loop=ten".parse::<CostTable>().is_err());
+        assert!("jump=1".parse::<CostTable>().is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeSet;
+
+use crate::expr::{Expr, ExprType};
+use crate::token::Token;
+
+/// A Coverage holds the first Token of each Expr that has run, so that the Exprs a run never
+/// reached can be found with `uncovered`.
+pub type Coverage = BTreeSet<Token>;
+
+/// The first Token of each of the given Exprs, including those inside loops, that the given
+/// Coverage does not hold, ordered by position.
+pub fn uncovered(exprs: &[Expr], coverage: &Coverage) -> Vec<Token> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut pending: Vec<&[Expr]> = vec![exprs];
+    while let Some(body) = pending.pop() {
+        for expr in body {
+            if !coverage.contains(&expr.tokens[0]) {
+                tokens.push(expr.tokens[0]);
+            }
+            if let ExprType::LoopBlock(lb) = &expr.ty {
+                pending.push(&lb.exprs);
+            }
+        }
+    }
+    tokens.sort();
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coverage::uncovered;
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+    use crate::progstate::ProgState;
+    use crate::token::{Token, TokenType};
+
+    #[test]
+    fn uncovered_loop_body() {
+        let exprs = parse_tokens(lex_string("+.>[.<+>,]+".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.enable_coverage();
+        state.run(&exprs).unwrap();
+        let coverage = state.take_coverage().unwrap();
+
+        assert_eq!(
+            uncovered(&exprs, &coverage),
+            vec![
+                Token::from(TokenType::Output, 1, 5),
+                Token::from(TokenType::PointDec, 1, 6),
+                Token::from(TokenType::ValInc, 1, 7),
+                Token::from(TokenType::PointInc, 1, 8),
+                Token::from(TokenType::Input, 1, 9),
+            ]
+        );
+        assert_eq!(coverage.len(), 5);
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+
+use crate::config::{Cell, Config};
+use crate::error::BrainfartResult;
+use crate::expr::Expr;
+use crate::progstate::{ProgState, TapeDump};
+
+/// A Behavior is everything a run of a program can be observed to do: the output it wrote and the
+/// tape it left behind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Behavior {
+    pub output: Vec<u8>,
+    pub tape: TapeDump,
+}
+
+/// A Difference is the first way in which two Behaviors were seen to differ, comparing output
+/// before the final tape. Output bytes and cells are given for the first Behavior, then the
+/// second; a missing byte means that output had already ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difference {
+    Output {
+        index: usize,
+        first: Option<u8>,
+        second: Option<u8>,
+    },
+    Cell {
+        index: usize,
+        first: Cell,
+        second: Cell,
+    },
+    Pointer {
+        first: usize,
+        second: usize,
+    },
+}
+
+impl Behavior {
+    /// Run the given Exprs on a fresh tape with the given Config, reading the given input, and
+    /// record what they did.
+    pub fn of(exprs: &[Expr], input: &[u8], config: &Config) -> BrainfartResult<Self> {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(config.clone());
+        state.set_input(Cursor::new(input.to_vec()));
+        state.run(exprs)?;
+        Ok(Behavior {
+            tape: state.tape_dump(),
+            output: state.output().clone(),
+        })
+    }
+
+    /// The first Difference between this Behavior and the given one, or None if they are the same.
+    pub fn first_difference(&self, other: &Behavior) -> Option<Difference> {
+        let len = self.output.len().max(other.output.len());
+        if let Some(index) = (0..len).find(|&i| self.output.get(i) != other.output.get(i)) {
+            return Some(Difference::Output {
+                index,
+                first: self.output.get(index).copied(),
+                second: other.output.get(index).copied(),
+            });
+        }
+
+        let cells = (&self.tape.cells, &other.tape.cells);
+        let len = cells.0.len().max(cells.1.len());
+        let cell = |cells: &Vec<Cell>, i: usize| cells.get(i).copied().unwrap_or(0);
+        if let Some(index) = (0..len).find(|&i| cell(cells.0, i) != cell(cells.1, i)) {
+            return Some(Difference::Cell {
+                index,
+                first: cell(cells.0, index),
+                second: cell(cells.1, index),
+            });
+        }
+
+        (self.tape.pointer != other.tape.pointer).then_some(Difference::Pointer {
+            first: self.tape.pointer,
+            second: other.tape.pointer,
+        })
+    }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Output {
+                index,
+                first,
+                second,
+            } => write!(
+                f,
+                "output differs at byte {}: {} vs {}",
+                index,
+                describe_byte(*first),
+                describe_byte(*second)
+            ),
+            Difference::Cell {
+                index,
+                first,
+                second,
+            } => write!(
+                f,
+                "final tape differs at cell {}: {} vs {}",
+                index, first, second
+            ),
+            Difference::Pointer { first, second } => {
+                write!(f, "final data pointer differs: {} vs {}", first, second)
+            }
+        }
+    }
+}
+
+/// Describe an output byte, quoted and escaped, or the end of output.
+fn describe_byte(byte: Option<u8>) -> String {
+    match byte {
+        Some(byte) => format!("'{}'", byte.escape_ascii()),
+        None => "end of output".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::equiv::{Behavior, Difference};
+    use crate::expr::Expr;
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+
+    fn behavior(source: &str, input: &[u8]) -> Behavior {
+        let exprs: Vec<Expr> = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+        Behavior::of(&exprs, input, &Config::canonical()).unwrap()
+    }
+
+    #[test]
+    fn equivalent_programs() {
+        let first = behavior("++++++++[>++++++++<-]>+.,.", b"b");
+        let second = behavior("+++++[>+++++++++++++<-]>.,.", b"b");
+        assert_eq!(first.first_difference(&second), None);
+    }
+
+    #[test]
+    fn first_differing_byte() {
+        let first = behavior("++++++++[>++++++++<-]>+.+.", b"");
+        let second = behavior("++++++++[>++++++++<-]>+.++.", b"");
+        let difference = first.first_difference(&second).unwrap();
+        assert_eq!(
+            difference,
+            Difference::Output {
+                index: 1,
+                first: Some(b'B'),
+                second: Some(b'C'),
+            }
+        );
+        assert_eq!(
+            difference.to_string(),
+            "output differs at byte 1: 'B' vs 'C'"
+        );
+    }
+
+    #[test]
+    fn tape_difference() {
+        let first = behavior("+>++", b"");
+        let second = behavior("+>+++<", b"");
+        assert_eq!(
+            first.first_difference(&second),
+            Some(Difference::Cell {
+                index: 1,
+                first: 2,
+                second: 3,
+            })
+        );
+    }
+}
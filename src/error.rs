@@ -1,6 +1,8 @@
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
+use crate::config::Cell;
 use crate::token::Token;
 
 pub type BrainfartResult<T> = Result<T, BrainfartError>;
@@ -12,44 +14,265 @@ pub enum BrainfartError {
     UnmatchedOpenBracket,
     UnmatchedCloseBracket(Token),
     PointZeroDec(Token),
+    PointMaxInc(Token),
     ValZeroDec(Token),
+    ValMaxInc(Token, Cell),
     Io(Token),
+    Output(Token),
+    InvalidChar(Token, Cell),
+    InfiniteLoop(Token),
+    ReadOnlyWrite(Token, usize),
+    LimitExceeded {
+        kind: LimitKind,
+        value: u64,
+        token: Option<Token>,
+    },
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+    FilesFailed(usize),
 }
 
 impl Error for BrainfartError {}
 
-impl Display for BrainfartError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl BrainfartError {
+    /// The line and column of the token this error occurred at, or None if it does not refer to a
+    /// position in the source.
+    pub fn position(&self) -> Option<(u32, u32)> {
+        match self {
+            BrainfartError::UnmatchedCloseBracket(tok)
+            | BrainfartError::PointZeroDec(tok)
+            | BrainfartError::PointMaxInc(tok)
+            | BrainfartError::ValZeroDec(tok)
+            | BrainfartError::ValMaxInc(tok, _)
+            | BrainfartError::Io(tok)
+            | BrainfartError::Output(tok)
+            | BrainfartError::InvalidChar(tok, _)
+            | BrainfartError::InfiniteLoop(tok)
+            | BrainfartError::ReadOnlyWrite(tok, _) => Some((tok.line, tok.col)),
+            BrainfartError::LimitExceeded { token, .. } => token.map(|tok| (tok.line, tok.col)),
+            BrainfartError::UnmatchedOpenBracket
+            | BrainfartError::UnknownFlag(_)
+            | BrainfartError::MissingValue(_)
+            | BrainfartError::InvalidValue(_, _)
+            | BrainfartError::FilesFailed(_) => None,
+        }
+    }
+}
+
+impl BrainfartError {
+    /// A short snake_case name for the kind of error, for tools that read errors.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BrainfartError::UnmatchedOpenBracket => "unmatched_open_bracket",
+            BrainfartError::UnmatchedCloseBracket(_) => "unmatched_close_bracket",
+            BrainfartError::PointZeroDec(_) => "pointer_underflow",
+            BrainfartError::PointMaxInc(_) => "pointer_overflow",
+            BrainfartError::ValZeroDec(_) => "value_underflow",
+            BrainfartError::ValMaxInc(_, _) => "value_overflow",
+            BrainfartError::Io(_) => "input",
+            BrainfartError::Output(_) => "output",
+            BrainfartError::InvalidChar(_, _) => "invalid_char",
+            BrainfartError::InfiniteLoop(_) => "infinite_loop",
+            BrainfartError::ReadOnlyWrite(_, _) => "read_only_write",
+            BrainfartError::LimitExceeded { .. } => "limit_exceeded",
+            BrainfartError::UnknownFlag(_) => "unknown_flag",
+            BrainfartError::MissingValue(_) => "missing_value",
+            BrainfartError::InvalidValue(_, _) => "invalid_value",
+            BrainfartError::FilesFailed(_) => "files_failed",
+        }
+    }
+
+    /// The description of the error, without the position that Display puts before it.
+    pub fn message(&self) -> String {
         match self {
             BrainfartError::UnmatchedOpenBracket => {
-                write!(f, "ERROR: Missing matching closing bracket ]")
+                "Missing matching closing bracket ]".to_string()
+            }
+            BrainfartError::UnmatchedCloseBracket(_) => {
+                "Encountered unmatched closing bracket ]".to_string()
+            }
+            BrainfartError::PointZeroDec(_) => {
+                "Attempted to decrement pointer that is at index 0".to_string()
+            }
+            BrainfartError::PointMaxInc(_) => {
+                "Attempted to increment pointer past the end of the tape".to_string()
+            }
+            BrainfartError::ValZeroDec(_) => "Attempted to decrement value that is 0".to_string(),
+            BrainfartError::ValMaxInc(_, max) => {
+                format!("Attempted to increment value past {}", max)
+            }
+            BrainfartError::Io(_) => "Failed to read character from input".to_string(),
+            BrainfartError::Output(_) => "Failed to write character to output".to_string(),
+            BrainfartError::InvalidChar(_, val) => format!(
+                "Attempted to output value {} that is not a valid character",
+                val
+            ),
+            BrainfartError::InfiniteLoop(_) => "Loop can never terminate once entered".to_string(),
+            BrainfartError::ReadOnlyWrite(_, index) => {
+                format!("Attempted to write to read-only cell {}", index)
             }
-            BrainfartError::UnmatchedCloseBracket(tok) => {
+            BrainfartError::LimitExceeded { kind, value, .. } => format!(
+                "Exceeded the {} limit of {} {}",
+                kind.name(),
+                value,
+                kind.unit()
+            ),
+            BrainfartError::UnknownFlag(flag) => format!("Unknown flag {}", flag),
+            BrainfartError::MissingValue(flag) => format!("Missing value for flag {}", flag),
+            BrainfartError::InvalidValue(flag, value) => {
+                format!("Invalid value {} for flag {}", value, flag)
+            }
+            BrainfartError::FilesFailed(1) => "1 file failed".to_string(),
+            BrainfartError::FilesFailed(count) => format!("{} files failed", count),
+        }
+    }
+
+    /// The error as a single-line JSON object with its kind, line, column and message, where the
+    /// line and column are null if the error has no position.
+    pub fn to_json(&self) -> String {
+        let (line, col) = match self.position() {
+            Some((line, col)) => (line.to_string(), col.to_string()),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        format!(
+            "{{\"kind\":\"{}\",\"line\":{},\"col\":{},\"message\":{}}}",
+            self.kind(),
+            line,
+            col,
+            json_string(&self.message())
+        )
+    }
+}
+
+impl Display for BrainfartError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.position() {
+            Some((line, col)) => write!(f, "ERROR line {} col {}: {}", line, col, self.message()),
+            None => write!(f, "ERROR: {}", self.message()),
+        }
+    }
+}
+
+/// Quote the given text as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The format errors are printed in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// A line of text for people to read
+    #[default]
+    Text,
+    /// A JSON object per error, from `BrainfartError::to_json`
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The limit a LimitExceeded error stopped the program at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    /// The number of Exprs and loop iterations run
+    Steps,
+    /// The time spent running, in milliseconds
+    Time,
+    /// The number of cells in a tape that cannot grow
+    Tape,
+    /// The number of bytes output
+    Output,
+    /// The number of bytes of source read
+    Source,
+    /// The number of commands in the source
+    Tokens,
+}
+
+impl LimitKind {
+    /// The name of the limit, as used in error messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LimitKind::Steps => "step",
+            LimitKind::Time => "time",
+            LimitKind::Tape => "tape",
+            LimitKind::Output => "output",
+            LimitKind::Source => "source size",
+            LimitKind::Tokens => "command count",
+        }
+    }
+
+    /// The unit the limit's value is measured in.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            LimitKind::Steps => "steps",
+            LimitKind::Time => "ms",
+            LimitKind::Tape => "cells",
+            LimitKind::Output => "bytes",
+            LimitKind::Source => "bytes",
+            LimitKind::Tokens => "commands",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// Possible non-fatal problems found while analyzing a program. Warnings are reported but do not
+/// stop the program from running.
+pub enum BrainfartWarning {
+    InfiniteLoop(Token),
+    TapeSize(Token, usize),
+    ControlOutput(Token, u32),
+    UninitRead(Token, usize),
+}
+
+impl Display for BrainfartWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BrainfartWarning::InfiniteLoop(tok) => {
                 write!(
                     f,
-                    "ERROR line {} col {}: Encountered unmatched closing bracket ]",
+                    "WARNING line {} col {}: Loop can never terminate if it is entered",
                     tok.line, tok.col
                 )
             }
-            BrainfartError::PointZeroDec(tok) => {
+            BrainfartWarning::TapeSize(tok, size) => {
                 write!(
                     f,
-                    "ERROR line {} col {}: Attempted to decrement pointer that is at index 0",
-                    tok.line, tok.col
+                    "WARNING line {} col {}: Tape grew past {} cells",
+                    tok.line, tok.col, size
                 )
             }
-            BrainfartError::ValZeroDec(tok) => {
+            BrainfartWarning::ControlOutput(tok, val) => {
                 write!(
                     f,
-                    "ERROR line {} col {}: Attempted to decrement value that is 0",
-                    tok.line, tok.col
+                    "WARNING line {} col {}: Output control character {}, which may not display correctly",
+                    tok.line, tok.col, val
                 )
             }
-            BrainfartError::Io(tok) => {
+            BrainfartWarning::UninitRead(tok, index) => {
                 write!(
                     f,
-                    "ERROR line {} col {}: Failed to read character from input",
-                    tok.line, tok.col
+                    "WARNING line {} col {}: Read cell {}, which the program never wrote",
+                    tok.line, tok.col, index
                 )
             }
         }
@@ -58,7 +281,8 @@ impl Display for BrainfartError {
 
 #[cfg(test)]
 mod tests {
-    use crate::error::{BrainfartError, BrainfartResult};
+    use crate::config::Cell;
+    use crate::error::{BrainfartError, BrainfartResult, BrainfartWarning, LimitKind};
     use crate::token::{Token, TokenType};
 
     #[test]
@@ -124,6 +348,58 @@ mod tests {
         };
     }
 
+    #[test]
+    fn error_positions() {
+        let positioned = [
+            BrainfartError::UnmatchedCloseBracket(Token::from(TokenType::IfNonZero, 1, 2)),
+            BrainfartError::PointZeroDec(Token::from(TokenType::PointDec, 3, 4)),
+            BrainfartError::PointMaxInc(Token::from(TokenType::PointInc, 5, 6)),
+            BrainfartError::ValZeroDec(Token::from(TokenType::ValDec, 7, 8)),
+            BrainfartError::ValMaxInc(Token::from(TokenType::ValInc, 9, 10), 255),
+            BrainfartError::Io(Token::from(TokenType::Input, 11, 12)),
+            BrainfartError::Output(Token::from(TokenType::Output, 13, 14)),
+            BrainfartError::InvalidChar(Token::from(TokenType::Output, 15, 16), 0xD800),
+            BrainfartError::InfiniteLoop(Token::from(TokenType::IfZero, 17, 18)),
+            BrainfartError::ReadOnlyWrite(Token::from(TokenType::ValInc, 19, 20), 3),
+        ];
+        for (i, err) in positioned.iter().enumerate() {
+            let line = 2 * i as u32 + 1;
+            assert_eq!(err.position(), Some((line, line + 1)), "{}", err);
+        }
+
+        assert_eq!(BrainfartError::UnmatchedOpenBracket.position(), None);
+        assert_eq!(
+            BrainfartError::UnknownFlag("--nope".to_string()).position(),
+            None
+        );
+        assert_eq!(
+            BrainfartError::MissingValue("--gen".to_string()).position(),
+            None
+        );
+        assert_eq!(
+            BrainfartError::InvalidValue("--tape-size".to_string(), "x".to_string()).position(),
+            None
+        );
+    }
+
+    #[test]
+    fn point_inc_error() {
+        let token: Token = Token::from(TokenType::PointInc, 1, 11);
+        assert_eq!(
+            format!("{}", BrainfartError::PointMaxInc(token)),
+            "ERROR line 1 col 11: Attempted to increment pointer past the end of the tape"
+        );
+    }
+
+    #[test]
+    fn val_inc_error() {
+        let token: Token = Token::from(TokenType::ValInc, 4, 1);
+        assert_eq!(
+            format!("{}", BrainfartError::ValMaxInc(token, u32::MAX as Cell)),
+            "ERROR line 4 col 1: Attempted to increment value past 4294967295"
+        );
+    }
+
     #[test]
     fn input_error() {
         let token: Token = Token {
@@ -140,4 +416,160 @@ mod tests {
             ),
         };
     }
+
+    #[test]
+    fn output_error() {
+        let token: Token = Token {
+            ty: TokenType::Output,
+            line: 2,
+            col: 5,
+        };
+        let err: BrainfartResult<()> = Err(BrainfartError::Output(token));
+        match err {
+            Ok(_) => panic!("output_error had Ok result"),
+            Err(e) => assert_eq!(
+                format!("{}", e),
+                "ERROR line 2 col 5: Failed to write character to output"
+            ),
+        };
+    }
+
+    #[test]
+    fn unknown_flag_error() {
+        let err = BrainfartError::UnknownFlag("--bogus".to_string());
+        assert_eq!(format!("{}", err), "ERROR: Unknown flag --bogus");
+    }
+
+    #[test]
+    fn missing_value_error() {
+        let err = BrainfartError::MissingValue("--gen".to_string());
+        assert_eq!(format!("{}", err), "ERROR: Missing value for flag --gen");
+    }
+
+    #[test]
+    fn invalid_char_error() {
+        let token: Token = Token {
+            ty: TokenType::Output,
+            line: 3,
+            col: 1,
+        };
+        let err: BrainfartResult<()> = Err(BrainfartError::InvalidChar(token, 0xD800));
+        match err {
+            Ok(_) => panic!("invalid_char_error had Ok result"),
+            Err(e) => assert_eq!(
+                format!("{}", e),
+                "ERROR line 3 col 1: Attempted to output value 55296 that is not a valid character"
+            ),
+        };
+    }
+
+    #[test]
+    fn invalid_value_error() {
+        let err = BrainfartError::InvalidValue("--invalid-output".to_string(), "x".to_string());
+        assert_eq!(
+            format!("{}", err),
+            "ERROR: Invalid value x for flag --invalid-output"
+        );
+    }
+
+    #[test]
+    fn infinite_loop_error() {
+        let token: Token = Token {
+            ty: TokenType::IfZero,
+            line: 4,
+            col: 2,
+        };
+        let err: BrainfartResult<()> = Err(BrainfartError::InfiniteLoop(token));
+        match err {
+            Ok(_) => panic!("infinite_loop_error had Ok result"),
+            Err(e) => assert_eq!(
+                format!("{}", e),
+                "ERROR line 4 col 2: Loop can never terminate once entered"
+            ),
+        };
+    }
+
+    #[test]
+    fn infinite_loop_warning() {
+        let token: Token = Token {
+            ty: TokenType::IfZero,
+            line: 1,
+            col: 7,
+        };
+        assert_eq!(
+            format!("{}", BrainfartWarning::InfiniteLoop(token)),
+            "WARNING line 1 col 7: Loop can never terminate if it is entered"
+        );
+    }
+
+    #[test]
+    fn tape_size_warning() {
+        let token: Token = Token::from(TokenType::PointInc, 2, 5);
+        assert_eq!(
+            format!("{}", BrainfartWarning::TapeSize(token, 30000)),
+            "WARNING line 2 col 5: Tape grew past 30000 cells"
+        );
+    }
+
+    #[test]
+    fn control_output_warning() {
+        let token: Token = Token::from(TokenType::Output, 1, 3);
+        assert_eq!(
+            format!("{}", BrainfartWarning::ControlOutput(token, 7)),
+            "WARNING line 1 col 3: Output control character 7, which may not display correctly"
+        );
+    }
+
+    #[test]
+    fn limit_errors() {
+        let token: Token = Token::from(TokenType::Output, 4, 2);
+        let limits = [
+            (LimitKind::Steps, 100, "step limit of 100 steps"),
+            (LimitKind::Time, 2500, "time limit of 2500 ms"),
+            (LimitKind::Tape, 30000, "tape limit of 30000 cells"),
+            (LimitKind::Output, 10, "output limit of 10 bytes"),
+            (LimitKind::Source, 64, "source size limit of 64 bytes"),
+            (LimitKind::Tokens, 8, "command count limit of 8 commands"),
+        ];
+        for (kind, value, description) in limits {
+            let err = BrainfartError::LimitExceeded {
+                kind,
+                value,
+                token: Some(token),
+            };
+            assert_eq!(
+                format!("{}", err),
+                format!("ERROR line 4 col 2: Exceeded the {}", description)
+            );
+            assert_eq!(err.position(), Some((4, 2)));
+        }
+
+        let err = BrainfartError::LimitExceeded {
+            kind: LimitKind::Steps,
+            value: 1,
+            token: None,
+        };
+        assert_eq!(
+            format!("{}", err),
+            "ERROR: Exceeded the step limit of 1 steps"
+        );
+        assert_eq!(err.position(), None);
+    }
+
+    #[test]
+    fn error_json() {
+        let token = Token::from(TokenType::IfNonZero, 2, 7);
+        let err = BrainfartError::UnmatchedCloseBracket(token);
+        assert_eq!(err.kind(), "unmatched_close_bracket");
+        assert_eq!(
+            err.to_json(),
+            "{\"kind\":\"unmatched_close_bracket\",\"line\":2,\"col\":7,\"message\":\"Encountered unmatched closing bracket ]\"}"
+        );
+
+        let err = BrainfartError::UnknownFlag("--\"odd\\".to_string());
+        assert_eq!(
+            err.to_json(),
+            "{\"kind\":\"unknown_flag\",\"line\":null,\"col\":null,\"message\":\"Unknown flag --\\\"odd\\\\\"}"
+        );
+    }
 }
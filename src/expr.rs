@@ -1,3 +1,6 @@
+use std::hash::{Hash, Hasher};
+use std::mem;
+
 use crate::token::Token;
 
 /// An Expr is the type of expression to evaluate (ExprType) that stores the token(s) associated
@@ -28,13 +31,149 @@ pub enum ExprType {
     Output(u32),
     /// Take input into the current cell u32 value times
     Input(u32),
+    /// Output the value of the cell at the offset from the current cell once, as a move there, an
+    /// output and a move back such as `>.<` do
+    OutputAt { offset: i64 },
+    /// Take input into the cell at the offset from the current cell once, as a move there, an
+    /// input and a move back such as `>,<` do
+    InputAt { offset: i64 },
+    /// Move right the u32 value of cells at a time until the pointer's cell is zero
+    ScanRight(u32),
+    /// Move left the u32 value of cells at a time until the pointer's cell is zero
+    ScanLeft(u32),
+    /// Add the current cell times each factor to the cell at each offset, then set the current cell
+    /// to zero, as a loop that only adds, subtracts and moves back to where it started does
+    LinearLoop(Vec<(i64, i64)>),
+    /// Add the current cell to the cell at the offset, then set the current cell to zero, as a loop
+    /// that moves a value such as `[->+<]` does. This is the LinearLoop with a single factor of 1
+    MoveCell { offset: i64 },
     /// Execute the expressions contained in the LoopBlock until the pointer's cell is zero
     LoopBlock(Box<LoopBlock>),
+    /// Write the output of the start of a program and put the tape in the state it left it in, as
+    /// worked out before running from an all-zero tape
+    EmitConst(Box<ConstPrefix>),
+    /// Make the tape with the usize index, 0 or 1, the one the other expressions work on, in the
+    /// two-tape language
+    SwitchTape(usize),
+}
+
+impl ExprType {
+    /// A short human-readable name for this kind of expression, naming the loop idioms the parser
+    /// recognizes.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExprType::Set(_) => "set",
+            ExprType::Add(_) => "add",
+            ExprType::Sub(_) => "sub",
+            ExprType::MoveRight(_) => "move right",
+            ExprType::MoveLeft(_) => "move left",
+            ExprType::Output(_) => "output",
+            ExprType::Input(_) => "input",
+            ExprType::OutputAt { .. } => "output at",
+            ExprType::InputAt { .. } => "input at",
+            ExprType::ScanRight(_) => "scan right",
+            ExprType::ScanLeft(_) => "scan left",
+            ExprType::LinearLoop(targets) => match targets.as_slice() {
+                [(_, 1), (_, 1)] => "duplicate loop",
+                _ => "linear loop",
+            },
+            ExprType::MoveCell { .. } => "move cell",
+            ExprType::LoopBlock(_) => "loop",
+            ExprType::EmitConst(_) => "const output",
+            ExprType::SwitchTape(_) => "switch tape",
+        }
+    }
 }
 
 /// A LoopBlock is a container for the tokens contained within IfZero and IfNonZero brackets. They
-/// can contain other LoopBlocks.
+/// can contain other LoopBlocks. The brackets are kept so that diagnostics can point at the whole
+/// loop; `close` is None for a loop whose tokens ran out before it was closed.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LoopBlock {
     pub exprs: Vec<Expr>,
+    pub open: Token,
+    pub close: Option<Token>,
+}
+
+impl Drop for LoopBlock {
+    /// Drop nested LoopBlocks one at a time instead of recursively, so that dropping a deeply
+    /// nested program does not overflow the stack.
+    fn drop(&mut self) {
+        let mut pending: Vec<Expr> = mem::take(&mut self.exprs);
+        while let Some(expr) = pending.pop() {
+            if let ExprType::LoopBlock(mut lb) = expr.ty {
+                pending.append(&mut lb.exprs);
+            }
+        }
+    }
+}
+
+/// A ConstPrefix is the result of running the start of a program from an all-zero tape: the bytes
+/// it output, the cells from the starting cell up to the last one it reached, and where it left the
+/// data pointer relative to the starting cell.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConstPrefix {
+    pub bytes: Vec<u8>,
+    pub cells: Vec<u32>,
+    pub pointer: usize,
+}
+
+/// A Shape is a view of a sequence of Exprs that ignores the tokens they came from, so that two
+/// loops with the same body written in different places compare and hash the same. Nested loops
+/// are compared and hashed with an explicit stack rather than by recursing, so deeply nested loops
+/// do not grow the call stack.
+#[derive(Debug, Clone, Copy)]
+pub struct Shape<'a>(pub &'a [Expr]);
+
+impl PartialEq for Shape<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pending: Vec<(&[Expr], &[Expr])> = vec![(self.0, other.0)];
+        while let Some((a_body, b_body)) = pending.pop() {
+            if a_body.len() != b_body.len() {
+                return false;
+            }
+            for (a, b) in a_body.iter().zip(b_body) {
+                match (&a.ty, &b.ty) {
+                    (ExprType::LoopBlock(a_lb), ExprType::LoopBlock(b_lb)) => {
+                        pending.push((&a_lb.exprs, &b_lb.exprs))
+                    }
+                    (a_ty, b_ty) if a_ty != b_ty => return false,
+                    _ => (),
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Eq for Shape<'_> {}
+
+impl Hash for Shape<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pending: Vec<&[Expr]> = vec![self.0];
+        while let Some(body) = pending.pop() {
+            body.len().hash(state);
+            for expr in body {
+                mem::discriminant(&expr.ty).hash(state);
+                match &expr.ty {
+                    ExprType::Set(val)
+                    | ExprType::Add(val)
+                    | ExprType::Sub(val)
+                    | ExprType::MoveRight(val)
+                    | ExprType::MoveLeft(val)
+                    | ExprType::Output(val)
+                    | ExprType::Input(val)
+                    | ExprType::ScanRight(val)
+                    | ExprType::ScanLeft(val) => val.hash(state),
+                    ExprType::OutputAt { offset }
+                    | ExprType::InputAt { offset }
+                    | ExprType::MoveCell { offset } => offset.hash(state),
+                    ExprType::LinearLoop(targets) => targets.hash(state),
+                    ExprType::LoopBlock(lb) => pending.push(&lb.exprs),
+                    ExprType::EmitConst(prefix) => prefix.hash(state),
+                    ExprType::SwitchTape(tape) => tape.hash(state),
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,82 @@
+/// Generate a brainfuck program that prints the given text. The program keeps the character being
+/// printed in the second cell and uses the first cell as a loop counter, so that large jumps between
+/// consecutive characters are made with a multiplication loop instead of a long run of `+` or `-`.
+pub fn generate(text: &str) -> String {
+    let mut program = String::from(">");
+    let mut current: u32 = 0;
+
+    for c in text.chars() {
+        let target = c as u32;
+        program.push_str(&adjust(current, target));
+        program.push('.');
+        current = target;
+    }
+
+    program
+}
+
+/// Produce the shortest code found that changes the second cell from `from` to `to`, with the
+/// pointer starting and ending on the second cell and the first cell left at zero.
+fn adjust(from: u32, to: u32) -> String {
+    let (diff, op) = if to >= from {
+        (to - from, '+')
+    } else {
+        (from - to, '-')
+    };
+    let mut best = repeat(op, diff);
+
+    let mut factor: u32 = 2;
+    while factor * factor <= diff {
+        let code = format!(
+            "<{}[>{}<-]>{}",
+            repeat('+', factor),
+            repeat(op, diff / factor),
+            repeat(op, diff % factor)
+        );
+        if code.len() < best.len() {
+            best = code;
+        }
+        factor += 1;
+    }
+
+    best
+}
+
+/// Repeat the given command the given number of times.
+fn repeat(c: char, times: u32) -> String {
+    c.to_string().repeat(times as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::{adjust, generate};
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+    use crate::progstate::ProgState;
+
+    fn run(source: String) -> String {
+        let exprs = parse_tokens(lex_string(source).unwrap()).unwrap();
+        let mut output: Vec<u8> = vec![];
+        ProgState::with_output(&mut output).run(&exprs).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn generate_round_trip() {
+        for text in ["Hello", "Hello World!\n", "", "zA~ ", "héllo ✓"] {
+            assert_eq!(run(generate(text)), text);
+        }
+    }
+
+    #[test]
+    fn adjust_small_diff() {
+        assert_eq!(adjust(72, 75), "+++");
+        assert_eq!(adjust(75, 72), "---");
+    }
+
+    #[test]
+    fn adjust_uses_loop() {
+        assert_eq!(adjust(0, 72), "<++++++++[>+++++++++<-]>");
+        assert_eq!(adjust(100, 0), "<++++++++++[>----------<-]>");
+    }
+}
@@ -1,58 +1,353 @@
+use std::io::{BufRead, Bytes};
+use std::iter::Peekable;
+use std::str::{self, FromStr};
+
+use crate::config::Limits;
 use crate::error::BrainfartError;
 use crate::error::BrainfartResult;
+use crate::error::LimitKind;
 use crate::token::Token;
+use crate::token::TokenRun;
 use crate::token::TokenType;
 
 /// Converts a String into a vector of Tokens, ignoring invalid characters
 pub fn lex_string(string: String) -> BrainfartResult<Vec<Token>> {
-    let mut line: u32 = 1;
-    let mut col: u32 = 1;
-    let mut tokens: Vec<Token> = vec![];
-    let mut brace_balance: u32 = 0;
-    for char in string.chars() {
-        let opt_token_type: Option<TokenType> = lex_char(char);
-        if let Some(token_type) = opt_token_type {
-            let token_result = add_token(&mut tokens, token_type, &mut brace_balance, line, col);
-            token_result?;
-            col += 1;
-        } else if char == '\n' || char == '\r' {
-            line += 1;
-            col = 1;
-        } else {
-            col += 1;
-        }
-    }
-
-    match brace_balance {
-        0 => Ok(tokens),
-        _ => Err(BrainfartError::UnmatchedOpenBracket),
-    }
-}
-
-/// Adds a token to the tokens vector
-fn add_token(
-    tokens: &mut Vec<Token>,
-    token_type: TokenType,
-    brace_balance: &mut u32,
+    Lexer::new(string.chars()).collect()
+}
+
+/// Converts a String into a vector of TokenRuns, coalescing consecutive identical commands other
+/// than brackets so that long runs take up a single TokenRun instead of one Token per character
+pub fn lex_runs(string: String) -> BrainfartResult<Vec<TokenRun>> {
+    lex_runs_with(string, CommandSet::default())
+}
+
+/// Converts a String into a vector of TokenRuns like `lex_runs`, reading commands from the given
+/// CommandSet instead of the canonical characters
+pub fn lex_runs_with(string: String, commands: CommandSet) -> BrainfartResult<Vec<TokenRun>> {
+    lex_runs_limited(string, commands, Limits::default())
+}
+
+/// Converts a String into a vector of TokenRuns like `lex_runs_with`, stopping with an error if the
+/// source is longer than the limit on its size in bytes, without lexing it, or has more commands
+/// than the limit on its tokens
+pub fn lex_runs_limited(
+    string: String,
+    commands: CommandSet,
+    limits: Limits,
+) -> BrainfartResult<Vec<TokenRun>> {
+    if let Some(max_bytes) = limits.source_bytes {
+        if string.len() as u64 > max_bytes {
+            return Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Source,
+                value: max_bytes,
+                token: None,
+            });
+        }
+    }
+
+    let mut runs: Vec<TokenRun> = vec![];
+    for token_result in Lexer::with_commands(string.chars(), commands).limit(limits) {
+        let token = token_result?;
+        match runs.last_mut() {
+            Some(run) if continues_run(run, &token) => run.count += 1,
+            _ => runs.push(TokenRun { token, count: 1 }),
+        }
+    }
+    Ok(runs)
+}
+
+/// Whether the given Token directly follows the given run and can be counted as part of it
+fn continues_run(run: &TokenRun, token: &Token) -> bool {
+    let first = run.token;
+    first.ty == token.ty
+        && !matches!(token.ty, TokenType::IfZero | TokenType::IfNonZero)
+        && first.line == token.line
+        && first.col.checked_add(run.count) == Some(token.col)
+}
+
+/// The token types in the order their characters are given to a CommandSet
+const COMMAND_ORDER: [TokenType; 8] = [
+    TokenType::PointInc,
+    TokenType::PointDec,
+    TokenType::ValInc,
+    TokenType::ValDec,
+    TokenType::Output,
+    TokenType::Input,
+    TokenType::IfZero,
+    TokenType::IfNonZero,
+];
+
+/// A Lang is the dialect of brainfuck a program is written in. The two-tape language adds `{` and
+/// `}` to switch between a first and a second tape, each with its own data pointer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    Brainfuck,
+    TwoTape,
+}
+
+impl FromStr for Lang {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "brainfuck" => Ok(Lang::Brainfuck),
+            "twotape" => Ok(Lang::TwoTape),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A CommandSet holds the eight characters a program's commands are written with, in the canonical
+/// order `><+-.,[]`, and the Lang it is written in. It is parsed from a string of eight distinct
+/// characters, none of which may be a line break, in the canonical brainfuck Lang.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandSet {
+    chars: [char; 8],
+    lang: Lang,
+}
+
+impl Default for CommandSet {
+    fn default() -> Self {
+        CommandSet {
+            chars: ['>', '<', '+', '-', '.', ',', '[', ']'],
+            lang: Lang::default(),
+        }
+    }
+}
+
+impl CommandSet {
+    /// Produce this CommandSet for programs written in the given Lang
+    pub fn with_lang(self, lang: Lang) -> Self {
+        CommandSet { lang, ..self }
+    }
+
+    /// The Lang programs are written in
+    pub fn lang(&self) -> Lang {
+        self.lang
+    }
+
+    /// Converts a character to the token type it stands for in this set, if any
+    pub fn lex_char(&self, c: char) -> Option<TokenType> {
+        let command = self
+            .chars
+            .iter()
+            .position(|&command| command == c)
+            .map(|i| COMMAND_ORDER[i]);
+        match (command, self.lang, c) {
+            (None, Lang::TwoTape, '{') => Some(TokenType::TapeDec),
+            (None, Lang::TwoTape, '}') => Some(TokenType::TapeInc),
+            _ => command,
+        }
+    }
+}
+
+impl FromStr for CommandSet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().collect();
+        let chars: [char; 8] = chars.try_into().map_err(|_| ())?;
+        for (i, c) in chars.iter().enumerate() {
+            if *c == '\n' || *c == '\r' || chars[..i].contains(c) {
+                return Err(());
+            }
+        }
+        Ok(CommandSet {
+            chars,
+            lang: Lang::default(),
+        })
+    }
+}
+
+/// A Lexer converts a stream of characters into Tokens as the characters are read, ignoring invalid
+/// characters. Line and column numbers stop at `u32::MAX` rather than wrapping around. An
+/// unmatched closing bracket is reported as soon as it is read, and an unmatched opening bracket
+/// once the characters run out. The Lexer stops after reporting an error.
+pub struct Lexer<I: Iterator<Item = char>> {
+    chars: I,
     line: u32,
     col: u32,
-) -> BrainfartResult<()> {
-    match token_type {
-        TokenType::IfZero => {
-            *brace_balance += 1;
-        }
-        TokenType::IfNonZero => {
-            if *brace_balance == 0 {
-                let token: Token = Token::from(token_type, line, col);
-                return Err(BrainfartError::UnmatchedCloseBracket(token));
+    brace_balance: u32,
+    finished: bool,
+    commands: Option<CommandSet>,
+    limits: Limits,
+    bytes: u64,
+    count: u64,
+}
+
+impl<I: Iterator<Item = char>> Lexer<I> {
+    /// Produce a Lexer reading from the given characters, starting at line 1 col 1
+    pub fn new(chars: I) -> Self {
+        Lexer {
+            chars,
+            line: 1,
+            col: 1,
+            brace_balance: 0,
+            finished: false,
+            commands: None,
+            limits: Limits::default(),
+            bytes: 0,
+            count: 0,
+        }
+    }
+
+    /// Produce a Lexer reading from the given characters whose commands are written with the given
+    /// CommandSet
+    pub fn with_commands(chars: I, commands: CommandSet) -> Self {
+        Lexer {
+            commands: Some(commands),
+            ..Lexer::new(chars)
+        }
+    }
+
+    /// Produce this Lexer stopping with an error once it has read more bytes of source or produced
+    /// more Tokens than the given Limits allow, so that an oversized program is rejected before
+    /// it runs, even when it is read as a stream
+    pub fn limit(self, limits: Limits) -> Self {
+        Lexer { limits, ..self }
+    }
+
+    /// Produce a Token of the given type at the current position, checking bracket balance and the
+    /// limit on the number of Tokens
+    fn make_token(&mut self, token_type: TokenType) -> BrainfartResult<Token> {
+        let token: Token = Token::from(token_type, self.line, self.col);
+        self.count += 1;
+        if let Some(max_tokens) = self.limits.tokens {
+            if self.count > max_tokens {
+                return Err(BrainfartError::LimitExceeded {
+                    kind: LimitKind::Tokens,
+                    value: max_tokens,
+                    token: Some(token),
+                });
+            }
+        }
+        match token_type {
+            TokenType::IfZero => {
+                self.brace_balance += 1;
+            }
+            TokenType::IfNonZero => {
+                if self.brace_balance == 0 {
+                    return Err(BrainfartError::UnmatchedCloseBracket(token));
+                }
+                self.brace_balance -= 1;
+            }
+            _ => (),
+        }
+        Ok(token)
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for Lexer<I> {
+    type Item = BrainfartResult<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        while let Some(char) = self.chars.next() {
+            self.bytes += char.len_utf8() as u64;
+            if let Some(max_bytes) = self.limits.source_bytes {
+                if self.bytes > max_bytes {
+                    self.finished = true;
+                    return Some(Err(BrainfartError::LimitExceeded {
+                        kind: LimitKind::Source,
+                        value: max_bytes,
+                        token: None,
+                    }));
+                }
+            }
+            let opt_token_type: Option<TokenType> = match &self.commands {
+                Some(commands) => commands.lex_char(char),
+                None => lex_char(char),
+            };
+            if let Some(token_type) = opt_token_type {
+                let token_result = self.make_token(token_type);
+                self.finished = token_result.is_err();
+                self.col = self.col.saturating_add(1);
+                return Some(token_result);
+            } else if char == '\n' || char == '\r' {
+                self.line = self.line.saturating_add(1);
+                self.col = 1;
+            } else {
+                self.col = self.col.saturating_add(1);
+            }
+        }
+
+        self.finished = true;
+        match self.brace_balance {
+            0 => None,
+            _ => Some(Err(BrainfartError::UnmatchedOpenBracket)),
+        }
+    }
+}
+
+/// ReadChars decodes the UTF-8 bytes of a buffered reader into characters as they become
+/// available, so that a program can be lexed while it is still being read. Invalid sequences are
+/// decoded as U+FFFD, and decoding picks back up at the first byte that does not continue them.
+pub struct ReadChars<R: BufRead> {
+    bytes: Peekable<Bytes<R>>,
+}
+
+impl<R: BufRead> ReadChars<R> {
+    /// Produce a ReadChars decoding the given reader
+    pub fn new(reader: R) -> Self {
+        ReadChars {
+            bytes: reader.bytes().peekable(),
+        }
+    }
+
+    /// Read the next byte, panicking if the reader fails
+    fn next_byte(&mut self) -> Option<u8> {
+        self.bytes.next().map(|byte| {
+            byte.unwrap_or_else(|e| panic!("Encountered an error while reading program: {}", e))
+        })
+    }
+
+    /// Read the next byte if it continues a multi-byte sequence, leaving any other byte to be
+    /// decoded on its own
+    fn next_continuation(&mut self) -> Option<u8> {
+        match self.bytes.peek()? {
+            Ok(byte @ 0x80..=0xBF) => {
+                let byte = *byte;
+                self.bytes.next();
+                Some(byte)
             }
-            *brace_balance -= 1;
+            Ok(_) => None,
+            Err(_) => self.next_byte(),
         }
-        _ => (),
     }
-    let token: Token = Token::from(token_type, line, col);
-    tokens.push(token);
-    Ok(())
+}
+
+impl<R: BufRead> Iterator for ReadChars<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let first = self.next_byte()?;
+        let width = match first {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+
+        let mut buf = [first, 0, 0, 0];
+        for byte in buf.iter_mut().take(width).skip(1) {
+            match self.next_continuation() {
+                Some(next) => *byte = next,
+                None => return Some(char::REPLACEMENT_CHARACTER),
+            }
+        }
+
+        let decoded = str::from_utf8(&buf[..width]).ok();
+        Some(
+            decoded
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER),
+        )
+    }
 }
 
 /// Converts a character to a token type, if valid
@@ -70,10 +365,32 @@ fn lex_char(c: char) -> Option<TokenType> {
     }
 }
 
+/// Converts a token type to the canonical source character it is lexed from
+pub fn token_char(ty: &TokenType) -> char {
+    match ty {
+        TokenType::PointInc => '>',
+        TokenType::PointDec => '<',
+        TokenType::ValInc => '+',
+        TokenType::ValDec => '-',
+        TokenType::Output => '.',
+        TokenType::Input => ',',
+        TokenType::IfZero => '[',
+        TokenType::IfNonZero => ']',
+        TokenType::TapeDec => '{',
+        TokenType::TapeInc => '}',
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::config::Limits;
+    use crate::error::{BrainfartError, LimitKind};
     use crate::lexer::lex_char;
+    use crate::lexer::lex_runs;
+    use crate::lexer::lex_runs_limited;
     use crate::lexer::lex_string;
+    use crate::lexer::token_char;
+    use crate::lexer::{CommandSet, Lang, Lexer, ReadChars};
     use crate::token::Token;
     use crate::token::TokenType;
 
@@ -212,10 +529,171 @@ mod tests {
     }
 
     #[test]
+    fn lex_runs_coalesce() {
+        let runs = lex_runs(format!("{}>>\n>[-]", "+".repeat(1000))).unwrap();
+        assert_eq!(runs.len(), 6);
+        assert_eq!(runs[0].token, Token::from(TokenType::ValInc, 1, 1));
+        assert_eq!(runs[0].count, 1000);
+        assert_eq!(runs[1].count, 2);
+        assert_eq!(runs[2].token, Token::from(TokenType::PointInc, 2, 1));
+
+        let expanded: Vec<Token> = runs.into_iter().flat_map(|run| run.tokens()).collect();
+        assert_eq!(
+            expanded,
+            lex_string(format!("{}>>\n>[-]", "+".repeat(1000))).unwrap()
+        );
+    }
+
+    #[test]
+    fn token_char_round_trip() {
+        for c in "><+-.,[]".chars() {
+            assert_eq!(token_char(&lex_char(c).unwrap()), c);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::redundant_pattern_matching)]
     fn lex_none() {
         matches!(lex_char('a'), None);
         matches!(lex_char('d'), None);
         matches!(lex_char(' '), None);
         matches!(lex_char('\n'), None);
     }
+
+    #[test]
+    fn lex_none_asserted() {
+        assert!(lex_char('a').is_none());
+        assert!(lex_char('d').is_none());
+        assert!(lex_char(' ').is_none());
+        assert!(lex_char('\n').is_none());
+    }
+
+    #[test]
+    fn lexer_stops_at_unmatched_close() {
+        let mut lexer = Lexer::new("+]+".chars());
+        assert!(lexer.next().unwrap().is_ok());
+        match lexer.next() {
+            Some(Err(BrainfartError::UnmatchedCloseBracket(tok))) => {
+                assert_eq!(tok, Token::from(TokenType::IfNonZero, 1, 2))
+            }
+            other => panic!("lexer_stops_at_unmatched_close had result {:?}", other),
+        }
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn lexer_unmatched_open() {
+        let results: Vec<_> = Lexer::new("[+".chars()).collect();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(
+            results[2],
+            Err(BrainfartError::UnmatchedOpenBracket)
+        ));
+    }
+
+    #[test]
+    fn read_chars_decodes_utf8() {
+        let bytes: &[u8] = "a+ é✓\n".as_bytes();
+        assert_eq!(ReadChars::new(bytes).collect::<String>(), "a+ é✓\n");
+        let invalid: &[u8] = &[b'+', 0xFF, 0xE2, 0x9C];
+        assert_eq!(
+            ReadChars::new(invalid).collect::<String>(),
+            "+\u{FFFD}\u{FFFD}"
+        );
+    }
+
+    #[test]
+    fn read_chars_resyncs_after_invalid_lead() {
+        let bytes: &[u8] = &[0xC3, b'+', b'.'];
+        assert_eq!(ReadChars::new(bytes).collect::<String>(), "\u{FFFD}+.");
+        let bytes: &[u8] = &[0xE2, 0x9C, b'+'];
+        assert_eq!(ReadChars::new(bytes).collect::<String>(), "\u{FFFD}+");
+    }
+
+    #[test]
+    fn lex_custom_commands() {
+        let commands: CommandSet = "rlpmoibe".parse().unwrap();
+        let custom: Vec<Token> = Lexer::with_commands("pb>mer\no".chars(), commands)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let canonical = lex_string("+[ -]>\n.".to_string()).unwrap();
+        assert_eq!(custom, canonical);
+        assert!("><+-.,[".parse::<CommandSet>().is_err());
+        assert!("rlidoibe".parse::<CommandSet>().is_err());
+        assert!("><+-.,\n]".parse::<CommandSet>().is_err());
+    }
+
+    #[test]
+    fn lex_two_tape_commands() {
+        let lex = |commands: CommandSet| -> Vec<TokenType> {
+            Lexer::with_commands("+}>{".chars(), commands)
+                .map(|token| token.unwrap().ty)
+                .collect()
+        };
+        let two_tape = CommandSet::default().with_lang(Lang::TwoTape);
+        assert_eq!(
+            lex(two_tape),
+            vec![
+                TokenType::ValInc,
+                TokenType::TapeInc,
+                TokenType::PointInc,
+                TokenType::TapeDec,
+            ]
+        );
+        assert_eq!(
+            lex(CommandSet::default()),
+            vec![TokenType::ValInc, TokenType::PointInc]
+        );
+        assert_eq!("twotape".parse(), Ok(Lang::TwoTape));
+        assert!("threetape".parse::<Lang>().is_err());
+    }
+
+    #[test]
+    fn lex_col_saturates() {
+        let mut lexer = Lexer::new("+ ++".chars());
+        lexer.col = u32::MAX - 2;
+        let cols: Vec<u32> = lexer.map(|token| token.unwrap().col).collect();
+        assert_eq!(cols, vec![u32::MAX - 2, u32::MAX, u32::MAX]);
+    }
+
+    #[test]
+    fn lex_size_limits() {
+        let source = "+++ add three\n.".to_string();
+        let limits = Limits {
+            source_bytes: Some(15),
+            tokens: Some(4),
+            ..Limits::default()
+        };
+        assert!(lex_runs_limited(source.clone(), CommandSet::default(), limits).is_ok());
+
+        let err =
+            lex_runs_limited(source.clone() + "\n", CommandSet::default(), limits).unwrap_err();
+        assert!(matches!(
+            err,
+            BrainfartError::LimitExceeded {
+                kind: LimitKind::Source,
+                value: 15,
+                token: None,
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "ERROR: Exceeded the source size limit of 15 bytes"
+        );
+
+        let limits = Limits {
+            tokens: Some(3),
+            ..limits
+        };
+        let results: Vec<_> = Lexer::new(source.chars()).limit(limits).collect();
+        assert_eq!(results.len(), 4);
+        match &results[3] {
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Tokens,
+                value: 3,
+                token: Some(token),
+            }) => assert_eq!(*token, Token::from(TokenType::Output, 2, 1)),
+            other => panic!("lex_size_limits had result {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,69 @@
+//! Brainfart is an optimizing interpreter for brainfuck. Source is lexed into Tokens, parsed into
+//! Exprs and run by a ProgState.
+
+pub mod analysis;
+pub mod bytecode;
+pub mod checkpoint;
+pub mod config;
+pub mod cost;
+pub mod coverage;
+pub mod equiv;
+pub mod error;
+pub mod expr;
+pub mod generator;
+pub mod lexer;
+pub mod lint;
+#[cfg(feature = "llvm")]
+pub mod llvm;
+pub mod macros;
+pub mod memo;
+pub mod minimize;
+pub mod normalize;
+pub mod parser;
+pub mod prefix;
+pub mod program;
+pub mod progstate;
+pub mod sink;
+pub mod source;
+pub mod token;
+pub mod trace;
+pub mod watch;
+
+pub use expr::Expr;
+pub use lexer::lex_string;
+pub use parser::parse_tokens;
+pub use progstate::ProgState;
+
+use error::BrainfartResult;
+
+/// Lex, parse and run the given source with the default Config, returning its output as a String,
+/// with any bytes that are not valid UTF-8 replaced. Nothing is printed. Input is read from stdin
+/// a line at a time as `bft` reads it, but stdin is only touched once the program reaches a `,`,
+/// so a program without one never reads it.
+///
+/// ```
+/// let output = brainfart::run_str("++++++++[>++++++++<-]>+.+.").unwrap();
+/// assert_eq!(output, "AB");
+/// ```
+pub fn run_str(source: &str) -> BrainfartResult<String> {
+    let exprs = parse_tokens(lex_string(source.to_string())?)?;
+    let mut state = ProgState::with_output(vec![]);
+    state.run(&exprs)?;
+    Ok(String::from_utf8_lossy(state.output()).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::BrainfartError;
+    use crate::run_str;
+
+    #[test]
+    fn run_str_output_and_errors() {
+        assert_eq!(run_str("+[->+<]>.").unwrap(), "\u{1}");
+        assert!(matches!(
+            run_str("+]"),
+            Err(BrainfartError::UnmatchedCloseBracket(_))
+        ));
+        assert!(matches!(run_str("<"), Err(BrainfartError::PointZeroDec(_))));
+    }
+}
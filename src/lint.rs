@@ -0,0 +1,193 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::error::BrainfartResult;
+use crate::expr::{Expr, ExprType};
+use crate::lexer::token_char;
+use crate::parser::parse_tokens;
+use crate::token::{Token, TokenType};
+
+/// A Lint is a pattern in a program that is allowed but is most likely a mistake or has no effect,
+/// found by `lint`. Lints are only advice: unlike a BrainfartError they never stop a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// A `[-]` directly after another one, at the second loop's opening bracket
+    RedundantClear(Token),
+    /// Two adjacent commands that undo each other, such as `><` or `+-`
+    CancelingCommands(Token, Token),
+    /// A loop whose body never changes the cell it tests, at its opening bracket
+    UnchangedCondition(Token),
+}
+
+impl Lint {
+    /// The Token the Lint points at.
+    pub fn token(&self) -> Token {
+        match self {
+            Lint::RedundantClear(tok)
+            | Lint::CancelingCommands(tok, _)
+            | Lint::UnchangedCondition(tok) => *tok,
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let tok = self.token();
+        write!(f, "LINT line {} col {}: ", tok.line, tok.col)?;
+        match self {
+            Lint::RedundantClear(_) => write!(f, "Clears a cell that was just cleared"),
+            Lint::CancelingCommands(first, second) => write!(
+                f,
+                "`{}` followed by `{}` has no effect",
+                token_char(&first.ty),
+                token_char(&second.ty)
+            ),
+            Lint::UnchangedCondition(_) => write!(
+                f,
+                "Loop never changes the cell it tests, so it cannot end once entered"
+            ),
+        }
+    }
+}
+
+/// Find the Lints in a program given as its Tokens, ordered by position.
+pub fn lint(tokens: &[Token]) -> BrainfartResult<Vec<Lint>> {
+    let mut lints: Vec<Lint> = vec![];
+
+    for window in tokens.windows(6) {
+        if is_clear(&window[..3]) && is_clear(&window[3..]) {
+            lints.push(Lint::RedundantClear(window[3]));
+        }
+    }
+
+    for pair in tokens.windows(2) {
+        if matches!(
+            (pair[0].ty, pair[1].ty),
+            (TokenType::PointInc, TokenType::PointDec) | (TokenType::ValInc, TokenType::ValDec)
+        ) {
+            lints.push(Lint::CancelingCommands(pair[0], pair[1]));
+        }
+    }
+
+    let exprs = parse_tokens(tokens.to_vec())?;
+    let mut bodies: Vec<&[Expr]> = vec![&exprs];
+    while let Some(body) = bodies.pop() {
+        for expr in body {
+            if let ExprType::LoopBlock(lb) = &expr.ty {
+                if !changes_condition(&lb.exprs) {
+                    lints.push(Lint::UnchangedCondition(lb.open));
+                }
+                bodies.push(&lb.exprs);
+            }
+        }
+    }
+
+    lints.sort_by_key(Lint::token);
+    Ok(lints)
+}
+
+/// Whether the given three Tokens are a `[-]` or `[+]`.
+fn is_clear(tokens: &[Token]) -> bool {
+    matches!(
+        tokens.iter().map(|tok| tok.ty).collect::<Vec<_>>()[..],
+        [
+            TokenType::IfZero,
+            TokenType::ValDec | TokenType::ValInc,
+            TokenType::IfNonZero
+        ]
+    )
+}
+
+/// Whether running the given loop body can change the cell the loop tests, either by writing to it
+/// or by ending on another cell. Bodies that move the pointer by an amount only known while
+/// running, such as with a scan, are assumed to.
+fn changes_condition(body: &[Expr]) -> bool {
+    let mut offset: i64 = 0;
+    for expr in body {
+        match &expr.ty {
+            ExprType::Set(_) | ExprType::Add(_) | ExprType::Sub(_) | ExprType::Input(_) => {
+                if offset == 0 {
+                    return true;
+                }
+            }
+            ExprType::InputAt { offset: at } => {
+                if offset + at == 0 {
+                    return true;
+                }
+            }
+            ExprType::LinearLoop(targets) => {
+                if offset == 0 || targets.iter().any(|(at, _)| offset + at == 0) {
+                    return true;
+                }
+            }
+            ExprType::MoveCell { offset: at } => {
+                if offset == 0 || offset + at == 0 {
+                    return true;
+                }
+            }
+            ExprType::MoveRight(val) => offset += *val as i64,
+            ExprType::MoveLeft(val) => offset -= *val as i64,
+            ExprType::Output(_) | ExprType::OutputAt { .. } => (),
+            ExprType::ScanRight(_)
+            | ExprType::ScanLeft(_)
+            | ExprType::LoopBlock(_)
+            | ExprType::EmitConst(_)
+            | ExprType::SwitchTape(_) => return true,
+        }
+    }
+    offset != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::lex_string;
+    use crate::lint::{lint, Lint};
+    use crate::token::{Token, TokenType};
+
+    fn lint_source(source: &str) -> Vec<Lint> {
+        lint(&lex_string(source.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn lint_redundant_clear() {
+        assert_eq!(
+            lint_source("+[-] [-]"),
+            vec![Lint::RedundantClear(Token::from(TokenType::IfZero, 1, 6))]
+        );
+        assert_eq!(lint_source("+[-]>[-]"), vec![]);
+    }
+
+    #[test]
+    fn lint_canceling_commands() {
+        let lints = lint_source(">>\n<+-");
+        assert_eq!(
+            lints,
+            vec![
+                Lint::CancelingCommands(
+                    Token::from(TokenType::PointInc, 1, 2),
+                    Token::from(TokenType::PointDec, 2, 1)
+                ),
+                Lint::CancelingCommands(
+                    Token::from(TokenType::ValInc, 2, 2),
+                    Token::from(TokenType::ValDec, 2, 3)
+                ),
+            ]
+        );
+        assert_eq!(
+            lints[0].to_string(),
+            "LINT line 1 col 2: `>` followed by `<` has no effect"
+        );
+        assert_eq!(lint_source("<>-+"), vec![]);
+    }
+
+    #[test]
+    fn lint_unchanged_condition() {
+        assert_eq!(
+            lint_source("+[.]>+[>+<.]"),
+            vec![
+                Lint::UnchangedCondition(Token::from(TokenType::IfZero, 1, 2)),
+                Lint::UnchangedCondition(Token::from(TokenType::IfZero, 1, 7)),
+            ]
+        );
+        assert_eq!(lint_source("+[.-]>+[>]>,[.>+<,]"), vec![]);
+    }
+}
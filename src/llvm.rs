@@ -0,0 +1,452 @@
+use inkwell::basic_block::BasicBlock;
+use inkwell::builder::{Builder, BuilderError};
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::values::{FunctionValue, IntValue, PointerValue};
+use inkwell::IntPredicate;
+
+use crate::expr::{ConstPrefix, Expr, ExprType};
+
+/// Lower the given Exprs to an LLVM module with a `main` function that runs them. The tape is a
+/// zeroed global array of `tape_size` 32-bit cells with the data pointer starting on cell
+/// `start_index`, output goes through `putchar` and input through `getchar`. A program that
+/// switches tapes gets a second such tape with its own data pointer.
+///
+/// The generated code behaves like ArithMode::Wrapping: cells wrap around instead of overflowing,
+/// `,` leaves the cell unchanged at the end of input and `.` writes the low byte of the cell.
+/// There are no bounds checks, so moving off either end of the tape is undefined behavior.
+pub fn build_module<'ctx>(
+    context: &'ctx Context,
+    exprs: &[Expr],
+    tape_size: usize,
+    start_index: usize,
+) -> Module<'ctx> {
+    let module = context.create_module("brainfart");
+    let tapes = if switches_tape(exprs) { 2 } else { 1 };
+    Codegen::new(context, &module, tapes, tape_size, start_index)
+        .build(exprs)
+        .expect("the builder is always positioned in a block");
+    module
+}
+
+/// Lower the given Exprs like `build_module` and print the module as textual LLVM IR.
+pub fn emit_llvm(exprs: &[Expr], tape_size: usize, start_index: usize) -> String {
+    let context = Context::create();
+    let module = build_module(&context, exprs, tape_size, start_index);
+    module.print_to_string().to_string()
+}
+
+/// Whether any of the given Exprs, including those in loop bodies, switches tapes.
+fn switches_tape(exprs: &[Expr]) -> bool {
+    let mut bodies = vec![exprs];
+    while let Some(body) = bodies.pop() {
+        for expr in body {
+            match &expr.ty {
+                ExprType::SwitchTape(_) => return true,
+                ExprType::LoopBlock(lb) => bodies.push(&lb.exprs),
+                _ => (),
+            }
+        }
+    }
+    false
+}
+
+/// The header and exit blocks of a loop being emitted.
+type LoopBlocks<'ctx> = (BasicBlock<'ctx>, BasicBlock<'ctx>);
+
+/// Codegen holds what is needed to emit the body of `main` into a module. The tapes are a single
+/// global array of tapes, and `pointers` holds the data pointer of each, indexed by `active`.
+struct Codegen<'ctx> {
+    context: &'ctx Context,
+    builder: Builder<'ctx>,
+    main: FunctionValue<'ctx>,
+    tape: PointerValue<'ctx>,
+    pointers: PointerValue<'ctx>,
+    active: PointerValue<'ctx>,
+    putchar: FunctionValue<'ctx>,
+    getchar: FunctionValue<'ctx>,
+}
+
+impl<'ctx> Codegen<'ctx> {
+    fn new(
+        context: &'ctx Context,
+        module: &Module<'ctx>,
+        tapes: u32,
+        tape_size: usize,
+        start_index: usize,
+    ) -> Self {
+        let i32_type = context.i32_type();
+        let i64_type = context.i64_type();
+
+        let putchar = module.add_function(
+            "putchar",
+            i32_type.fn_type(&[i32_type.into()], false),
+            Some(Linkage::External),
+        );
+        let getchar = module.add_function(
+            "getchar",
+            i32_type.fn_type(&[], false),
+            Some(Linkage::External),
+        );
+        let main = module.add_function("main", i32_type.fn_type(&[], false), None);
+
+        let tape_type = i32_type.array_type(tape_size as u32).array_type(tapes);
+        let tape = module.add_global(tape_type, None, "tape");
+        tape.set_linkage(Linkage::Internal);
+        tape.set_initializer(&tape_type.const_zero());
+
+        let builder = context.create_builder();
+        builder.position_at_end(context.append_basic_block(main, "entry"));
+        let start = i64_type.const_int(start_index as u64, false);
+        let pointers = builder
+            .build_alloca(i64_type.array_type(tapes), "pointers")
+            .expect("the builder was just positioned");
+        builder
+            .build_store(pointers, i64_type.const_array(&vec![start; tapes as usize]))
+            .expect("the builder was just positioned");
+        let active = builder
+            .build_alloca(i64_type, "active")
+            .expect("the builder was just positioned");
+        builder
+            .build_store(active, i64_type.const_zero())
+            .expect("the builder was just positioned");
+
+        Codegen {
+            context,
+            builder,
+            main,
+            tape: tape.as_pointer_value(),
+            pointers,
+            active,
+            putchar,
+            getchar,
+        }
+    }
+
+    fn cell_const(&self, val: u64) -> IntValue<'ctx> {
+        self.context.i32_type().const_int(val, false)
+    }
+
+    /// The index of the active tape and a pointer to its data pointer.
+    fn pointer_slot(&self) -> Result<(IntValue<'ctx>, PointerValue<'ctx>), BuilderError> {
+        let zero = self.context.i64_type().const_zero();
+        let active = self
+            .builder
+            .build_load(self.active, "active")?
+            .into_int_value();
+        // Safety: the active tape is always one that was allocated, since only programs that
+        // switch tapes can make it nonzero and they get two
+        let slot = unsafe {
+            self.builder
+                .build_in_bounds_gep(self.pointers, &[zero, active], "pointer")?
+        };
+        Ok((active, slot))
+    }
+
+    /// A pointer to the cell `offset` cells from the data pointer.
+    fn cell_ptr(&self, offset: i64) -> Result<PointerValue<'ctx>, BuilderError> {
+        let i64_type = self.context.i64_type();
+        let (active, pointer) = self.pointer_slot()?;
+        let mut index = self.builder.build_load(pointer, "index")?.into_int_value();
+        if offset != 0 {
+            let offset = i64_type.const_int(offset as u64, true);
+            index = self.builder.build_int_add(index, offset, "index")?;
+        }
+        // Safety: the index is in bounds as long as the program stays on the tape, which is
+        // documented as the caller's responsibility
+        unsafe {
+            self.builder.build_in_bounds_gep(
+                self.tape,
+                &[i64_type.const_zero(), active, index],
+                "cell",
+            )
+        }
+    }
+
+    fn load_cell(&self, offset: i64) -> Result<IntValue<'ctx>, BuilderError> {
+        let cell = self.cell_ptr(offset)?;
+        Ok(self.builder.build_load(cell, "val")?.into_int_value())
+    }
+
+    fn store_cell(&self, offset: i64, val: IntValue<'ctx>) -> Result<(), BuilderError> {
+        let cell = self.cell_ptr(offset)?;
+        self.builder.build_store(cell, val)?;
+        Ok(())
+    }
+
+    fn move_pointer(&self, amount: i64) -> Result<(), BuilderError> {
+        let (_, pointer) = self.pointer_slot()?;
+        let index = self.builder.build_load(pointer, "index")?.into_int_value();
+        let amount = self.context.i64_type().const_int(amount as u64, true);
+        let index = self.builder.build_int_add(index, amount, "index")?;
+        self.builder.build_store(pointer, index)?;
+        Ok(())
+    }
+
+    /// Branch on whether the current cell is zero, to `nonzero` or `zero`.
+    fn branch_on_cell(
+        &self,
+        nonzero: BasicBlock<'ctx>,
+        zero: BasicBlock<'ctx>,
+    ) -> Result<(), BuilderError> {
+        let val = self.load_cell(0)?;
+        let is_nonzero =
+            self.builder
+                .build_int_compare(IntPredicate::NE, val, self.cell_const(0), "nonzero")?;
+        self.builder
+            .build_conditional_branch(is_nonzero, nonzero, zero)?;
+        Ok(())
+    }
+
+    /// Emit `body` `count` times, in a counted loop unless it only runs once.
+    fn repeat(
+        &self,
+        count: u32,
+        body: impl Fn(&Self) -> Result<(), BuilderError>,
+    ) -> Result<(), BuilderError> {
+        if count == 1 {
+            return body(self);
+        }
+
+        let i32_type = self.context.i32_type();
+        let before = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned");
+        let repeat_block = self.context.append_basic_block(self.main, "repeat");
+        let done_block = self.context.append_basic_block(self.main, "repeat.done");
+        self.builder.build_unconditional_branch(repeat_block)?;
+
+        self.builder.position_at_end(repeat_block);
+        let counter = self.builder.build_phi(i32_type, "counter")?;
+        body(self)?;
+        let next = self.builder.build_int_add(
+            counter.as_basic_value().into_int_value(),
+            i32_type.const_int(1, false),
+            "counter",
+        )?;
+        let more = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            next,
+            i32_type.const_int(count as u64, false),
+            "more",
+        )?;
+        let body_end = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned");
+        counter.add_incoming(&[(&i32_type.const_zero(), before), (&next, body_end)]);
+        self.builder
+            .build_conditional_branch(more, repeat_block, done_block)?;
+
+        self.builder.position_at_end(done_block);
+        Ok(())
+    }
+
+    fn output(&self, offset: i64) -> Result<(), BuilderError> {
+        let val = self.load_cell(offset)?;
+        self.builder
+            .build_call(self.putchar, &[val.into()], "putchar")?;
+        Ok(())
+    }
+
+    fn input(&self, offset: i64) -> Result<(), BuilderError> {
+        let read = self
+            .builder
+            .build_call(self.getchar, &[], "getchar")?
+            .try_as_basic_value()
+            .left()
+            .expect("getchar returns an i32")
+            .into_int_value();
+        let eof = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            read,
+            self.context.i32_type().const_all_ones(),
+            "eof",
+        )?;
+        let old = self.load_cell(offset)?;
+        let new = self.builder.build_select(eof, old, read, "val")?;
+        self.store_cell(offset, new.into_int_value())
+    }
+
+    fn scan(&self, step: i64) -> Result<(), BuilderError> {
+        let header = self.context.append_basic_block(self.main, "scan");
+        let body = self.context.append_basic_block(self.main, "scan.body");
+        let exit = self.context.append_basic_block(self.main, "scan.done");
+        self.builder.build_unconditional_branch(header)?;
+        self.builder.position_at_end(header);
+        self.branch_on_cell(body, exit)?;
+        self.builder.position_at_end(body);
+        self.move_pointer(step)?;
+        self.builder.build_unconditional_branch(header)?;
+        self.builder.position_at_end(exit);
+        Ok(())
+    }
+
+    /// Add the current cell times each factor to the cell at each offset and clear it, skipping
+    /// the targets when the current cell is already zero since they may be off the tape.
+    fn linear_loop(&self, targets: &[(i64, i64)]) -> Result<(), BuilderError> {
+        let body = self.context.append_basic_block(self.main, "linear");
+        let exit = self.context.append_basic_block(self.main, "linear.done");
+        self.branch_on_cell(body, exit)?;
+
+        self.builder.position_at_end(body);
+        let val = self.load_cell(0)?;
+        for &(offset, factor) in targets {
+            let factor = self.context.i32_type().const_int(factor as u64, true);
+            let product = self.builder.build_int_mul(val, factor, "product")?;
+            let target = self.load_cell(offset)?;
+            let sum = self.builder.build_int_add(target, product, "sum")?;
+            self.store_cell(offset, sum)?;
+        }
+        self.store_cell(0, self.cell_const(0))?;
+        self.builder.build_unconditional_branch(exit)?;
+
+        self.builder.position_at_end(exit);
+        Ok(())
+    }
+
+    /// Write the bytes the prefix output, which are the interpreter's encoding of each cell rather
+    /// than its low byte as a lowered `.` writes, then set up the tape it left.
+    fn emit_const(&self, prefix: &ConstPrefix) -> Result<(), BuilderError> {
+        for &byte in &prefix.bytes {
+            self.builder.build_call(
+                self.putchar,
+                &[self.cell_const(byte as u64).into()],
+                "putchar",
+            )?;
+        }
+        for (offset, &val) in prefix.cells.iter().enumerate() {
+            self.store_cell(offset as i64, self.cell_const(val as u64))?;
+        }
+        self.move_pointer(prefix.pointer as i64)
+    }
+
+    /// Emit the given Exprs followed by `ret i32 0`. Loops are emitted from a stack of bodies
+    /// rather than recursively, so deeply nested programs cannot overflow the stack.
+    fn build(&self, exprs: &[Expr]) -> Result<(), BuilderError> {
+        // The bodies being emitted, the position reached in each, and each loop's header and exit
+        let mut frames: Vec<(&[Expr], usize, Option<LoopBlocks>)> = vec![(exprs, 0, None)];
+
+        while let Some((body, position, blocks)) = frames.pop() {
+            if position == body.len() {
+                if let Some((header, exit)) = blocks {
+                    self.builder.build_unconditional_branch(header)?;
+                    self.builder.position_at_end(exit);
+                }
+                continue;
+            }
+            frames.push((body, position + 1, blocks));
+
+            match &body[position].ty {
+                ExprType::Set(val) => self.store_cell(0, self.cell_const(*val as u64))?,
+                ExprType::Add(val) => {
+                    let sum = self.builder.build_int_add(
+                        self.load_cell(0)?,
+                        self.cell_const(*val as u64),
+                        "sum",
+                    )?;
+                    self.store_cell(0, sum)?;
+                }
+                ExprType::Sub(val) => {
+                    let difference = self.builder.build_int_sub(
+                        self.load_cell(0)?,
+                        self.cell_const(*val as u64),
+                        "difference",
+                    )?;
+                    self.store_cell(0, difference)?;
+                }
+                ExprType::MoveRight(val) => self.move_pointer(*val as i64)?,
+                ExprType::MoveLeft(val) => self.move_pointer(-(*val as i64))?,
+                ExprType::Output(val) => self.repeat(*val, |codegen| codegen.output(0))?,
+                ExprType::Input(val) => self.repeat(*val, |codegen| codegen.input(0))?,
+                ExprType::OutputAt { offset } => self.output(*offset)?,
+                ExprType::InputAt { offset } => self.input(*offset)?,
+                ExprType::ScanRight(val) => self.scan(*val as i64)?,
+                ExprType::ScanLeft(val) => self.scan(-(*val as i64))?,
+                ExprType::LinearLoop(targets) => self.linear_loop(targets)?,
+                ExprType::MoveCell { offset } => self.linear_loop(&[(*offset, 1)])?,
+                ExprType::EmitConst(prefix) => self.emit_const(prefix)?,
+                ExprType::SwitchTape(tape) => {
+                    let tape = self.context.i64_type().const_int(*tape as u64, false);
+                    self.builder.build_store(self.active, tape)?;
+                }
+                ExprType::LoopBlock(lb) => {
+                    let header = self.context.append_basic_block(self.main, "loop");
+                    let loop_body = self.context.append_basic_block(self.main, "loop.body");
+                    let exit = self.context.append_basic_block(self.main, "loop.done");
+                    self.builder.build_unconditional_branch(header)?;
+                    self.builder.position_at_end(header);
+                    self.branch_on_cell(loop_body, exit)?;
+                    self.builder.position_at_end(loop_body);
+                    frames.push((&lb.exprs, 0, Some((header, exit))));
+                }
+            }
+        }
+
+        self.builder
+            .build_return(Some(&self.context.i32_type().const_zero()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    use inkwell::context::Context;
+
+    use crate::expr::Expr;
+    use crate::generator::generate;
+    use crate::lexer::{lex_runs_with, lex_string, CommandSet, Lang};
+    use crate::llvm::{build_module, emit_llvm};
+    use crate::parser::{parse_token_runs, parse_tokens};
+
+    fn parse_source(source: &str) -> Vec<Expr> {
+        parse_tokens(lex_string(source.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn module_verifies() {
+        let exprs = parse_source(">+++[->++>+<<]>[>]<[<]+[,.-]..[-]>>[>+[-]<-]");
+        let context = Context::create();
+        let module = build_module(&context, &exprs, 100, 1);
+        if let Err(e) = module.verify() {
+            panic!("module_verifies had error {}", e);
+        }
+    }
+
+    /// Run the given IR with LLVM's interpreter, returning its output, or None where it is not
+    /// installed.
+    fn run_ir(ir: &str) -> Option<Vec<u8>> {
+        let mut lli = Command::new("lli")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        lli.stdin.take().unwrap().write_all(ir.as_bytes()).unwrap();
+        let output = lli.wait_with_output().unwrap();
+        assert!(output.status.success());
+        Some(output.stdout)
+    }
+
+    #[test]
+    fn module_runs() {
+        let ir = emit_llvm(&parse_source(&generate("Hi")), 30000, 0);
+        if let Some(output) = run_ir(&ir) {
+            assert_eq!(output, b"Hi");
+        }
+    }
+
+    #[test]
+    fn module_runs_two_tapes() {
+        let two_tape = CommandSet::default().with_lang(Lang::TwoTape);
+        let source = format!("{}}}{}{{.}}.", generate("H"), generate("i"));
+        let runs = lex_runs_with(source, two_tape).unwrap();
+        let ir = emit_llvm(&parse_token_runs(runs).unwrap(), 30000, 0);
+        if let Some(output) = run_ir(&ir) {
+            assert_eq!(output, b"HiHi");
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::Cursor;
+
+use crate::error::BrainfartResult;
+use crate::lexer::lex_string;
+use crate::parser::parse_tokens;
+use crate::progstate::ProgState;
+
+/// Run a brainfuck program given as a string literal, returning everything it outputs. Input can
+/// be given as a second argument of bytes, which `,` reads a line at a time as it does stdin;
+/// without it, the program sees the end of input at once.
+///
+/// The brackets in the literal are checked while the Rust program is compiled, so a program that
+/// can never parse is caught before it can run:
+///
+/// ```
+/// let output = brainfart::brainfart!("++++++++[>++++++++<-]>+.").unwrap();
+/// assert_eq!(output, b"A");
+///
+/// let echoed = brainfart::brainfart!(",.,.", b"h\ni\n").unwrap();
+/// assert_eq!(echoed, b"hi");
+/// ```
+///
+/// An unbalanced literal fails to compile:
+///
+/// ```compile_fail
+/// brainfart::brainfart!("+[.");
+/// ```
+#[macro_export]
+macro_rules! brainfart {
+    ($source:literal) => {
+        $crate::brainfart!($source, b"")
+    };
+    ($source:literal, $input:expr) => {{
+        const _: () = assert!(
+            $crate::macros::balanced($source),
+            "brainfart! program has unbalanced brackets"
+        );
+        $crate::macros::run_literal($source, $input)
+    }};
+}
+
+/// Whether every `[` in the given source has a matching `]` after it and every `]` a matching `[`
+/// before it. This is what `brainfart!` checks while compiling.
+pub const fn balanced(source: &str) -> bool {
+    let bytes = source.as_bytes();
+    let mut depth: usize = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'[' {
+            depth += 1;
+        } else if bytes[i] == b']' {
+            if depth == 0 {
+                return false;
+            }
+            depth -= 1;
+        }
+        i += 1;
+    }
+    depth == 0
+}
+
+/// Lex, parse and run the given source with the default Config on the given input, returning its
+/// output. This is what `brainfart!` expands to.
+pub fn run_literal(source: &str, input: &[u8]) -> BrainfartResult<Vec<u8>> {
+    let exprs = parse_tokens(lex_string(source.to_string())?)?;
+    let mut state = ProgState::with_output(vec![]);
+    state.set_input(Cursor::new(input.to_vec()));
+    state.run(&exprs)?;
+    Ok(state.output().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::macros::balanced;
+
+    #[test]
+    fn brainfart_macro_runs() {
+        assert_eq!(
+            crate::brainfart!("+++++++++++++++++++++++++++++++++.").unwrap(),
+            b"!"
+        );
+        assert_eq!(crate::brainfart!(",+.", b"a").unwrap(), b"b");
+    }
+
+    #[test]
+    fn balanced_brackets() {
+        assert!(balanced("+[>[-]<-]x"));
+        assert!(!balanced("+[."));
+        assert!(!balanced("]["));
+    }
+}
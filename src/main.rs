@@ -1,43 +1,911 @@
 use std::env;
+use std::fmt::Display;
 use std::fs;
+use std::io::{self, Cursor, IsTerminal, Read, Write};
 use std::process::exit;
+use std::time::Instant;
 
-mod error;
-mod expr;
-mod lexer;
-mod parser;
-mod progstate;
-mod token;
+mod options;
 
-use crate::error::BrainfartResult;
-use crate::expr::Expr;
-use crate::parser::parse_tokens;
-use crate::progstate::ProgState;
-use crate::token::Token;
+use brainfart::analysis::{check_infinite_loops, max_tape_index};
+use brainfart::checkpoint::Checkpoint;
+use brainfart::config::{ArithMode, Cell};
+use brainfart::coverage::uncovered;
+use brainfart::equiv::Behavior;
+use brainfart::error::{BrainfartError, BrainfartResult, ErrorFormat};
+use brainfart::expr::{Expr, ExprType};
+use brainfart::generator::generate;
+use brainfart::lexer::{self, Lexer, ReadChars};
+use brainfart::lint::{lint, Lint};
+use brainfart::minimize::minimize;
+use brainfart::parser::{parse_token_runs_with, ExprStream, ParseOptions};
+use brainfart::prefix::fold_const_prefix;
+use brainfart::progstate::{ProgState, TapeDump};
+#[cfg(feature = "hash")]
+use brainfart::sink::HashingSink;
+use brainfart::sink::{CountingSink, HexdumpSink, RleSink};
+use brainfart::source::{
+    self, default_paths, expand_dirs, open_source, read_source, read_stdin_program, STDIN_PATH,
+    TAB_WIDTH,
+};
+use brainfart::token::{Token, TokenRun, TokenType};
+#[cfg(feature = "watch")]
+use brainfart::watch::{watch_files, watch_loop, CLEAR_SCREEN};
+
+use crate::options::{parse_args, Options};
 
 fn main() {
-    let filenames: Vec<String> = env::args().skip(1).collect();
-    for filename in filenames {
-        let result: BrainfartResult<()> = run_file(filename);
-        match result {
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("{}", e);
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    if options.dump_tape_json && !cfg!(feature = "serde") {
+        diagnostic(
+            &options,
+            "ERROR: --dump-tape-json requires the serde feature",
+        );
+        exit(1);
+    }
+
+    if options.hash_output && !cfg!(feature = "hash") {
+        diagnostic(&options, "ERROR: --hash-output requires the hash feature");
+        exit(1);
+    }
+
+    if options.watch && !cfg!(feature = "watch") {
+        diagnostic(&options, "ERROR: --watch requires the watch feature");
+        exit(1);
+    }
+
+    if options.integrity && !cfg!(feature = "hash") {
+        diagnostic(&options, "ERROR: --integrity requires the hash feature");
+        exit(1);
+    }
+
+    if options.emit_llvm && !cfg!(feature = "llvm") {
+        diagnostic(&options, "ERROR: --emit-llvm requires the llvm feature");
+        exit(1);
+    }
+
+    if options.config.unbounded_cells && !cfg!(feature = "unbounded") {
+        diagnostic(
+            &options,
+            "ERROR: --unbounded-cells requires the unbounded feature",
+        );
+        exit(1);
+    }
+
+    if let Some(text) = &options.gen {
+        println!("{}", generate(text));
+        return;
+    }
+
+    if let Some((first, second)) = &options.diff {
+        exit(run_diff(first, second, &options));
+    }
+
+    let paths = match options.filenames.is_empty() {
+        true => default_paths(io::stdin().is_terminal()),
+        false => options.filenames.clone(),
+    };
+    if paths.is_empty() {
+        diagnostic(
+            &options,
+            "ERROR: no program given: name a file, or pipe a program into stdin",
+        );
+        exit(1);
+    }
+
+    let filenames = match expand_dirs(&paths) {
+        Ok(filenames) => filenames,
+        Err(e) => {
+            diagnostic(
+                &options,
+                format!("Encountered an error while reading a directory: {}", e),
+            );
+            exit(1);
+        }
+    };
+
+    if options.checkpoint_every.is_some() != options.checkpoint_file.is_some() {
+        diagnostic(
+            &options,
+            "ERROR: --checkpoint-every and --checkpoint-file must be given together",
+        );
+        exit(1);
+    }
+
+    if (options.checkpoint_every.is_some() || options.resume.is_some()) && options.stream {
+        diagnostic(&options, "ERROR: checkpoints cannot be used with --stream");
+        exit(1);
+    }
+
+    if let Some(name) = &options.cells_from_env {
+        if env::var(name).is_err() {
+            diagnostic(
+                &options,
+                format!("ERROR: environment variable {} is not set", name),
+            );
+            exit(1);
+        }
+    }
+
+    if options.no_io_extras {
+        let restriction = match options.sandbox {
+            true => "--sandbox",
+            false => "--no-io-extras",
+        };
+        if let Some(flag) = options.io_extra() {
+            diagnostic(
+                &options,
+                format!("ERROR: {} cannot be used with {}", flag, restriction),
+            );
+            exit(1);
+        }
+        if filenames.iter().any(|filename| filename.ends_with(".gz")) {
+            diagnostic(
+                &options,
+                format!(
+                    "ERROR: gzip-compressed programs cannot be used with {}",
+                    restriction
+                ),
+            );
+            exit(1);
+        }
+    }
+
+    if options.coverage && options.stream {
+        diagnostic(&options, "ERROR: --coverage cannot be used with --stream");
+        exit(1);
+    }
+
+    if options.watch && filenames.iter().any(|filename| filename == STDIN_PATH) {
+        diagnostic(
+            &options,
+            "ERROR: a program read from stdin cannot be used with --watch",
+        );
+        exit(1);
+    }
+
+    if options.stream && filenames.iter().any(|filename| filename == STDIN_PATH) {
+        diagnostic(
+            &options,
+            "ERROR: a program read from stdin cannot be used with --stream",
+        );
+        exit(1);
+    }
+
+    if options.resume.is_some() && filenames.len() != 1 {
+        diagnostic(
+            &options,
+            "ERROR: --resume needs exactly one file to pick back up",
+        );
+        exit(1);
+    }
+
+    if options.lint {
+        for filename in &filenames {
+            match lint_file(filename, &options) {
+                Ok(lints) => lints
+                    .iter()
+                    .for_each(|lint| println!("{}: {}", filename, lint)),
+                Err(e) => {
+                    error(&options, &e);
+                    show_source_context(&options, filename, &e);
+                    exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    if options.minimize {
+        let mut input = None;
+        for filename in &filenames {
+            match minimize_file(filename, &options, &mut input) {
+                Ok(Some(program)) => println!("{}: {}", filename, program),
+                Ok(None) => println!("{}: runs without error", filename),
+                Err(e) => {
+                    error(&options, &e);
+                    show_source_context(&options, filename, &e);
+                    exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    if options.estimate_cost {
+        for filename in &filenames {
+            match compile_file(filename, &options) {
+                Ok(exprs) => println!("{}: {}", filename, options.cost_table.estimate(&exprs)),
+                Err(e) => {
+                    error(&options, &e);
+                    show_source_context(&options, filename, &e);
+                    exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    if options.tape_bound {
+        for filename in &filenames {
+            match compile_file(filename, &options) {
+                Ok(exprs) => match max_tape_index(&exprs) {
+                    Some(index) => println!(
+                        "{}: reaches at most cell {}",
+                        filename,
+                        options.start_index + index
+                    ),
+                    None => println!("{}: unbounded", filename),
+                },
+                Err(e) => {
+                    error(&options, &e);
+                    show_source_context(&options, filename, &e);
+                    exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    if options.emit_llvm {
+        for filename in &filenames {
+            match compile_file(filename, &options) {
+                Ok(exprs) => print!("{}", emit_llvm(&exprs, &options)),
+                Err(e) => {
+                    error(&options, &e);
+                    show_source_context(&options, filename, &e);
+                    exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    if options.bench {
+        for filename in &filenames {
+            if let Err(e) = bench_file(filename, &options) {
+                error(&options, &e);
+                show_source_context(&options, filename, &e);
                 exit(1);
             }
         }
+        return;
+    }
+
+    if options.watch {
+        watch(&filenames, &options);
+        return;
+    }
+
+    let result: BrainfartResult<Cell> = if let Some(list) = &options.input_list {
+        run_input_list(&filenames, list, &options)
+    } else if options.count_only {
+        run_files(&filenames, &options, CountingSink::default, |state| {
+            println!("{}", state.output())
+        })
+    } else if options.integrity {
+        run_integrity(&filenames, &options)
+    } else if options.hash_output {
+        run_hashed(&filenames, &options)
+    } else if options.rle_output {
+        run_files(&filenames, &options, RleSink::default, |state| {
+            println!("{}", state.output())
+        })
+    } else if options.hexdump {
+        run_files(&filenames, &options, HexdumpSink::default, |state| {
+            println!("{}", state.output())
+        })
+    } else {
+        run_files(&filenames, &options, io::stdout, |_| ())
+    };
+    let last_cell = match result {
+        Ok(cell) => cell,
+        Err(e) => {
+            error(&options, &e);
+            exit(1);
+        }
+    };
+
+    if options.exit_from_cell {
+        exit((last_cell % 256) as i32);
+    }
+}
+
+/// Run the given files in order, each with a fresh ProgState writing to a new sink unless chaining,
+/// calling `finish` with each ProgState once its files are done. Returns the final value of the cell
+/// under the pointer. With --keep-going a file that fails does not stop the rest, and an error
+/// counting the failures is returned at the end instead.
+fn run_files<W: Write>(
+    filenames: &[String],
+    options: &Options,
+    sink: impl Fn() -> W,
+    finish: impl Fn(&ProgState<W>),
+) -> BrainfartResult<Cell> {
+    if filenames.is_empty() {
+        return Ok(0);
+    }
+
+    let mut failed = 0;
+    let mut state = new_state(options, sink());
+    for (i, filename) in filenames.iter().enumerate() {
+        if i > 0 && !options.chain {
+            finish(&state);
+            state = new_state(options, sink());
+        }
+        let result = run_reporting(&mut state, filename, options);
+        keep_going(result, filename, options, &mut failed)?;
+    }
+    finish(&state);
+    match failed {
+        0 => Ok(state.current()),
+        _ => Err(BrainfartError::FilesFailed(failed)),
     }
 }
 
-fn run_file(filename: String) -> BrainfartResult<()> {
-    let contents = fs::read_to_string(filename.clone())
-        .unwrap_or_else(|_| panic!("Encountered an error while attempting to read {}", filename));
-    let tokens_result: BrainfartResult<Vec<Token>> = lexer::lex_string(contents);
-    match tokens_result {
-        Ok(tokens) => {
-            let exprs_result: BrainfartResult<Vec<Expr>> = parse_tokens(tokens);
+/// Pass on the error of a file that failed, or with --keep-going print it along with the file's
+/// name and count it as a failure so the caller can carry on. With --show-source-context an error
+/// with a position is printed the same way and ends the run, since the file is not known once the
+/// error has been passed on.
+fn keep_going<T>(
+    result: BrainfartResult<T>,
+    filename: &str,
+    options: &Options,
+    failed: &mut usize,
+) -> BrainfartResult<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if options.keep_going => {
+            file_error(options, filename, &e);
+            *failed += 1;
+            Ok(None)
+        }
+        Err(e) if options.show_source_context && e.position().is_some() => {
+            file_error(options, filename, &e);
+            exit(1);
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Compile the given file and run it twice on the same input, once to warm up and once timed,
+/// then print the timed run's output and how long compiling and running took. The output is only
+/// written once the run has finished so that writing it is not timed. Stdin is read in full first
+/// if the program reads input.
+fn bench_file(filename: &str, options: &Options) -> BrainfartResult<()> {
+    let started = Instant::now();
+    let exprs = compile_file(filename, options)?;
+    let compiled = started.elapsed();
+
+    let mut input = vec![];
+    if reads_input(&exprs) {
+        io::stdin().read_to_end(&mut input).unwrap_or_else(|e| {
+            panic!("Encountered an error while attempting to read stdin: {}", e)
+        });
+    }
+
+    let mut warm_up = new_state(options, io::sink());
+    warm_up.set_input(Cursor::new(input.clone()));
+    warm_up.run(&exprs)?;
+
+    let mut state = new_state(options, vec![]);
+    state.set_input(Cursor::new(input));
+    let started = Instant::now();
+    let result = state.run(&exprs);
+    let ran = started.elapsed();
+    let mut stdout = io::stdout();
+    let _ = stdout
+        .write_all(state.output())
+        .and_then(|_| stdout.flush());
+    report(&mut state, result, options)?;
+    diagnostic(
+        options,
+        format!(
+            "BENCH {}: compiled in {:?}, ran in {:?}",
+            filename, compiled, ran
+        ),
+    );
+    Ok(())
+}
+
+/// Whether any of the given Exprs, including those in loop bodies, reads input.
+fn reads_input(exprs: &[Expr]) -> bool {
+    exprs.iter().any(|expr| match &expr.ty {
+        ExprType::Input(_) | ExprType::InputAt { .. } => true,
+        ExprType::LoopBlock(lb) => reads_input(&lb.exprs),
+        _ => false,
+    })
+}
+
+/// Lower the given Exprs to LLVM IR with the configured tape.
+#[cfg(feature = "llvm")]
+fn emit_llvm(exprs: &[Expr], options: &Options) -> String {
+    brainfart::llvm::emit_llvm(exprs, options.config.tape_size, options.start_index)
+}
+
+#[cfg(not(feature = "llvm"))]
+fn emit_llvm(_exprs: &[Expr], _options: &Options) -> String {
+    unreachable!("--emit-llvm is rejected without the llvm feature")
+}
+
+/// Run the given files to stdout, then clear the screen and run them again from the start each time
+/// one of them changes on disk. An error is printed rather than ending the watch, so that it can be
+/// fixed and the files saved again.
+#[cfg(feature = "watch")]
+fn watch(filenames: &[String], options: &Options) {
+    let (_watcher, changes) = watch_files(filenames).unwrap_or_else(|e| {
+        diagnostic(options, format!("ERROR: cannot watch the files: {}", e));
+        exit(1);
+    });
+    watch_loop(changes, || {
+        print!("{}", CLEAR_SCREEN);
+        if let Err(e) = run_files(filenames, options, io::stdout, |_| ()) {
+            error(options, &e);
+        }
+        let _ = io::stdout().flush();
+    });
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_filenames: &[String], _options: &Options) {
+    unreachable!("--watch is rejected without the watch feature")
+}
+
+/// Run the given files like `run_files`, printing a SHA-256 digest of each sink's output instead of
+/// the output itself.
+#[cfg(feature = "hash")]
+fn run_hashed(filenames: &[String], options: &Options) -> BrainfartResult<Cell> {
+    run_files(filenames, options, HashingSink::default, |state| {
+        println!("{}", state.output())
+    })
+}
+
+#[cfg(not(feature = "hash"))]
+fn run_hashed(_filenames: &[String], _options: &Options) -> BrainfartResult<Cell> {
+    unreachable!("--hash-output is rejected without the hash feature")
+}
+
+/// Run the given files like `run_files`, printing a SHA-256 digest of each sink's output and the
+/// final tape instead of the output itself.
+#[cfg(feature = "hash")]
+fn run_integrity(filenames: &[String], options: &Options) -> BrainfartResult<Cell> {
+    run_files(filenames, options, HashingSink::default, |state| {
+        println!("{}", state.output().with_tape(&state.tape_dump()))
+    })
+}
+
+#[cfg(not(feature = "hash"))]
+fn run_integrity(_filenames: &[String], _options: &Options) -> BrainfartResult<Cell> {
+    unreachable!("--integrity is rejected without the hash feature")
+}
+
+/// Run both files on the same input and print whether they behave the same, or the first
+/// difference between them. Returns the exit code: 0 if they behave the same and 1 otherwise.
+fn run_diff(first: &str, second: &str, options: &Options) -> i32 {
+    let input = match &options.diff_input {
+        Some(path) => fs::read(path).unwrap_or_else(|e| {
+            panic!(
+                "Encountered an error while attempting to read {}: {}",
+                path, e
+            )
+        }),
+        None => vec![],
+    };
+
+    let mut behaviors = vec![];
+    for filename in [first, second] {
+        let behavior = compile_file(filename, options)
+            .and_then(|exprs| Behavior::of(&exprs, &input, &options.config));
+        match behavior {
+            Ok(behavior) => behaviors.push(behavior),
+            Err(e) => {
+                file_error(options, filename, &e);
+                return 1;
+            }
+        }
+    }
+
+    match behaviors[0].first_difference(&behaviors[1]) {
+        Some(difference) => {
+            println!("{}", difference);
+            1
+        }
+        None => {
+            println!("equivalent");
+            0
+        }
+    }
+}
+
+/// Run each file once for every line of the given input list, each time with a fresh ProgState
+/// reading that line as its input, and end each run's output with a newline. Each file is only
+/// compiled once. Returns the final value of the cell under the pointer in the last run. With
+/// --keep-going a file that fails to compile or run is counted like in `run_files`.
+fn run_input_list(filenames: &[String], list: &str, options: &Options) -> BrainfartResult<Cell> {
+    let inputs = fs::read_to_string(list).unwrap_or_else(|e| {
+        panic!(
+            "Encountered an error while attempting to read {}: {}",
+            list, e
+        )
+    });
+
+    let mut failed = 0;
+    let mut last_cell: Cell = 0;
+    for filename in filenames {
+        let compiled = compile_file(filename, options);
+        let Some(exprs) = keep_going(compiled, filename, options, &mut failed)? else {
+            continue;
+        };
+        let mut failed_runs = 0;
+        for input in inputs.lines() {
+            let mut state = new_state(options, io::stdout());
+            state.set_input(Cursor::new(format!("{}\n", input)));
+            let result = state.run(&exprs);
+            let result = report(&mut state, result, options);
+            keep_going(result, filename, options, &mut failed_runs)?;
+            println!();
+            last_cell = state.current();
+        }
+        failed += failed_runs.min(1);
+    }
+    match failed {
+        0 => Ok(last_cell),
+        _ => Err(BrainfartError::FilesFailed(failed)),
+    }
+}
+
+/// Run the given file, printing any warnings and profile counts gathered while running whether or
+/// not it succeeds.
+fn run_reporting<W: Write>(
+    state: &mut ProgState<W>,
+    filename: &str,
+    options: &Options,
+) -> BrainfartResult<()> {
+    let result = run_source(state, filename, options);
+    report(state, result, options)
+}
+
+/// The length of the bar drawn for the most accessed cell with --heatmap.
+const HEATMAP_WIDTH: u64 = 40;
+
+/// Print any warnings, profile counts and heatmap gathered while running, and the final tape if
+/// requested, then pass on the result of the run.
+fn report<W: Write>(
+    state: &mut ProgState<W>,
+    result: BrainfartResult<()>,
+    options: &Options,
+) -> BrainfartResult<()> {
+    for warning in state.take_warnings() {
+        diagnostic(options, warning);
+    }
+    if let Some(profile) = state.take_profile() {
+        for (name, count) in profile {
+            diagnostic(options, format!("PROFILE {}: {}", name, count));
+        }
+        diagnostic(
+            options,
+            format!("PROFILE max loop depth: {}", state.take_max_loop_depth()),
+        );
+    }
+    if let Some(heatmap) = state.take_heatmap() {
+        let hottest = heatmap.iter().copied().max().unwrap_or(0);
+        for (index, &count) in heatmap.iter().enumerate().filter(|(_, &count)| count > 0) {
+            let bar = "#".repeat((count * HEATMAP_WIDTH).div_ceil(hottest) as usize);
+            diagnostic(
+                options,
+                format!("HEATMAP cell {}: {} {}", index, count, bar),
+            );
+        }
+    }
+    if options.dump_tape_json && result.is_ok() {
+        diagnostic(options, tape_json(&state.tape_dump()));
+    }
+    result
+}
+
+/// Print a diagnostic, such as a warning or an error, to stderr, or to stdout with --merge-streams
+/// so that it appears in order with the program's output.
+fn diagnostic(options: &Options, message: impl Display) {
+    if options.merge_streams {
+        println!("{}", message);
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+/// Print an error that stopped the program, in the format given with --error-format.
+fn error(options: &Options, e: &BrainfartError) {
+    match options.error_format {
+        ErrorFormat::Text => diagnostic(options, e),
+        ErrorFormat::Json => diagnostic(options, e.to_json()),
+    }
+}
+
+/// Print an error like `error`, naming the file it came from when printing text.
+fn file_error(options: &Options, filename: &str, e: &BrainfartError) {
+    match options.error_format {
+        ErrorFormat::Text => diagnostic(options, format!("{}: {}", filename, e)),
+        ErrorFormat::Json => diagnostic(options, e.to_json()),
+    }
+    show_source_context(options, filename, e);
+}
+
+/// With --show-source-context, print the line of the given file that an error points at with a
+/// caret under its column, when printing text. A program read from stdin cannot be read again, so
+/// it is not shown.
+fn show_source_context(options: &Options, filename: &str, e: &BrainfartError) {
+    if !options.show_source_context
+        || options.error_format != ErrorFormat::Text
+        || filename == STDIN_PATH
+    {
+        return;
+    }
+    let Some((line, col)) = e.position() else {
+        return;
+    };
+    let tab_width = options.tab_width.unwrap_or(TAB_WIDTH);
+    let context = read_source(filename)
+        .ok()
+        .and_then(|source| source::source_context(&source, line, col, tab_width));
+    if let Some(context) = context {
+        diagnostic(options, context);
+    }
+}
+
+#[cfg(feature = "serde")]
+fn tape_json(dump: &TapeDump) -> String {
+    serde_json::to_string(dump).expect("a TapeDump can always be serialized")
+}
+
+#[cfg(not(feature = "serde"))]
+fn tape_json(_dump: &TapeDump) -> String {
+    unreachable!("--dump-tape-json is rejected without the serde feature")
+}
+
+fn new_state<W: Write>(options: &Options, output: W) -> ProgState<W> {
+    let mut state = ProgState::with_output(output);
+    state.set_config(options.config.clone());
+    state.set_grow_chunk(options.grow_chunk);
+    state.set_data_index(options.start_index);
+    if let Some(value) = options
+        .cells_from_env
+        .as_ref()
+        .and_then(|name| env::var(name).ok())
+    {
+        state.write_cells(options.start_index, value.bytes().map(Cell::from));
+    }
+    if let Some(argv) = &options.argv {
+        let argv_cell = options.argv_cell.unwrap_or(options.start_index);
+        state.write_cells(argv_cell, argv.chars().map(|c| c as Cell));
+    }
+    if options.profile {
+        state.enable_profile();
+    }
+    if options.heatmap {
+        state.enable_heatmap();
+    }
+    if options.coverage {
+        state.enable_coverage();
+    }
+    if let Some(format) = options.trace {
+        if let Some(header) = format.header() {
+            diagnostic(options, header);
+        }
+        let merge_streams = options.merge_streams;
+        state.set_trace_hook(move |step| match merge_streams {
+            true => println!("{}", step.display(format)),
+            false => eprintln!("{}", step.display(format)),
+        });
+    }
+    if let Some(every) = options.progress {
+        let merge_streams = options.merge_streams;
+        state.set_progress_hook(every, move |progress| match merge_streams {
+            true => println!("{}", progress),
+            false => eprintln!("{}", progress),
+        });
+    }
+    if let (Some(every), Some(path)) = (options.checkpoint_every, &options.checkpoint_file) {
+        let path = path.clone();
+        state.set_checkpoint_hook(every, move |checkpoint| save_checkpoint(&path, checkpoint));
+    }
+    state
+}
+
+/// Save the given Checkpoint to the given file, writing it alongside first so that a crash
+/// partway through cannot leave a damaged checkpoint behind.
+fn save_checkpoint(path: &str, checkpoint: &Checkpoint) {
+    let partial = format!("{}.partial", path);
+    fs::write(&partial, checkpoint.to_string())
+        .and_then(|_| fs::rename(&partial, path))
+        .unwrap_or_else(|e| {
+            panic!(
+                "Encountered an error while attempting to write {}: {}",
+                path, e
+            )
+        });
+}
+
+/// Run the given Exprs from the start, or from the checkpoint given with `--resume`.
+fn run_exprs<W: Write>(
+    state: &mut ProgState<W>,
+    exprs: &[Expr],
+    options: &Options,
+) -> BrainfartResult<()> {
+    let Some(path) = &options.resume else {
+        return state.run(exprs);
+    };
+    let contents = fs::read_to_string(path).unwrap_or_else(|e| {
+        panic!(
+            "Encountered an error while attempting to read {}: {}",
+            path, e
+        )
+    });
+    let mut execution = contents
+        .parse()
+        .ok()
+        .and_then(|checkpoint| state.restore_checkpoint(&checkpoint, exprs))
+        .unwrap_or_else(|| panic!("{} is not a checkpoint of this program", path));
+    state.finish(&mut execution)
+}
+
+fn run_source<W: Write>(
+    state: &mut ProgState<W>,
+    filename: &str,
+    options: &Options,
+) -> BrainfartResult<()> {
+    if options.stream {
+        let reader = open_source(filename).unwrap_or_else(|e| {
+            panic!(
+                "Encountered an error while attempting to read {}: {}",
+                filename, e
+            )
+        });
+        let tokens = Lexer::with_commands(ReadChars::new(reader), options.commands)
+            .limit(options.config.limits);
+        state.run_stream(ExprStream::with_options(
+            tokens,
+            ParseOptions::for_config(&options.config),
+        ))
+    } else if filename == STDIN_PATH {
+        let (program, input) = read_stdin_program().unwrap_or_else(|e| {
+            panic!("Encountered an error while attempting to read stdin: {}", e)
+        });
+        if let Some(input) = input {
+            state.set_input(Cursor::new(input.into_bytes()));
+        }
+        let exprs = compile_source(program, options)?;
+        let result = run_exprs(state, &exprs, options);
+        report_coverage(state, &exprs, options);
+        result
+    } else {
+        let exprs = compile_file(filename, options)?;
+        let result = run_exprs(state, &exprs, options);
+        report_coverage(state, &exprs, options);
+        result
+    }
+}
+
+/// Print the position of each of the given Exprs that never ran, if coverage is enabled, then how
+/// many there were.
+fn report_coverage<W: Write>(state: &mut ProgState<W>, exprs: &[Expr], options: &Options) {
+    if let Some(coverage) = state.take_coverage() {
+        let never_ran = uncovered(exprs, &coverage);
+        for token in &never_ran {
+            diagnostic(
+                options,
+                format!("COVERAGE line {} col {}: never ran", token.line, token.col),
+            );
+        }
+        diagnostic(
+            options,
+            format!("COVERAGE {} instructions never ran", never_ran.len()),
+        );
+    }
+}
+
+fn lint_file(filename: &str, options: &Options) -> BrainfartResult<Vec<Lint>> {
+    let contents = read_source(filename).unwrap_or_else(|e| {
+        panic!(
+            "Encountered an error while attempting to read {}: {}",
+            filename, e
+        )
+    });
+    let tokens: Vec<Token> = Lexer::with_commands(contents.chars(), options.commands)
+        .limit(options.config.limits)
+        .collect::<BrainfartResult<_>>()?;
+    lint(&tokens)
+}
+
+/// Shrink the given file to the fewest commands that fail with the same kind of error, returning
+/// them as a program, or None if it does not fail. Stdin is read in full the first time a file
+/// that reads input is minimized, and kept in `input` for the rest.
+fn minimize_file(
+    filename: &str,
+    options: &Options,
+    input: &mut Option<Vec<u8>>,
+) -> BrainfartResult<Option<String>> {
+    let contents = read_source(filename).unwrap_or_else(|e| {
+        panic!(
+            "Encountered an error while attempting to read {}: {}",
+            filename, e
+        )
+    });
+    let tokens: Vec<Token> = Lexer::with_commands(contents.chars(), options.commands)
+        .limit(options.config.limits)
+        .collect::<BrainfartResult<_>>()?;
+    let input: &[u8] = if tokens.iter().any(|tok| tok.ty == TokenType::Input) {
+        input.get_or_insert_with(|| {
+            let mut input = vec![];
+            io::stdin().read_to_end(&mut input).unwrap_or_else(|e| {
+                panic!("Encountered an error while attempting to read stdin: {}", e)
+            });
+            input
+        })
+    } else {
+        &[]
+    };
+    let minimized = minimize(&tokens, &options.config, input);
+    Ok(minimized.map(|tokens| {
+        tokens
+            .iter()
+            .map(|tok| lexer::token_char(&tok.ty))
+            .collect()
+    }))
+}
+
+fn compile_file(filename: &str, options: &Options) -> BrainfartResult<Vec<Expr>> {
+    let contents = read_source(filename).unwrap_or_else(|e| {
+        panic!(
+            "Encountered an error while attempting to read {}: {}",
+            filename, e
+        )
+    });
+    compile_source(contents, options)
+}
+
+fn compile_source(contents: String, options: &Options) -> BrainfartResult<Vec<Expr>> {
+    let runs_result: BrainfartResult<Vec<TokenRun>> =
+        lexer::lex_runs_limited(contents, options.commands, options.config.limits);
+    match runs_result {
+        Ok(runs) => {
+            let parse_options = ParseOptions {
+                drop_dead_loops: !options.coverage,
+                ..ParseOptions::for_config(&options.config)
+            };
+            let exprs_result: BrainfartResult<Vec<Expr>> =
+                parse_token_runs_with(runs, &parse_options);
             match exprs_result {
-                Ok(exprs) => ProgState::default().run(&exprs),
+                Ok(exprs) => {
+                    if options.config.arith == ArithMode::Strict {
+                        for warning in check_infinite_loops(&exprs)? {
+                            diagnostic(options, warning);
+                        }
+                    }
+                    let exprs = if options.chain
+                        || options.argv.is_some()
+                        || options.cells_from_env.is_some()
+                        || options.heatmap
+                        || options.coverage
+                        || options.profile
+                        || options.trace.is_some()
+                        || options.progress.is_some()
+                        || options.checkpoint_every.is_some()
+                        || options.resume.is_some()
+                        // The lowered `.` writes a cell's low byte, not the UTF-8 a prefix holds
+                        || options.emit_llvm
+                    {
+                        exprs
+                    } else {
+                        fold_const_prefix(exprs, &options.config, options.start_index)
+                    };
+                    Ok(exprs)
+                }
                 Err(e) => Err(e),
             }
         }
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+
+use crate::expr::{Expr, ExprType, Shape};
+
+/// A LoopMemo caches the result of analyzing a loop body, keyed by the body's Shape, so that a
+/// program containing many identical loops only has each distinct body analyzed once.
+#[derive(Debug)]
+pub struct LoopMemo<'a, T> {
+    results: HashMap<Shape<'a>, T>,
+}
+
+impl<T> Default for LoopMemo<'_, T> {
+    fn default() -> Self {
+        LoopMemo {
+            results: HashMap::new(),
+        }
+    }
+}
+
+impl<'a, T: Clone> LoopMemo<'a, T> {
+    /// Return the cached result for a body of the same Shape as the given Exprs, computing and
+    /// caching it first if no such body has been seen.
+    pub fn get_or_compute<F>(&mut self, exprs: &'a [Expr], compute: F) -> T
+    where
+        F: FnOnce(&'a [Expr]) -> T,
+    {
+        self.results
+            .entry(Shape(exprs))
+            .or_insert_with(|| compute(exprs))
+            .clone()
+    }
+
+    /// The number of distinct loop bodies cached.
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Whether no loop bodies have been cached.
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
+
+/// The LinearLoop targets worked out for a loop body, or None if it is not a linear loop.
+pub type LinearTargets = Option<Vec<(i64, i64)>>;
+
+/// A LinearMemo caches the LinearLoop targets the parser works out for a loop body that only adds,
+/// subtracts and moves, keyed by those instructions, so a program with many identical linear loops
+/// only has each distinct body's effect computed once. Unlike a LoopMemo it owns its keys, since
+/// the parser consumes each loop body as it folds it.
+#[derive(Debug, Default)]
+pub struct LinearMemo {
+    targets: HashMap<Vec<(Discriminant<ExprType>, u32)>, LinearTargets>,
+}
+
+impl LinearMemo {
+    /// Return the cached targets for a body with the same instructions as the given Exprs,
+    /// computing and caching them first if no such body has been seen. Bodies containing anything
+    /// other than additions, subtractions and moves are never linear, so they are computed without
+    /// being cached.
+    pub fn get_or_compute<F>(&mut self, exprs: &[Expr], compute: F) -> LinearTargets
+    where
+        F: FnOnce(&[Expr]) -> LinearTargets,
+    {
+        let key: Option<Vec<_>> = exprs
+            .iter()
+            .map(|expr| match expr.ty {
+                ExprType::Add(val)
+                | ExprType::Sub(val)
+                | ExprType::MoveRight(val)
+                | ExprType::MoveLeft(val) => Some((mem::discriminant(&expr.ty), val)),
+                _ => None,
+            })
+            .collect();
+        match key {
+            Some(key) => self
+                .targets
+                .entry(key)
+                .or_insert_with(|| compute(exprs))
+                .clone(),
+            None => compute(exprs),
+        }
+    }
+
+    /// The number of distinct loop bodies cached.
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    /// Whether no loop bodies have been cached.
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::{Expr, ExprType, Shape};
+    use crate::lexer::lex_string;
+    use crate::memo::{LinearMemo, LoopMemo};
+    use crate::parser::{parse_tokens, parse_tokens_with, ParseOptions};
+
+    fn parse(source: &str) -> Vec<Expr> {
+        parse_tokens(lex_string(source.to_string()).unwrap()).unwrap()
+    }
+
+    /// The bodies of the top-level loops in the given Exprs.
+    fn loop_bodies(exprs: &[Expr]) -> Vec<&[Expr]> {
+        exprs
+            .iter()
+            .filter_map(|expr| match &expr.ty {
+                ExprType::LoopBlock(lb) => Some(lb.exprs.as_slice()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shape_ignores_positions() {
+        let exprs = parse("[->+<[.]]>\n  [->+<[.]]>[->+<[,]]");
+        let bodies = loop_bodies(&exprs);
+        assert_ne!(bodies[0], bodies[1]);
+        assert_eq!(Shape(bodies[0]), Shape(bodies[1]));
+        assert_ne!(Shape(bodies[0]), Shape(bodies[2]));
+    }
+
+    #[test]
+    fn shape_deeply_nested() {
+        let depth = 100000;
+        let nested = |body: &str| format!("[{}{}{}]", "[".repeat(depth), body, "]".repeat(depth));
+        let exprs = parse(&format!("{}>{}>{}", nested("-"), nested("-"), nested("+")));
+        let bodies = loop_bodies(&exprs);
+        assert_eq!(Shape(bodies[0]), Shape(bodies[1]));
+        assert_ne!(Shape(bodies[0]), Shape(bodies[2]));
+
+        let mut memo: LoopMemo<()> = LoopMemo::default();
+        bodies
+            .iter()
+            .for_each(|body| memo.get_or_compute(body, |_| ()));
+        assert_eq!(memo.len(), 2);
+    }
+
+    #[test]
+    fn memo_identical_loops() {
+        let exprs = parse(&"[->++>+++<<.]>".repeat(5000));
+        let mut memo: LoopMemo<usize> = LoopMemo::default();
+        let mut computed = 0;
+
+        for body in loop_bodies(&exprs) {
+            let len = memo.get_or_compute(body, |body| {
+                computed += 1;
+                body.len()
+            });
+            assert_eq!(len, 7);
+        }
+
+        assert_eq!(computed, 1);
+        assert_eq!(memo.len(), 1);
+    }
+
+    #[test]
+    fn linear_memo_identical_loops() {
+        let options = ParseOptions {
+            fold_linear_loops: false,
+            ..ParseOptions::default()
+        };
+        let source = "[->++>+++<<]>[.]>".repeat(5000);
+        let exprs = parse_tokens_with(lex_string(source).unwrap(), &options).unwrap();
+        let mut memo = LinearMemo::default();
+        let mut computed = 0;
+
+        for body in loop_bodies(&exprs) {
+            memo.get_or_compute(body, |_| {
+                computed += 1;
+                Some(vec![(1, 2), (2, 3)])
+            });
+        }
+
+        assert_eq!(computed, 5001);
+        assert_eq!(memo.len(), 1);
+    }
+}
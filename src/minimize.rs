@@ -0,0 +1,83 @@
+use std::io::{self, Cursor};
+
+use crate::config::Config;
+use crate::error::BrainfartResult;
+use crate::parser::parse_tokens;
+use crate::progstate::ProgState;
+use crate::token::Token;
+
+/// How many steps each attempt may run for when the Config sets no step limit, since removing
+/// commands can turn a failing program into one that never ends.
+pub const MINIMIZE_STEP_LIMIT: u64 = 1_000_000;
+
+/// Shrink a program that fails to the fewest commands that still fail with the same kind of error,
+/// by delta debugging: remove ever smaller chunks of commands, keeping each removal after which the
+/// program still fails the same way, until no single command can be removed. Each attempt runs on a
+/// fresh tape under the given Config with the given input. Returns None if the program does not
+/// fail.
+pub fn minimize(tokens: &[Token], config: &Config, input: &[u8]) -> Option<Vec<Token>> {
+    let mut config = config.clone();
+    config.limits.steps.get_or_insert(MINIMIZE_STEP_LIMIT);
+    let kind = |tokens: &[Token]| run_tokens(tokens, &config, input).err().map(|e| e.kind());
+
+    let target = kind(tokens)?;
+    let mut tokens = tokens.to_vec();
+    let mut chunk = (tokens.len() / 2).max(1);
+    loop {
+        let mut removed = false;
+        let mut start = 0;
+        while start < tokens.len() {
+            let end = (start + chunk).min(tokens.len());
+            let candidate = [&tokens[..start], &tokens[end..]].concat();
+            if kind(&candidate) == Some(target) {
+                tokens = candidate;
+                removed = true;
+            } else {
+                start = end;
+            }
+        }
+        match chunk {
+            1 if !removed => return Some(tokens),
+            1 => (),
+            _ => chunk /= 2,
+        }
+    }
+}
+
+/// Parse and run the given Tokens on a fresh tape, discarding the output.
+fn run_tokens(tokens: &[Token], config: &Config, input: &[u8]) -> BrainfartResult<()> {
+    let exprs = parse_tokens(tokens.to_vec())?;
+    let mut state = ProgState::with_output(io::sink());
+    state.set_config(config.clone());
+    state.set_input(Cursor::new(input.to_vec()));
+    state.run(&exprs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::lexer::{lex_string, token_char};
+    use crate::minimize::minimize;
+
+    fn minimize_source(source: &str) -> Option<String> {
+        let tokens = lex_string(source.to_string()).unwrap();
+        minimize(&tokens, &Config::default(), b"")
+            .map(|tokens| tokens.iter().map(|tok| token_char(&tok.ty)).collect())
+    }
+
+    #[test]
+    fn minimize_to_fragment() {
+        let padding = "+>+-<-+[-]>>><<<".repeat(5);
+        let source = format!("{}++[>+<-]>---{}", padding, padding);
+        assert_eq!(minimize_source(&source).as_deref(), Some("-"));
+        assert_eq!(
+            minimize_source(&format!("{}<", padding)).as_deref(),
+            Some("<")
+        );
+    }
+
+    #[test]
+    fn minimize_passing_program() {
+        assert_eq!(minimize_source("+++[-]."), None);
+    }
+}
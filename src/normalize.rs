@@ -0,0 +1,94 @@
+use crate::expr::{Expr, ExprType};
+
+/// Rewrite every `Sub(n)` in the given Exprs, including inside loops, as the `Add` of its two's
+/// complement, so that later passes only have to handle additions. This only preserves behavior
+/// with ArithMode::Wrapping, where the two are the same: with ArithMode::Strict the Add would
+/// overflow instead. It is also only correct for cell widths that divide 2^32, so not for
+/// unbounded cells, where the complement is far short of wrapping around.
+///
+/// The overflows an overflow hook sees do not match the original program's. With 32-bit cells a
+/// rewritten Add overflows exactly when its Sub would not have underflowed, such as on `5 - 1`,
+/// and with narrower cells every rewritten Add overflows. Leave Subs as they are when overflows
+/// are being watched.
+///
+/// This is an optional pass for library users: `bft` never runs it, since an Add of a complement
+/// runs slower than the Sub it replaces and is reported as an addition by profiles and traces.
+pub fn subs_to_adds(exprs: &mut [Expr]) {
+    let mut pending: Vec<&mut [Expr]> = vec![exprs];
+    while let Some(body) = pending.pop() {
+        for expr in body {
+            if let ExprType::Sub(val) = expr.ty {
+                expr.ty = ExprType::Add(val.wrapping_neg());
+            } else if let ExprType::LoopBlock(lb) = &mut expr.ty {
+                pending.push(&mut lb.exprs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::config::{ArithMode, Config};
+    use crate::expr::{Expr, ExprType};
+    use crate::lexer::lex_string;
+    use crate::normalize::subs_to_adds;
+    use crate::parser::parse_tokens;
+    use crate::progstate::{CellOverflow, ProgState};
+
+    fn run_wrapping(exprs: &[Expr]) -> Vec<u8> {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            arith: ArithMode::Wrapping,
+            ..Config::default()
+        });
+        state.run(exprs).unwrap();
+        state.output().clone()
+    }
+
+    #[test]
+    fn subs_become_adds() {
+        let mut exprs = parse_tokens(lex_string("+++>--[>-<.]".to_string()).unwrap()).unwrap();
+        subs_to_adds(&mut exprs);
+        assert_eq!(exprs[0].ty, ExprType::Add(3));
+        assert_eq!(exprs[2].ty, ExprType::Add(u32::MAX - 1));
+        match &exprs[3].ty {
+            ExprType::LoopBlock(lb) => assert_eq!(lb.exprs[1].ty, ExprType::Add(u32::MAX)),
+            other => panic!("subs_become_adds had expr {:?}", other),
+        }
+    }
+
+    #[test]
+    fn subs_to_adds_overflows() {
+        let overflows = |exprs: &[Expr]| {
+            let events = Rc::new(RefCell::new(vec![]));
+            let seen = events.clone();
+            let mut state = ProgState::with_output(vec![]);
+            state.set_config(Config {
+                arith: ArithMode::Wrapping,
+                ..Config::default()
+            });
+            state.set_overflow_hook(move |_, overflow| seen.borrow_mut().push(overflow));
+            state.run(exprs).unwrap();
+            let events = events.borrow().clone();
+            events
+        };
+        let exprs = parse_tokens(lex_string("+++++>-<-".to_string()).unwrap()).unwrap();
+        let mut normalized = exprs.clone();
+        subs_to_adds(&mut normalized);
+        assert_eq!(overflows(&exprs), vec![CellOverflow::Under]);
+        assert_eq!(overflows(&normalized), vec![CellOverflow::Over]);
+    }
+
+    #[test]
+    fn subs_to_adds_same_output() {
+        let source = "++++++++[>++++++++++<-]>--.+++.---.>-[-<+>]<.".to_string();
+        let exprs = parse_tokens(lex_string(source).unwrap()).unwrap();
+        let mut normalized = exprs.clone();
+        subs_to_adds(&mut normalized);
+        assert_ne!(normalized, exprs);
+        assert_eq!(run_wrapping(&normalized), run_wrapping(&exprs));
+    }
+}
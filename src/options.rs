@@ -0,0 +1,796 @@
+use std::str::FromStr;
+use std::time::Duration;
+use std::vec::IntoIter;
+
+use brainfart::config::{ArithMode, Config, OutputDelay, ReadOnly, ReadOnlyStart};
+use brainfart::cost::CostTable;
+use brainfart::error::{BrainfartError, BrainfartResult, ErrorFormat};
+use brainfart::lexer::CommandSet;
+use brainfart::trace::TraceFormat;
+
+/// Options holds the configuration given on the command line: the files to run and any flags that
+/// change how they are run.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// The source files to run, in the order they were given
+    pub filenames: Vec<String>,
+    /// Text to generate a printing program for instead of running any files
+    pub gen: Option<String>,
+    /// Whether to report the size and hash of the output instead of printing it
+    pub count_only: bool,
+    /// Two files to run on the same input and compare instead of running any files
+    pub diff: Option<(String, String)>,
+    /// A file holding the input the files compared with `diff` are run on
+    pub diff_input: Option<String>,
+    /// Whether to print the Lints found in each file instead of running it
+    pub lint: bool,
+    /// Whether to print the smallest part of each file that fails the same way instead of running it
+    pub minimize: bool,
+    /// Whether to print a static cost estimate of each file instead of running it
+    pub estimate_cost: bool,
+    /// The cost of each kind of Expr used for the estimate
+    pub cost_table: CostTable,
+    /// Whether to print a SHA-256 digest of the output instead of printing it
+    pub hash_output: bool,
+    /// Whether to print a SHA-256 digest of the output and the final tape instead of the output
+    pub integrity: bool,
+    /// Whether to print the output run-length encoded instead of as is
+    pub rle_output: bool,
+    /// Whether to print the output as a hex dump instead of as is
+    pub hexdump: bool,
+    /// Whether to print a static bound on the highest cell index each file reaches instead of
+    /// running it
+    pub tape_bound: bool,
+    /// Whether to time running each file after a warm-up run and print how long it took
+    pub bench: bool,
+    /// Whether to print each file lowered to LLVM IR instead of running it
+    pub emit_llvm: bool,
+    /// Whether to start running each file while it is still being read
+    pub stream: bool,
+    /// Whether to run the files again each time one of them changes
+    pub watch: bool,
+    /// A file whose lines are each fed as the input of a separate run of every file
+    pub input_list: Option<String>,
+    /// Whether to print the final tape of each file as JSON
+    pub dump_tape_json: bool,
+    /// Whether to print how many times each kind of Expr ran after each file
+    pub profile: bool,
+    /// Whether to print how many times each cell was read or written after each file
+    pub heatmap: bool,
+    /// Whether to print the positions of the Exprs that never ran after each file
+    pub coverage: bool,
+    /// The format to print each Expr in as it runs, if tracing
+    pub trace: Option<TraceFormat>,
+    /// Whether to print warnings and errors to stdout along with the output instead of to stderr
+    pub merge_streams: bool,
+    /// The format to print errors in
+    pub error_format: ErrorFormat,
+    /// Whether to show the source line a file's error points at, with a caret under the column
+    pub show_source_context: bool,
+    /// The columns between tab stops when showing a source line, if not the default TAB_WIDTH
+    pub tab_width: Option<usize>,
+    /// Whether to run every file on the same tape instead of giving each a fresh one
+    pub chain: bool,
+    /// The number of cells to grow the tape by at a time, or 0 to at least double it
+    pub grow_chunk: usize,
+    /// Whether to carry on with the remaining files after one fails instead of stopping
+    pub keep_going: bool,
+    /// Whether to exit with the final value of the cell under the pointer
+    pub exit_from_cell: bool,
+    /// The cell the data pointer starts on
+    pub start_index: usize,
+    /// Text to write into the tape, one character per cell, before running each file
+    pub argv: Option<String>,
+    /// The cell to write `argv` from instead of the starting cell
+    pub argv_cell: Option<usize>,
+    /// The environment variable whose bytes are written into the tape from the starting cell
+    pub cells_from_env: Option<String>,
+    /// How many steps to run between saving checkpoints of each file's run
+    pub checkpoint_every: Option<u64>,
+    /// How many steps to run between progress lines, if printing them
+    pub progress: Option<u64>,
+    /// The file checkpoints are saved to
+    pub checkpoint_file: Option<String>,
+    /// A checkpoint file to pick the run back up from instead of starting it afresh
+    pub resume: Option<String>,
+    /// Whether to ignore other policy flags and run with the canonical Config, without streaming
+    pub deterministic: bool,
+    /// Whether to ignore other policy flags and run with the strict Config: canonical brainfuck
+    /// with 8-bit cells, without streaming
+    pub strict: bool,
+    /// Whether to run with a fixed tape, the sandbox limits for any limit not given, and no access
+    /// to files or the environment as with `no_io_extras`
+    pub sandbox: bool,
+    /// Whether to refuse the flags that read or write files other than the programs, or read the
+    /// environment, so that programs can only reach stdin and stdout
+    pub no_io_extras: bool,
+    /// The characters the files' commands are written with, and the language they are in
+    pub commands: CommandSet,
+    /// The policies to run the files with
+    pub config: Config,
+}
+
+impl Options {
+    /// The first flag given that reads or writes a file other than the programs or reads the
+    /// environment, which `--no-io-extras` refuses.
+    pub fn io_extra(&self) -> Option<&'static str> {
+        [
+            (self.input_list.is_some(), "--input-list"),
+            (self.diff_input.is_some(), "--diff-input"),
+            (self.checkpoint_file.is_some(), "--checkpoint-file"),
+            (self.resume.is_some(), "--resume"),
+            (self.cells_from_env.is_some(), "--cells-from-env"),
+        ]
+        .into_iter()
+        .find_map(|(given, flag)| given.then_some(flag))
+    }
+}
+
+/// How many steps run between progress lines with `--progress` when `--progress-every` is not
+/// given.
+pub const PROGRESS_EVERY: u64 = 100_000_000;
+
+/// Parse the command line arguments, excluding the binary name, into Options.
+pub fn parse_args(args: Vec<String>) -> BrainfartResult<Options> {
+    let mut options = Options::default();
+    let mut args_iter = args.into_iter();
+    let mut readonly_from = ReadOnlyStart::default();
+    let mut delay_per_line = false;
+
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--gen" => options.gen = Some(next_value(&arg, &mut args_iter)?),
+            "--count-only" => options.count_only = true,
+            "--diff" => {
+                let first = next_value(&arg, &mut args_iter)?;
+                options.diff = Some((first, next_value(&arg, &mut args_iter)?));
+                options.deterministic = true;
+            }
+            "--diff-input" => options.diff_input = Some(next_value(&arg, &mut args_iter)?),
+            "--hash-output" => options.hash_output = true,
+            "--integrity" => options.integrity = true,
+            "--rle-output" => options.rle_output = true,
+            "--hexdump" => options.hexdump = true,
+            "--lint" => options.lint = true,
+            "--minimize" => options.minimize = true,
+            "--estimate-cost" => options.estimate_cost = true,
+            "--cost-table" => options.cost_table = parse_value(&arg, &mut args_iter)?,
+            "--tape-bound" => options.tape_bound = true,
+            "--bench" => options.bench = true,
+            "--emit-llvm" => options.emit_llvm = true,
+            "--stream" => options.stream = true,
+            "--watch" => options.watch = true,
+            "--exit-from-cell" => options.exit_from_cell = true,
+            "--chain" => options.chain = true,
+            "--keep-going" => options.keep_going = true,
+            "--profile" => options.profile = true,
+            "--heatmap" => options.heatmap = true,
+            "--coverage" => options.coverage = true,
+            "--trace" => {
+                options.trace.get_or_insert_with(TraceFormat::default);
+            }
+            "--trace-format" => options.trace = Some(parse_value(&arg, &mut args_iter)?),
+            "--merge-streams" => options.merge_streams = true,
+            "--error-format" => options.error_format = parse_value(&arg, &mut args_iter)?,
+            "--show-source-context" => options.show_source_context = true,
+            "--tab-width" => options.tab_width = Some(parse_value(&arg, &mut args_iter)?),
+            "--dump-tape-json" => options.dump_tape_json = true,
+            "--input-list" => options.input_list = Some(next_value(&arg, &mut args_iter)?),
+            "--deterministic" => options.deterministic = true,
+            "--strict" => options.strict = true,
+            "--sandbox" => options.sandbox = true,
+            "--no-io-extras" => options.no_io_extras = true,
+            "--max-steps" => options.config.limits.steps = Some(parse_value(&arg, &mut args_iter)?),
+            "--max-output" => {
+                options.config.limits.output = Some(parse_value(&arg, &mut args_iter)?)
+            }
+            "--timeout" => {
+                let secs = parse_value(&arg, &mut args_iter)?;
+                options.config.limits.time = Some(Duration::from_secs(secs))
+            }
+            "--max-source-bytes" => {
+                options.config.limits.source_bytes = Some(parse_value(&arg, &mut args_iter)?)
+            }
+            "--max-tokens" => {
+                options.config.limits.tokens = Some(parse_value(&arg, &mut args_iter)?)
+            }
+            "--commands" => {
+                let commands: CommandSet = parse_value(&arg, &mut args_iter)?;
+                options.commands = commands.with_lang(options.commands.lang());
+            }
+            "--lang" => {
+                options.commands = options
+                    .commands
+                    .with_lang(parse_value(&arg, &mut args_iter)?)
+            }
+            "--progress" => {
+                options.progress.get_or_insert(PROGRESS_EVERY);
+            }
+            "--progress-every" => options.progress = Some(parse_value(&arg, &mut args_iter)?),
+            "--checkpoint-every" => {
+                options.checkpoint_every = Some(parse_value(&arg, &mut args_iter)?)
+            }
+            "--checkpoint-file" => {
+                options.checkpoint_file = Some(next_value(&arg, &mut args_iter)?)
+            }
+            "--resume" => options.resume = Some(next_value(&arg, &mut args_iter)?),
+            "--argv" => options.argv = Some(next_value(&arg, &mut args_iter)?),
+            "--argv-cell" => options.argv_cell = Some(parse_value(&arg, &mut args_iter)?),
+            "--cells-from-env" => options.cells_from_env = Some(next_value(&arg, &mut args_iter)?),
+            "--start-index" => options.start_index = parse_value(&arg, &mut args_iter)?,
+            "--eof-flag-cell" => {
+                options.config.eof_flag_cell = Some(parse_value(&arg, &mut args_iter)?)
+            }
+            "--no-grow" => options.config.no_grow = true,
+            "--unbounded-cells" => options.config.unbounded_cells = true,
+            "--cell-width" => options.config.cell_size = parse_value(&arg, &mut args_iter)?,
+            "--wrap" => options.config.arith = ArithMode::Wrapping,
+            "--warn-control-output" => options.config.warn_control_output = true,
+            "--warn-uninit" => options.config.warn_uninit = true,
+            "--detect-hangs" => options.config.detect_hangs = true,
+            "--readonly-after" => {
+                options.config.readonly = Some(ReadOnly {
+                    below: parse_value(&arg, &mut args_iter)?,
+                    from: ReadOnlyStart::default(),
+                })
+            }
+            "--readonly-from" => readonly_from = parse_value(&arg, &mut args_iter)?,
+            "--output-delay" => {
+                let millis = parse_value(&arg, &mut args_iter)?;
+                options.config.output_delay = Some(OutputDelay {
+                    duration: Duration::from_millis(millis),
+                    per_line: false,
+                })
+            }
+            "--delay-per-line" => delay_per_line = true,
+            "--tape-size" => options.config.tape_size = parse_value(&arg, &mut args_iter)?,
+            "--grow-chunk" => options.grow_chunk = parse_value(&arg, &mut args_iter)?,
+            "--max-tape-report" => options.config.tape_report = parse_value(&arg, &mut args_iter)?,
+            "--invalid-output" => {
+                options.config.invalid_output = parse_value(&arg, &mut args_iter)?
+            }
+            _ if arg.starts_with("--") => return Err(BrainfartError::UnknownFlag(arg)),
+            _ => options.filenames.push(arg),
+        }
+    }
+
+    if let Some(readonly) = &mut options.config.readonly {
+        readonly.from = readonly_from;
+    }
+    if let Some(delay) = &mut options.config.output_delay {
+        delay.per_line = delay_per_line;
+    }
+    if options.deterministic || options.strict {
+        let base = match options.strict {
+            true => Config::strict(),
+            false => Config::canonical(),
+        };
+        options.config = Config {
+            readonly: options.config.readonly,
+            limits: options.config.limits,
+            ..base
+        };
+        options.stream = false;
+    }
+    if options.sandbox {
+        options.config = options.config.sandboxed();
+        options.no_io_extras = true;
+    }
+
+    Ok(options)
+}
+
+/// Take the value following the given flag from the remaining arguments.
+fn next_value(flag: &str, args: &mut IntoIter<String>) -> BrainfartResult<String> {
+    args.next()
+        .ok_or_else(|| BrainfartError::MissingValue(flag.to_string()))
+}
+
+/// Take the value following the given flag from the remaining arguments and parse it.
+fn parse_value<T: FromStr>(flag: &str, args: &mut IntoIter<String>) -> BrainfartResult<T> {
+    let value = next_value(flag, args)?;
+    value
+        .parse()
+        .map_err(|_| BrainfartError::InvalidValue(flag.to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::{parse_args, Options, PROGRESS_EVERY};
+    use brainfart::config::{
+        ArithMode, CellWidth, Config, InvalidOutput, Limits, OutputDelay, ReadOnly, ReadOnlyStart,
+    };
+    use brainfart::error::{BrainfartError, ErrorFormat};
+    use brainfart::lexer::Lang;
+    use brainfart::trace::TraceFormat;
+    use std::time::Duration;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_filenames() {
+        let options = parse_args(args(&["a.bf", "b.bf"])).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                filenames: args(&["a.bf", "b.bf"]),
+                ..Options::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_gen() {
+        let options = parse_args(args(&["--gen", "Hello"])).unwrap();
+        assert_eq!(options.gen, Some("Hello".to_string()));
+        assert!(options.filenames.is_empty());
+    }
+
+    #[test]
+    fn parse_diff() {
+        let options =
+            parse_args(args(&["--diff", "a.bf", "b.bf", "--diff-input", "in.txt"])).unwrap();
+        assert_eq!(options.diff, Some(("a.bf".to_string(), "b.bf".to_string())));
+        assert_eq!(options.diff_input, Some("in.txt".to_string()));
+        assert_eq!(options.config, Config::canonical());
+        assert!(options.filenames.is_empty());
+        assert!(parse_args(args(&["--diff", "a.bf"])).is_err());
+    }
+
+    #[test]
+    fn parse_missing_value() {
+        match parse_args(args(&["--gen"])) {
+            Err(BrainfartError::MissingValue(flag)) => assert_eq!(flag, "--gen"),
+            other => panic!("parse_missing_value had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_unknown_flag() {
+        match parse_args(args(&["--bogus", "a.bf"])) {
+            Err(BrainfartError::UnknownFlag(flag)) => assert_eq!(flag, "--bogus"),
+            other => panic!("parse_unknown_flag had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_count_only() {
+        let options = parse_args(args(&["a.bf", "--count-only"])).unwrap();
+        assert!(options.count_only);
+        assert_eq!(options.filenames, args(&["a.bf"]));
+    }
+
+    #[test]
+    fn parse_hash_output() {
+        assert!(
+            parse_args(args(&["--hash-output", "a.bf"]))
+                .unwrap()
+                .hash_output
+        );
+        assert!(
+            parse_args(args(&["--integrity", "a.bf"]))
+                .unwrap()
+                .integrity
+        );
+    }
+
+    #[test]
+    fn parse_rle_output() {
+        assert!(
+            parse_args(args(&["--rle-output", "a.bf"]))
+                .unwrap()
+                .rle_output
+        );
+    }
+
+    #[test]
+    fn parse_hexdump() {
+        assert!(parse_args(args(&["--hexdump", "a.bf"])).unwrap().hexdump);
+    }
+
+    #[test]
+    fn parse_merge_streams() {
+        assert!(
+            parse_args(args(&["--merge-streams", "a.bf"]))
+                .unwrap()
+                .merge_streams
+        );
+    }
+
+    #[test]
+    fn parse_grow_chunk() {
+        let options = parse_args(args(&["--grow-chunk", "4096", "a.bf"])).unwrap();
+        assert_eq!(options.grow_chunk, 4096);
+        assert!(parse_args(args(&["--grow-chunk", "big"])).is_err());
+    }
+
+    #[test]
+    fn parse_error_format() {
+        let options = parse_args(args(&["--error-format", "json", "a.bf"])).unwrap();
+        assert_eq!(options.error_format, ErrorFormat::Json);
+    }
+
+    #[test]
+    fn parse_cell_width_wrap() {
+        let options = parse_args(args(&["--cell-width", "8", "--wrap", "a.bf"])).unwrap();
+        assert_eq!(options.config.cell_size, CellWidth::Bits8);
+        assert_eq!(options.config.arith, ArithMode::Wrapping);
+        assert_eq!(options.config.cell_max(), 255);
+
+        assert!(matches!(
+            parse_args(args(&["--cell-width", "7", "a.bf"])),
+            Err(BrainfartError::InvalidValue(..))
+        ));
+    }
+
+    #[cfg(feature = "unbounded")]
+    #[test]
+    fn parse_unbounded_cells() {
+        let options = parse_args(args(&["--unbounded-cells", "a.bf"])).unwrap();
+        assert!(options.config.unbounded_cells);
+        assert_eq!(options.config.cell_max(), u128::MAX);
+    }
+
+    #[test]
+    fn parse_lint() {
+        assert!(parse_args(args(&["--lint", "a.bf"])).unwrap().lint);
+    }
+
+    #[test]
+    fn parse_minimize() {
+        assert!(parse_args(args(&["--minimize", "a.bf"])).unwrap().minimize);
+    }
+
+    #[test]
+    fn parse_estimate_cost() {
+        assert!(
+            parse_args(args(&["--estimate-cost", "a.bf"]))
+                .unwrap()
+                .estimate_cost
+        );
+    }
+
+    #[test]
+    fn parse_cost_table() {
+        let options = parse_args(args(&["--cost-table", "io=5,scan=3", "a.bf"])).unwrap();
+        assert_eq!(options.cost_table.io, 5);
+        assert_eq!(options.cost_table.scan, 3);
+        assert!(parse_args(args(&["--cost-table", "io"])).is_err());
+    }
+
+    #[test]
+    fn parse_tape_bound() {
+        assert!(
+            parse_args(args(&["--tape-bound", "a.bf"]))
+                .unwrap()
+                .tape_bound
+        );
+    }
+
+    #[test]
+    fn parse_bench() {
+        assert!(parse_args(args(&["--bench", "a.bf"])).unwrap().bench);
+    }
+
+    #[test]
+    fn parse_emit_llvm() {
+        assert!(
+            parse_args(args(&["--emit-llvm", "a.bf"]))
+                .unwrap()
+                .emit_llvm
+        );
+    }
+
+    #[test]
+    fn parse_stream() {
+        assert!(parse_args(args(&["--stream", "a.bf"])).unwrap().stream);
+        assert!(!parse_args(args(&["a.bf"])).unwrap().stream);
+    }
+
+    #[test]
+    fn parse_watch() {
+        assert!(parse_args(args(&["--watch", "a.bf"])).unwrap().watch);
+    }
+
+    #[test]
+    fn parse_exit_from_cell() {
+        assert!(
+            parse_args(args(&["--exit-from-cell"]))
+                .unwrap()
+                .exit_from_cell
+        );
+    }
+
+    #[test]
+    fn parse_start_index() {
+        let options = parse_args(args(&["--start-index", "5", "a.bf"])).unwrap();
+        assert_eq!(options.start_index, 5);
+        assert!(parse_args(args(&["--start-index", "-1"])).is_err());
+    }
+
+    #[test]
+    fn parse_commands() {
+        let options = parse_args(args(&["--commands", "rlpmoibe", "a.bf"])).unwrap();
+        assert_eq!(options.commands, "rlpmoibe".parse().unwrap());
+        assert!(parse_args(args(&["--commands", "rl"])).is_err());
+    }
+
+    #[test]
+    fn parse_lang() {
+        let options = parse_args(args(&["--lang", "twotape", "--commands", "rlpmoibe"])).unwrap();
+        assert_eq!(options.commands.lang(), Lang::TwoTape);
+        assert!(parse_args(args(&["--lang", "ook"])).is_err());
+    }
+
+    #[test]
+    fn parse_cells_from_env() {
+        let options = parse_args(args(&["--cells-from-env", "BF_INPUT", "a.bf"])).unwrap();
+        assert_eq!(options.cells_from_env, Some("BF_INPUT".to_string()));
+    }
+
+    #[test]
+    fn parse_argv() {
+        let options = parse_args(args(&["--argv", "hi there", "--argv-cell", "2"])).unwrap();
+        assert_eq!(options.argv, Some("hi there".to_string()));
+        assert_eq!(options.argv_cell, Some(2));
+    }
+
+    #[test]
+    fn parse_checkpoint() {
+        let options = parse_args(args(&[
+            "--checkpoint-every",
+            "1000",
+            "--checkpoint-file",
+            "run.ckpt",
+            "--resume",
+            "old.ckpt",
+            "a.bf",
+        ]))
+        .unwrap();
+        assert_eq!(options.checkpoint_every, Some(1000));
+        assert_eq!(options.checkpoint_file, Some("run.ckpt".to_string()));
+        assert_eq!(options.resume, Some("old.ckpt".to_string()));
+    }
+
+    #[test]
+    fn parse_deterministic() {
+        let options = parse_args(args(&[
+            "--invalid-output",
+            "error",
+            "--deterministic",
+            "--stream",
+            "a.bf",
+        ]))
+        .unwrap();
+        assert_eq!(options.config, Config::canonical());
+        assert!(!options.stream);
+    }
+
+    #[test]
+    fn parse_strict() {
+        let options = parse_args(args(&["--unbounded-cells", "--strict", "a.bf"])).unwrap();
+        assert_eq!(options.config, Config::strict());
+        assert_eq!(options.config.cell_max(), 255);
+        assert!(!options.stream);
+    }
+
+    #[test]
+    fn parse_sandbox() {
+        let options = parse_args(args(&["--max-steps", "50", "--sandbox", "a.bf"])).unwrap();
+        assert!(options.config.no_grow);
+        assert_eq!(
+            options.config.limits,
+            Limits {
+                steps: Some(50),
+                ..Limits::SANDBOX
+            }
+        );
+        assert!(options.no_io_extras);
+    }
+
+    #[test]
+    fn parse_no_io_extras() {
+        let options = parse_args(args(&["--no-io-extras", "a.bf"])).unwrap();
+        assert!(options.no_io_extras);
+        assert_eq!(options.io_extra(), None);
+
+        let options = parse_args(args(&["--resume", "a.ckpt", "--cells-from-env", "X"])).unwrap();
+        assert_eq!(options.io_extra(), Some("--resume"));
+    }
+
+    #[test]
+    fn parse_limits() {
+        let options = parse_args(args(&[
+            "--max-steps",
+            "10",
+            "--max-output",
+            "20",
+            "--timeout",
+            "3",
+            "--max-source-bytes",
+            "100",
+            "--max-tokens",
+            "40",
+            "--deterministic",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.config.limits,
+            Limits {
+                steps: Some(10),
+                output: Some(20),
+                time: Some(Duration::from_secs(3)),
+                source_bytes: Some(100),
+                tokens: Some(40),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_input_list() {
+        let options = parse_args(args(&["--input-list", "inputs.txt", "a.bf"])).unwrap();
+        assert_eq!(options.input_list, Some("inputs.txt".to_string()));
+        assert_eq!(options.filenames, vec!["a.bf".to_string()]);
+    }
+
+    #[test]
+    fn parse_dump_tape_json() {
+        assert!(
+            parse_args(args(&["--dump-tape-json"]))
+                .unwrap()
+                .dump_tape_json
+        );
+    }
+
+    #[test]
+    fn parse_profile() {
+        assert!(parse_args(args(&["--profile", "a.bf"])).unwrap().profile);
+    }
+
+    #[test]
+    fn parse_heatmap() {
+        assert!(parse_args(args(&["--heatmap", "a.bf"])).unwrap().heatmap);
+    }
+
+    #[test]
+    fn parse_coverage() {
+        assert!(parse_args(args(&["--coverage", "a.bf"])).unwrap().coverage);
+    }
+
+    #[test]
+    fn parse_trace() {
+        let options = parse_args(args(&["--trace", "a.bf"])).unwrap();
+        assert_eq!(options.trace, Some(TraceFormat::Text));
+        let options = parse_args(args(&["--trace-format", "csv", "a.bf"])).unwrap();
+        assert_eq!(options.trace, Some(TraceFormat::Csv));
+    }
+
+    #[test]
+    fn parse_progress() {
+        let options = parse_args(args(&["--progress", "a.bf"])).unwrap();
+        assert_eq!(options.progress, Some(PROGRESS_EVERY));
+        let options = parse_args(args(&["--progress-every", "50", "a.bf"])).unwrap();
+        assert_eq!(options.progress, Some(50));
+    }
+
+    #[test]
+    fn parse_chain() {
+        let options = parse_args(args(&["--chain", "a.bf", "b.bf"])).unwrap();
+        assert!(options.chain);
+    }
+
+    #[test]
+    fn parse_keep_going() {
+        assert!(
+            parse_args(args(&["--keep-going", "a.bf"]))
+                .unwrap()
+                .keep_going
+        );
+    }
+
+    #[test]
+    fn parse_eof_flag_cell() {
+        assert_eq!(parse_args(args(&[])).unwrap().config.eof_flag_cell, None);
+        let options = parse_args(args(&["--eof-flag-cell", "3"])).unwrap();
+        assert_eq!(options.config.eof_flag_cell, Some(3));
+    }
+
+    #[test]
+    fn parse_no_grow() {
+        let options = parse_args(args(&["--no-grow", "--tape-size", "10"])).unwrap();
+        assert!(options.config.no_grow);
+        assert_eq!(options.config.tape_size, 10);
+    }
+
+    #[test]
+    fn parse_warn_control_output() {
+        assert!(
+            parse_args(args(&["--warn-control-output"]))
+                .unwrap()
+                .config
+                .warn_control_output
+        );
+    }
+
+    #[test]
+    fn parse_warn_uninit() {
+        assert!(
+            parse_args(args(&["--warn-uninit"]))
+                .unwrap()
+                .config
+                .warn_uninit
+        );
+    }
+
+    #[test]
+    fn parse_detect_hangs() {
+        assert!(
+            parse_args(args(&["--detect-hangs"]))
+                .unwrap()
+                .config
+                .detect_hangs
+        );
+    }
+
+    #[test]
+    fn parse_readonly() {
+        let options = parse_args(args(&["--readonly-after", "4", "a.bf"])).unwrap();
+        assert_eq!(
+            options.config.readonly,
+            Some(ReadOnly {
+                below: 4,
+                from: ReadOnlyStart::FirstOutput,
+            })
+        );
+        let options =
+            parse_args(args(&["--readonly-from", "100", "--readonly-after", "4"])).unwrap();
+        assert_eq!(
+            options.config.readonly.unwrap().from,
+            ReadOnlyStart::Step(100)
+        );
+        assert!(parse_args(args(&["--readonly-from", "later"])).is_err());
+    }
+
+    #[test]
+    fn parse_output_delay() {
+        let options = parse_args(args(&["--delay-per-line", "--output-delay", "20"])).unwrap();
+        assert_eq!(
+            options.config.output_delay,
+            Some(OutputDelay {
+                duration: Duration::from_millis(20),
+                per_line: true,
+            })
+        );
+        let options = parse_args(args(&["--delay-per-line", "a.bf"])).unwrap();
+        assert_eq!(options.config.output_delay, None);
+    }
+
+    #[test]
+    fn parse_max_tape_report() {
+        assert_eq!(parse_args(args(&[])).unwrap().config.tape_report, 30000);
+        let options = parse_args(args(&["--max-tape-report", "100"])).unwrap();
+        assert_eq!(options.config.tape_report, 100);
+    }
+
+    #[test]
+    fn parse_invalid_output() {
+        let options = parse_args(args(&["--invalid-output", "replace", "a.bf"])).unwrap();
+        assert_eq!(options.config.invalid_output, InvalidOutput::Replace);
+        assert_eq!(options.filenames, args(&["a.bf"]));
+    }
+
+    #[test]
+    fn parse_invalid_value() {
+        match parse_args(args(&["--invalid-output", "loud"])) {
+            Err(BrainfartError::InvalidValue(flag, value)) => {
+                assert_eq!(
+                    (flag.as_str(), value.as_str()),
+                    ("--invalid-output", "loud")
+                )
+            }
+            other => panic!("parse_invalid_value had result {:?}", other),
+        }
+    }
+}
@@ -1,31 +1,221 @@
-use std::slice::Iter;
-
+use crate::analysis::linear_effect;
+use crate::config::{ArithMode, Config};
 use crate::error::{BrainfartError, BrainfartResult};
 use crate::expr::{Expr, ExprType, LoopBlock};
-use crate::token::{Token, TokenType};
+use crate::memo::{LinearMemo, LinearTargets};
+use crate::token::{Token, TokenRun, TokenType};
+
+/// ParseOptions choose which loop idioms the parser folds into a single Expr, whether loops that
+/// can never be entered are dropped, and whether `-` cancels `+`. Other consecutive instructions
+/// are always merged. By default every fold is made, every such loop is dropped and `-` cancels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Whether `[-]` becomes a Set(0)
+    pub fold_clear: bool,
+    /// Whether `[>]` and `[<]`, with any step, become a ScanRight or ScanLeft
+    pub fold_scans: bool,
+    /// Whether loops such as `[->+<]` become a LinearLoop
+    pub fold_linear_loops: bool,
+    /// Whether a loop directly following an Expr that always leaves the current cell zero, such as
+    /// another loop, is dropped
+    pub drop_dead_loops: bool,
+    /// Whether a `-` directly after `+`s cancels one of them, and one directly after a Set lowers
+    /// it, rather than starting a new Sub. Cancelling hides any overflow the `+`s reach before
+    /// the `-` brings the cell back down.
+    pub cancel_changes: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            fold_clear: true,
+            fold_scans: true,
+            fold_linear_loops: true,
+            drop_dead_loops: true,
+            cancel_changes: true,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// The ParseOptions for running with the given Config: every fold is made, but `-` only
+    /// cancels `+` when cells wrap around, so that an overflow on the way is still reported or
+    /// saturated at when it happens.
+    pub fn for_config(config: &Config) -> Self {
+        ParseOptions {
+            cancel_changes: config.arith == ArithMode::Wrapping,
+            ..ParseOptions::default()
+        }
+    }
+}
 
 /// Parse tokens produced by the lexer to produce a vector of Exprs.
 pub fn parse_tokens(tokens: Vec<Token>) -> BrainfartResult<Vec<Expr>> {
+    parse_tokens_with(tokens, &ParseOptions::default())
+}
+
+/// Parse tokens produced by the lexer to produce a vector of Exprs, folding only the loop idioms
+/// the given ParseOptions allow.
+pub fn parse_tokens_with(tokens: Vec<Token>, options: &ParseOptions) -> BrainfartResult<Vec<Expr>> {
+    parse_token_iter(tokens.into_iter(), options)
+}
+
+/// Parse TokenRuns produced by `lex_runs`, expanding each run only as it is parsed. The result is
+/// the same as parsing the expanded Tokens with `parse_tokens`.
+pub fn parse_token_runs(runs: Vec<TokenRun>) -> BrainfartResult<Vec<Expr>> {
+    parse_token_runs_with(runs, &ParseOptions::default())
+}
+
+/// Parse TokenRuns produced by `lex_runs` as `parse_token_runs` does, folding only the loop idioms
+/// the given ParseOptions allow.
+pub fn parse_token_runs_with(
+    runs: Vec<TokenRun>,
+    options: &ParseOptions,
+) -> BrainfartResult<Vec<Expr>> {
+    parse_token_iter(runs.into_iter().flat_map(TokenRun::tokens), options)
+}
+
+/// Parse Tokens appended to a program that has already been parsed into the given Exprs, such as a
+/// new line typed into an interactive session, without parsing the earlier Tokens again. The new
+/// Tokens are folded into the last existing Expr where they can be, so `+++` followed by `+++`
+/// gives the same single `Add(6)` as parsing them together.
+pub fn parse_tokens_onto(exprs: &mut Vec<Expr>, tokens: Vec<Token>) -> BrainfartResult<()> {
+    parse_token_iter_onto(exprs, tokens.into_iter(), &ParseOptions::default())
+}
+
+/// Parse the given Tokens to produce a vector of Exprs.
+fn parse_token_iter<I: Iterator<Item = Token>>(
+    tokens: I,
+    options: &ParseOptions,
+) -> BrainfartResult<Vec<Expr>> {
     let mut exprs: Vec<Expr> = vec![];
-    let mut tokens_iter = tokens.iter();
+    parse_token_iter_onto(&mut exprs, tokens, options)?;
+    Ok(exprs)
+}
 
-    while let Some(token) = tokens_iter.next() {
+/// Parse the given Tokens onto the end of the given Exprs.
+fn parse_token_iter_onto<I: Iterator<Item = Token>>(
+    exprs: &mut Vec<Expr>,
+    mut tokens: I,
+    options: &ParseOptions,
+) -> BrainfartResult<()> {
+    let mut memo = LinearMemo::default();
+    while let Some(token) = tokens.next() {
         match token.ty {
-            TokenType::PointInc => parse_point_inc(&mut exprs, *token),
-            TokenType::PointDec => parse_point_dec(&mut exprs, *token),
-            TokenType::ValInc => parse_val_inc(&mut exprs, *token),
-            TokenType::ValDec => parse_val_dec(&mut exprs, *token)?,
-            TokenType::Output => parse_output(&mut exprs, *token),
-            TokenType::Input => parse_input(&mut exprs, *token),
-            TokenType::IfZero => parse_loop_block(&mut exprs, &mut tokens_iter)?,
+            TokenType::PointInc => parse_point_inc(exprs, token),
+            TokenType::PointDec => parse_point_dec(exprs, token),
+            TokenType::ValInc => parse_val_inc(exprs, token),
+            TokenType::ValDec => parse_val_dec_with(exprs, token, options)?,
+            TokenType::Output => parse_output(exprs, token),
+            TokenType::Input => parse_input(exprs, token),
+            TokenType::TapeDec => parse_switch_tape(exprs, token, 0),
+            TokenType::TapeInc => parse_switch_tape(exprs, token, 1),
+            TokenType::IfZero => parse_loop_block(exprs, token, &mut tokens, options, &mut memo)?,
             TokenType::IfNonZero => (),
         }
     }
 
-    Ok(exprs)
+    Ok(())
+}
+
+/// An ExprStream parses Tokens into top-level Exprs as the Tokens arrive, yielding each Expr once no
+/// later Token can change it. Consecutive instructions are still folded, but an Expr that has been
+/// yielded is never folded with later instructions. A loop is yielded only after its closing
+/// bracket has been read. The stream ends after yielding an error.
+pub struct ExprStream<I: Iterator<Item = BrainfartResult<Token>>> {
+    tokens: I,
+    pending: Vec<Expr>,
+    memo: LinearMemo,
+    options: ParseOptions,
+}
+
+impl<I: Iterator<Item = BrainfartResult<Token>>> ExprStream<I> {
+    /// Produce an ExprStream parsing the given Tokens.
+    pub fn new(tokens: I) -> Self {
+        ExprStream::with_options(tokens, ParseOptions::default())
+    }
+
+    /// Produce an ExprStream parsing the given Tokens with the given ParseOptions.
+    pub fn with_options(tokens: I, options: ParseOptions) -> Self {
+        ExprStream {
+            tokens,
+            pending: vec![],
+            memo: LinearMemo::default(),
+            options,
+        }
+    }
+
+    /// Parse a single Token into the pending Exprs, reading a whole loop if it opens one.
+    fn parse_token(&mut self, token: Token) -> BrainfartResult<()> {
+        match token.ty {
+            TokenType::PointInc => parse_point_inc(&mut self.pending, token),
+            TokenType::PointDec => parse_point_dec(&mut self.pending, token),
+            TokenType::ValInc => parse_val_inc(&mut self.pending, token),
+            TokenType::ValDec => parse_val_dec_with(&mut self.pending, token, &self.options)?,
+            TokenType::Output => parse_output(&mut self.pending, token),
+            TokenType::Input => parse_input(&mut self.pending, token),
+            TokenType::TapeDec => parse_switch_tape(&mut self.pending, token, 0),
+            TokenType::TapeInc => parse_switch_tape(&mut self.pending, token, 1),
+            TokenType::IfZero => {
+                let body = self.read_loop_body()?;
+                parse_loop_block(
+                    &mut self.pending,
+                    token,
+                    &mut body.into_iter(),
+                    &self.options,
+                    &mut self.memo,
+                )?
+            }
+            TokenType::IfNonZero => (),
+        }
+        Ok(())
+    }
+
+    /// Read the Tokens following an IfZero up to and including its matching IfNonZero.
+    fn read_loop_body(&mut self) -> BrainfartResult<Vec<Token>> {
+        let mut body: Vec<Token> = vec![];
+        let mut depth: u32 = 1;
+
+        while depth > 0 {
+            let token = self
+                .tokens
+                .next()
+                .ok_or(BrainfartError::UnmatchedOpenBracket)??;
+            match token.ty {
+                TokenType::IfZero => depth += 1,
+                TokenType::IfNonZero => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+
+        Ok(body)
+    }
 }
 
-/// Given a Token of type PointInc, add to the vector of Exprs.
+impl<I: Iterator<Item = BrainfartResult<Token>>> Iterator for ExprStream<I> {
+    type Item = BrainfartResult<Expr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.len() < 2 {
+            let result = match self.tokens.next() {
+                Some(Ok(token)) => self.parse_token(token),
+                Some(Err(e)) => Err(e),
+                None => return self.pending.pop().map(Ok),
+            };
+
+            if let Err(e) = result {
+                self.pending.clear();
+                return Some(Err(e));
+            }
+        }
+
+        Some(Ok(self.pending.remove(0)))
+    }
+}
+
+/// Given a Token of type PointInc, add to the vector of Exprs. As with every instruction, a new Expr
+/// is started instead of folding into the previous one once its count has reached u32::MAX.
 fn parse_point_inc(exprs: &mut Vec<Expr>, token: Token) {
     if exprs.is_empty() {
         push_new_move_right(exprs, token);
@@ -33,12 +223,14 @@ fn parse_point_inc(exprs: &mut Vec<Expr>, token: Token) {
         let last_index: usize = &exprs.len() - 1;
         let prev: &mut Expr = &mut exprs[last_index];
 
-        if let ExprType::MoveRight(x) = prev.ty {
-            prev.ty = ExprType::MoveRight(x + 1);
-            prev.tokens.push(token);
-        } else {
-            push_new_move_right(exprs, token);
+        match prev.ty {
+            ExprType::MoveRight(x) if x < u32::MAX => {
+                prev.ty = ExprType::MoveRight(x + 1);
+                prev.tokens.push(token);
+            }
+            _ => push_new_move_right(exprs, token),
         }
+        fuse_move_io(exprs);
     }
 }
 
@@ -60,15 +252,41 @@ fn parse_point_dec(exprs: &mut Vec<Expr>, token: Token) {
                     prev.tokens.pop();
                 }
             }
-            ExprType::MoveLeft(x) => {
+            ExprType::MoveLeft(x) if *x < u32::MAX => {
                 prev.ty = ExprType::MoveLeft(x + 1);
                 prev.tokens.push(token);
             }
             _ => push_new_move_left(exprs, token),
         }
+        fuse_move_io(exprs);
     }
 }
 
+/// Fuse a move, a single output or input and a move back to where it started at the end of the
+/// given Exprs, such as `>.<`, into an OutputAt or InputAt, so the pointer is not moved there and
+/// back.
+fn fuse_move_io(exprs: &mut Vec<Expr>) {
+    let [.., there, io, back] = exprs.as_slice() else {
+        return;
+    };
+    let offset = match (&there.ty, &back.ty) {
+        (ExprType::MoveRight(x), ExprType::MoveLeft(y)) if x == y => *x as i64,
+        (ExprType::MoveLeft(x), ExprType::MoveRight(y)) if x == y => -(*x as i64),
+        _ => return,
+    };
+    let ty = match io.ty {
+        ExprType::Output(1) => ExprType::OutputAt { offset },
+        ExprType::Input(1) => ExprType::InputAt { offset },
+        _ => return,
+    };
+
+    let tokens = exprs
+        .drain(exprs.len() - 3..)
+        .flat_map(|expr| expr.tokens)
+        .collect();
+    exprs.push(Expr { ty, tokens });
+}
+
 /// Given a Token of type ValInc, add to the vector of Exprs.
 fn parse_val_inc(exprs: &mut Vec<Expr>, token: Token) {
     if exprs.is_empty() {
@@ -79,11 +297,11 @@ fn parse_val_inc(exprs: &mut Vec<Expr>, token: Token) {
         let prev_type: &ExprType = &prev.ty;
 
         match prev_type {
-            ExprType::Add(x) => {
+            ExprType::Add(x) if *x < u32::MAX => {
                 prev.ty = ExprType::Add(x + 1);
                 prev.tokens.push(token);
             }
-            ExprType::Set(x) => {
+            ExprType::Set(x) if *x < u32::MAX => {
                 prev.ty = ExprType::Set(x + 1);
                 prev.tokens.push(token);
             }
@@ -111,15 +329,11 @@ fn parse_val_dec(exprs: &mut Vec<Expr>, token: Token) -> BrainfartResult<()> {
                     prev.tokens.pop();
                 }
             }
-            ExprType::Sub(x) => {
+            ExprType::Sub(x) if *x < u32::MAX => {
                 prev.ty = ExprType::Sub(x + 1);
                 prev.tokens.push(token);
             }
-            ExprType::Set(x) => {
-                if x == &0 {
-                    return Err(BrainfartError::ValZeroDec(token));
-                }
-
+            ExprType::Set(x) if *x > 0 => {
                 prev.ty = ExprType::Set(x - 1);
                 prev.tokens.push(token);
             }
@@ -130,6 +344,23 @@ fn parse_val_dec(exprs: &mut Vec<Expr>, token: Token) -> BrainfartResult<()> {
     }
 }
 
+/// Given a Token of type ValDec, add to the vector of Exprs as `parse_val_dec` does, except that it
+/// starts a new Sub after an Add or Set if the given ParseOptions do not let it cancel them.
+fn parse_val_dec_with(
+    exprs: &mut Vec<Expr>,
+    token: Token,
+    options: &ParseOptions,
+) -> BrainfartResult<()> {
+    let cancels = exprs
+        .last()
+        .is_some_and(|prev| matches!(prev.ty, ExprType::Add(_) | ExprType::Set(_)));
+    if cancels && !options.cancel_changes {
+        push_new_sub(exprs, token);
+        return Ok(());
+    }
+    parse_val_dec(exprs, token)
+}
+
 /// Given a Token of type Output, add to the vector of Exprs.
 fn parse_output(exprs: &mut Vec<Expr>, token: Token) {
     if exprs.is_empty() {
@@ -139,7 +370,7 @@ fn parse_output(exprs: &mut Vec<Expr>, token: Token) {
         let prev: &mut Expr = &mut exprs[last_index];
         let prev_type: &ExprType = &prev.ty;
         match prev_type {
-            ExprType::Output(x) => {
+            ExprType::Output(x) if *x < u32::MAX => {
                 prev.ty = ExprType::Output(x + 1);
                 prev.tokens.push(token);
             }
@@ -157,7 +388,7 @@ fn parse_input(exprs: &mut Vec<Expr>, token: Token) {
         let prev: &mut Expr = &mut exprs[last_index];
         let prev_type: &ExprType = &prev.ty;
         match prev_type {
-            ExprType::Input(x) => {
+            ExprType::Input(x) if *x < u32::MAX => {
                 prev.ty = ExprType::Input(x + 1);
                 prev.tokens.push(token);
             }
@@ -166,46 +397,175 @@ fn parse_input(exprs: &mut Vec<Expr>, token: Token) {
     }
 }
 
-/// Given a Token of type IfZero, parse a LoopBlock and add to the vector of Exprs.
-fn parse_loop_block(exprs: &mut Vec<Expr>, tokens: &mut Iter<'_, Token>) -> BrainfartResult<()> {
-    let mut lb_exprs: Vec<Expr> = vec![];
+/// Given a Token of type TapeDec or TapeInc, switching to the tape with the given index, add to the
+/// vector of Exprs. Consecutive switches fold into the last one.
+fn parse_switch_tape(exprs: &mut Vec<Expr>, token: Token, tape: usize) {
+    match exprs.last_mut() {
+        Some(prev) if matches!(prev.ty, ExprType::SwitchTape(_)) => {
+            prev.ty = ExprType::SwitchTape(tape);
+            prev.tokens.push(token);
+        }
+        _ => push_new_expr(exprs, ExprType::SwitchTape(tape), token),
+    }
+}
+
+/// Given a Token of type IfZero, parse a LoopBlock and add to the vector of Exprs. The IfZero token
+/// is stored on the resulting Expr so that diagnostics can refer to the loop's position. Nested
+/// loops are tracked with an explicit stack of the loops still open, so deeply nested loops do not
+/// grow the call stack. The targets of linear loops are cached in the given LinearMemo.
+fn parse_loop_block<I: Iterator<Item = Token>>(
+    exprs: &mut Vec<Expr>,
+    open: Token,
+    tokens: &mut I,
+    options: &ParseOptions,
+    memo: &mut LinearMemo,
+) -> BrainfartResult<()> {
+    let mut open_blocks: Vec<(Token, Vec<Expr>)> = vec![(open, vec![])];
 
-    while let Some(token) = tokens.next() {
+    for token in tokens.by_ref() {
+        let (_, lb_exprs) = open_blocks.last_mut().unwrap();
         match token.ty {
-            TokenType::PointInc => parse_point_inc(&mut lb_exprs, *token),
-            TokenType::PointDec => parse_point_dec(&mut lb_exprs, *token),
-            TokenType::ValInc => parse_val_inc(&mut lb_exprs, *token),
-            TokenType::ValDec => parse_val_dec(&mut lb_exprs, *token)?,
-            TokenType::Output => parse_output(&mut lb_exprs, *token),
-            TokenType::Input => parse_input(&mut lb_exprs, *token),
-            TokenType::IfZero => parse_loop_block(&mut lb_exprs, tokens)?,
+            TokenType::PointInc => parse_point_inc(lb_exprs, token),
+            TokenType::PointDec => parse_point_dec(lb_exprs, token),
+            TokenType::ValInc => parse_val_inc(lb_exprs, token),
+            TokenType::ValDec => parse_val_dec_with(lb_exprs, token, options)?,
+            TokenType::Output => parse_output(lb_exprs, token),
+            TokenType::Input => parse_input(lb_exprs, token),
+            TokenType::TapeDec => parse_switch_tape(lb_exprs, token, 0),
+            TokenType::TapeInc => parse_switch_tape(lb_exprs, token, 1),
+            TokenType::IfZero => open_blocks.push((token, vec![])),
             TokenType::IfNonZero => {
-                if lb_exprs.len() == 1 {
-                    let expr: &Expr = &lb_exprs[0];
-                    if let ExprType::Sub(1) = expr.ty {
-                        let expr_token: Token = expr.tokens[0];
-                        let set_expr = Expr {
-                            ty: ExprType::Set(0),
-                            tokens: vec![expr_token],
-                        };
-                        exprs.push(set_expr);
+                let (open, lb_exprs) = open_blocks.pop().unwrap();
+                let expr = fold_loop_block(&lb_exprs, options, memo)
+                    .unwrap_or_else(|| loop_block(open, Some(token), lb_exprs));
+                match open_blocks.last_mut() {
+                    Some((_, parent_exprs)) => push_loop(parent_exprs, expr, options),
+                    None => {
+                        push_loop(exprs, expr, options);
                         return Ok(());
                     }
                 }
-                break;
             }
         }
     }
 
-    let loop_block = LoopBlock { exprs: lb_exprs };
+    while let Some((open, lb_exprs)) = open_blocks.pop() {
+        let expr = loop_block(open, None, lb_exprs);
+        match open_blocks.last_mut() {
+            Some((_, parent_exprs)) => parent_exprs.push(expr),
+            None => exprs.push(expr),
+        }
+    }
+
+    Ok(())
+}
+
+/// Add a parsed loop to the given Exprs, unless it directly follows an Expr that always leaves the
+/// current cell zero, such as another loop. The loop could then never be entered, so it is dropped
+/// if the given ParseOptions allow it.
+fn push_loop(exprs: &mut Vec<Expr>, expr: Expr, options: &ParseOptions) {
+    let follows_zero = options.drop_dead_loops
+        && exprs.last().is_some_and(|prev| {
+            matches!(
+                prev.ty,
+                ExprType::Set(0)
+                    | ExprType::ScanRight(_)
+                    | ExprType::ScanLeft(_)
+                    | ExprType::LinearLoop(_)
+                    | ExprType::MoveCell { .. }
+                    | ExprType::LoopBlock(_)
+            )
+        });
+    if !follows_zero {
+        exprs.push(expr);
+    }
+}
+
+/// Produce a LoopBlock Expr between the given IfZero and IfNonZero tokens, containing the given
+/// Exprs.
+fn loop_block(open: Token, close: Option<Token>, lb_exprs: Vec<Expr>) -> Expr {
+    let loop_block = LoopBlock {
+        exprs: lb_exprs,
+        open,
+        close,
+    };
     let boxed_loop_block = Box::new(loop_block);
-    let loop_block_expr = Expr {
+    Expr {
         ty: ExprType::LoopBlock(boxed_loop_block),
-        tokens: vec![],
+        tokens: vec![open],
+    }
+}
+
+/// Fold a LoopBlock whose body matches a known idiom into one Expr: `[-]` sets the cell to zero,
+/// `[>]` or `[<]` with any step scans for a zero cell, and a body that decrements the current cell
+/// once while adding to or subtracting from others, such as `[->+>+<<]`, becomes a LinearLoop, or a
+/// MoveCell if it adds to a single other cell once, such as `[->+<]`. The folded Expr keeps the
+/// body's tokens. Only the folds the given ParseOptions allow are made.
+///
+/// Folding `[-]` assumes the loop ends by counting the cell down to zero. Cells are unsigned, so
+/// this holds from any value under every ArithMode. A signed cell mode would have to turn off
+/// `fold_clear`, since `[-]` would never end on a negative cell.
+fn fold_loop_block(
+    lb_exprs: &[Expr],
+    options: &ParseOptions,
+    memo: &mut LinearMemo,
+) -> Option<Expr> {
+    if lb_exprs.len() != 1 {
+        return options
+            .fold_linear_loops
+            .then(|| fold_linear_loop(lb_exprs, memo))
+            .flatten();
+    }
+
+    let expr: &Expr = &lb_exprs[0];
+    let ty = match expr.ty {
+        ExprType::Sub(1) if options.fold_clear => ExprType::Set(0),
+        ExprType::MoveRight(step) if options.fold_scans => ExprType::ScanRight(step),
+        ExprType::MoveLeft(step) if options.fold_scans => ExprType::ScanLeft(step),
+        _ => return None,
     };
-    exprs.push(loop_block_expr);
 
-    Ok(())
+    Some(Expr {
+        ty,
+        tokens: expr.tokens.clone(),
+    })
+}
+
+/// Fold a loop body that only adds, subtracts and moves into a LinearLoop, if it ends where it
+/// started and decrements the starting cell by exactly one per iteration. The cells it adds to may
+/// be on either side, such as the left neighbor in `[-<+>]`. A body that ends on another cell, such
+/// as `[-<+>>]`, tests a different cell each iteration, so it is left as a loop. A loop that only
+/// moves the value to one other cell becomes a MoveCell instead. Identical bodies share the targets
+/// cached in the given LinearMemo.
+fn fold_linear_loop(lb_exprs: &[Expr], memo: &mut LinearMemo) -> Option<Expr> {
+    let targets = memo.get_or_compute(lb_exprs, linear_targets)?;
+    let ty = match targets[..] {
+        [(offset, 1)] => ExprType::MoveCell { offset },
+        _ => ExprType::LinearLoop(targets),
+    };
+    Some(Expr {
+        ty,
+        tokens: lb_exprs
+            .iter()
+            .flat_map(|expr| expr.tokens.clone())
+            .collect(),
+    })
+}
+
+/// The LinearLoop targets of a loop body, if it is a linear loop as `fold_linear_loop` describes.
+fn linear_targets(lb_exprs: &[Expr]) -> LinearTargets {
+    let effect = linear_effect(lb_exprs)?;
+    if effect.shift != 0 || effect.deltas.get(&0) != Some(&-1) {
+        return None;
+    }
+
+    Some(
+        effect
+            .deltas
+            .into_iter()
+            .filter(|&(offset, _)| offset != 0)
+            .collect(),
+    )
 }
 
 /// Push a new Expr with the given ExprType containing the given token.
@@ -249,11 +609,14 @@ fn push_new_input(exprs: &mut Vec<Expr>, token: Token) {
 
 #[cfg(test)]
 mod tests {
-    use crate::error::BrainfartResult;
+    use crate::error::{BrainfartError, BrainfartResult};
     use crate::expr::{Expr, ExprType, LoopBlock};
+    use crate::lexer::{lex_runs, lex_runs_with, lex_string, CommandSet, Lang, Lexer};
+    use crate::memo::LinearMemo;
     use crate::parser::{
         parse_input, parse_loop_block, parse_output, parse_point_dec, parse_point_inc,
-        parse_tokens, parse_val_dec, parse_val_inc,
+        parse_token_runs, parse_tokens, parse_tokens_onto, parse_tokens_with, parse_val_dec,
+        parse_val_inc, ExprStream, ParseOptions,
     };
     use crate::token::{Token, TokenType};
 
@@ -855,7 +1218,7 @@ mod tests {
                 col: 1,
             }],
         }];
-        let _already_consumed_token: Token = Token {
+        let open_token: Token = Token {
             ty: TokenType::IfZero,
             line: 1,
             col: 2,
@@ -872,9 +1235,15 @@ mod tests {
                 col: 4,
             },
         ];
-        let mut tokens_iter = tokens.iter();
+        let mut tokens_iter = tokens.into_iter();
 
-        if let Err(e) = parse_loop_block(&mut exprs, &mut tokens_iter) {
+        if let Err(e) = parse_loop_block(
+            &mut exprs,
+            open_token,
+            &mut tokens_iter,
+            &ParseOptions::default(),
+            &mut LinearMemo::default(),
+        ) {
             panic!("{}", e);
         }
 
@@ -890,17 +1259,12 @@ mod tests {
                     }]
                 },
                 Expr {
-                    ty: ExprType::LoopBlock(Box::new(LoopBlock {
-                        exprs: vec![Expr {
-                            ty: ExprType::MoveRight(1),
-                            tokens: vec![Token {
-                                ty: TokenType::PointInc,
-                                line: 1,
-                                col: 3,
-                            }],
-                        }],
-                    })),
-                    tokens: vec![],
+                    ty: ExprType::ScanRight(1),
+                    tokens: vec![Token {
+                        ty: TokenType::PointInc,
+                        line: 1,
+                        col: 3,
+                    }],
                 }
             ]
         );
@@ -1026,17 +1390,12 @@ mod tests {
                     exprs,
                     vec![
                         Expr {
-                            ty: ExprType::LoopBlock(Box::new(LoopBlock {
-                                exprs: vec![Expr {
-                                    ty: ExprType::MoveLeft(1),
-                                    tokens: vec![Token {
-                                        ty: TokenType::PointDec,
-                                        line: 1,
-                                        col: 2,
-                                    }],
-                                }],
-                            })),
-                            tokens: vec![],
+                            ty: ExprType::ScanLeft(1),
+                            tokens: vec![Token {
+                                ty: TokenType::PointDec,
+                                line: 1,
+                                col: 2,
+                            }],
                         },
                         Expr {
                             ty: ExprType::MoveLeft(1),
@@ -1054,7 +1413,7 @@ mod tests {
     }
 
     #[test]
-    // LoopBlock MoveRight case [<]
+    // LoopBlock MoveRight case [>]
     fn parse_lb_mvr() {
         let tokens = vec![
             Token {
@@ -1085,17 +1444,12 @@ mod tests {
                     exprs,
                     vec![
                         Expr {
-                            ty: ExprType::LoopBlock(Box::new(LoopBlock {
-                                exprs: vec![Expr {
-                                    ty: ExprType::MoveRight(1),
-                                    tokens: vec![Token {
-                                        ty: TokenType::PointInc,
-                                        line: 1,
-                                        col: 2,
-                                    }],
-                                }],
-                            })),
-                            tokens: vec![],
+                            ty: ExprType::ScanRight(1),
+                            tokens: vec![Token {
+                                ty: TokenType::PointInc,
+                                line: 1,
+                                col: 2,
+                            }],
                         },
                         Expr {
                             ty: ExprType::MoveRight(1),
@@ -1111,4 +1465,344 @@ mod tests {
             Err(e) => panic!("{}", e),
         }
     }
+
+    #[test]
+    fn expr_stream_matches_parse() {
+        let source = "++>-<<[->+<]>[-]+..,";
+        let streamed: Vec<Expr> = ExprStream::new(Lexer::new(source.chars()))
+            .collect::<BrainfartResult<_>>()
+            .unwrap();
+        let parsed = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+        assert_eq!(streamed, parsed);
+    }
+
+    #[test]
+    fn expr_stream_yields_before_end() {
+        let mut stream = ExprStream::new(Lexer::new("+.[-]".chars()));
+        let first = stream.next().unwrap().unwrap();
+        assert_eq!(first.ty, ExprType::Add(1));
+        assert!(stream.next().unwrap().is_ok());
+        assert_eq!(stream.next().unwrap().unwrap().ty, ExprType::Set(0));
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn expr_stream_unmatched_open() {
+        let results: Vec<_> = ExprStream::new(Lexer::new("+[+".chars())).collect();
+        assert!(matches!(
+            results.last(),
+            Some(Err(BrainfartError::UnmatchedOpenBracket))
+        ));
+    }
+
+    #[test]
+    fn parse_scan_step() {
+        let exprs = parse_tokens(lex_string("[>>]>[<<<]".to_string()).unwrap()).unwrap();
+        let types: Vec<ExprType> = exprs.into_iter().map(|expr| expr.ty).collect();
+        assert_eq!(
+            types,
+            vec![
+                ExprType::ScanRight(2),
+                ExprType::MoveRight(1),
+                ExprType::ScanLeft(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_many_linear_loops() {
+        let exprs = parse_tokens(lex_string("+[->++>+++<<]>".repeat(5000)).unwrap()).unwrap();
+        let loops: Vec<&ExprType> = exprs
+            .iter()
+            .map(|expr| &expr.ty)
+            .filter(|ty| matches!(ty, ExprType::LinearLoop(_)))
+            .collect();
+        assert_eq!(loops.len(), 5000);
+        assert!(loops
+            .iter()
+            .all(|&ty| ty == &ExprType::LinearLoop(vec![(1, 2), (2, 3)])));
+    }
+
+    #[test]
+    fn parse_with_options() {
+        let tokens = lex_string("[-]".to_string()).unwrap();
+        let sub = Expr {
+            ty: ExprType::Sub(1),
+            tokens: vec![Token::from(TokenType::ValDec, 1, 2)],
+        };
+        assert_eq!(
+            parse_tokens_with(tokens.clone(), &ParseOptions::default()).unwrap(),
+            vec![Expr {
+                ty: ExprType::Set(0),
+                tokens: sub.tokens.clone(),
+            }]
+        );
+
+        let no_clear = ParseOptions {
+            fold_clear: false,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            parse_tokens_with(tokens, &no_clear).unwrap(),
+            vec![Expr {
+                ty: ExprType::LoopBlock(Box::new(LoopBlock {
+                    exprs: vec![sub],
+                    open: Token::from(TokenType::IfZero, 1, 1),
+                    close: Some(Token::from(TokenType::IfNonZero, 1, 3)),
+                })),
+                tokens: vec![Token::from(TokenType::IfZero, 1, 1)],
+            }]
+        );
+
+        let nothing = ParseOptions {
+            fold_clear: false,
+            fold_scans: false,
+            fold_linear_loops: false,
+            drop_dead_loops: true,
+            cancel_changes: true,
+        };
+        let exprs = parse_tokens_with(lex_string("[>]>[->+<]".to_string()).unwrap(), &nothing);
+        let loops = exprs
+            .unwrap()
+            .iter()
+            .filter(|expr| matches!(expr.ty, ExprType::LoopBlock(_)))
+            .count();
+        assert_eq!(loops, 2);
+    }
+
+    #[test]
+    fn parse_scan_not_folded() {
+        let exprs = parse_tokens(lex_string("[>+]".to_string()).unwrap()).unwrap();
+        assert_eq!(
+            exprs,
+            vec![Expr {
+                ty: ExprType::LoopBlock(Box::new(LoopBlock {
+                    exprs: vec![
+                        Expr {
+                            ty: ExprType::MoveRight(1),
+                            tokens: vec![Token::from(TokenType::PointInc, 1, 2)],
+                        },
+                        Expr {
+                            ty: ExprType::Add(1),
+                            tokens: vec![Token::from(TokenType::ValInc, 1, 3)],
+                        },
+                    ],
+                    open: Token::from(TokenType::IfZero, 1, 1),
+                    close: Some(Token::from(TokenType::IfNonZero, 1, 4)),
+                })),
+                tokens: vec![Token::from(TokenType::IfZero, 1, 1)],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_token_runs_same_exprs() {
+        let source = format!("{}[->+<]>\n{}<-.", "+".repeat(1000), "-".repeat(3));
+        let runs = lex_runs(source.clone()).unwrap();
+        assert_eq!(runs[0].count, 1000);
+        assert_eq!(
+            parse_token_runs(runs).unwrap(),
+            parse_tokens(lex_string(source).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_linear_loop() {
+        let exprs = parse_tokens(lex_string("[->+>+<<]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs.len(), 1);
+        assert_eq!(exprs[0].ty, ExprType::LinearLoop(vec![(1, 1), (2, 1)]));
+        assert_eq!(exprs[0].tokens.len(), 7);
+
+        let exprs = parse_tokens(lex_string("[-<<--->+<+>>]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs[0].ty, ExprType::LinearLoop(vec![(-2, -2), (-1, 1)]));
+    }
+
+    #[test]
+    fn parse_move_cell() {
+        let exprs = parse_tokens(lex_string("[->+<]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs.len(), 1);
+        assert_eq!(exprs[0].ty, ExprType::MoveCell { offset: 1 });
+        assert_eq!(exprs[0].tokens.len(), 4);
+
+        let exprs = parse_tokens(lex_string("[<<<+>>>-]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs[0].ty, ExprType::MoveCell { offset: -3 });
+
+        let exprs = parse_tokens(lex_string("[->++<]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs[0].ty, ExprType::LinearLoop(vec![(1, 2)]));
+    }
+
+    #[test]
+    fn parse_linear_loop_not_folded() {
+        for source in ["[--]", "[->+]", "[+>-<]", "[->+<.]"] {
+            let exprs = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+            assert!(
+                matches!(exprs[0].ty, ExprType::LoopBlock(_)),
+                "{} was folded",
+                source
+            );
+        }
+    }
+
+    /// Parse two more tokens of the given type onto an Expr whose count is one below u32::MAX, and
+    /// check that the second starts a new Expr.
+    fn assert_count_limit(start: ExprType, ty: TokenType, parse: fn(&mut Vec<Expr>, Token)) {
+        let token = Token::from(ty, 1, 1);
+        let mut exprs: Vec<Expr> = vec![Expr {
+            ty: start,
+            tokens: vec![token],
+        }];
+        parse(&mut exprs, token);
+        parse(&mut exprs, token);
+        assert_eq!(exprs.len(), 2);
+        assert_eq!(exprs[1].tokens, vec![token]);
+    }
+
+    #[test]
+    fn parse_count_limit() {
+        assert_count_limit(
+            ExprType::Add(u32::MAX - 1),
+            TokenType::ValInc,
+            parse_val_inc,
+        );
+        assert_count_limit(
+            ExprType::Set(u32::MAX - 1),
+            TokenType::ValInc,
+            parse_val_inc,
+        );
+        assert_count_limit(
+            ExprType::MoveRight(u32::MAX - 1),
+            TokenType::PointInc,
+            parse_point_inc,
+        );
+        assert_count_limit(
+            ExprType::MoveLeft(u32::MAX - 1),
+            TokenType::PointDec,
+            parse_point_dec,
+        );
+        assert_count_limit(
+            ExprType::Output(u32::MAX - 1),
+            TokenType::Output,
+            parse_output,
+        );
+        assert_count_limit(ExprType::Input(u32::MAX - 1), TokenType::Input, parse_input);
+    }
+
+    #[test]
+    fn parse_count_limit_values() {
+        let token = Token::from(TokenType::ValInc, 1, 1);
+        let mut exprs: Vec<Expr> = vec![Expr {
+            ty: ExprType::Add(u32::MAX - 1),
+            tokens: vec![token],
+        }];
+        parse_val_inc(&mut exprs, token);
+        parse_val_inc(&mut exprs, token);
+        let types: Vec<ExprType> = exprs.into_iter().map(|expr| expr.ty).collect();
+        assert_eq!(types, vec![ExprType::Add(u32::MAX), ExprType::Add(1)]);
+    }
+
+    #[test]
+    fn parse_sub_count_limit() {
+        let token = Token::from(TokenType::ValDec, 1, 1);
+        let mut exprs: Vec<Expr> = vec![Expr {
+            ty: ExprType::Sub(u32::MAX),
+            tokens: vec![token],
+        }];
+        parse_val_dec(&mut exprs, token).unwrap();
+        let types: Vec<ExprType> = exprs.into_iter().map(|expr| expr.ty).collect();
+        assert_eq!(types, vec![ExprType::Sub(u32::MAX), ExprType::Sub(1)]);
+    }
+
+    #[test]
+    fn parse_loop_span() {
+        let exprs = parse_tokens(lex_string("+\n [>+\n[-.]<]".to_string()).unwrap()).unwrap();
+        match &exprs[1].ty {
+            ExprType::LoopBlock(lb) => {
+                assert_eq!(lb.open, Token::from(TokenType::IfZero, 2, 2));
+                assert_eq!(lb.close, Some(Token::from(TokenType::IfNonZero, 3, 6)));
+            }
+            other => panic!("parse_loop_span had expr {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_onto_folds_across_lines() {
+        let mut exprs = parse_tokens(lex_string("+++".to_string()).unwrap()).unwrap();
+        parse_tokens_onto(&mut exprs, lex_string("+++".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs.len(), 1);
+        assert_eq!(exprs[0].ty, ExprType::Add(6));
+
+        parse_tokens_onto(&mut exprs, lex_string(".>".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs.len(), 3);
+        assert_eq!(exprs[0].ty, ExprType::Add(6));
+    }
+
+    #[test]
+    fn parse_dead_loop_dropped() {
+        let exprs = parse_tokens(lex_string("+[->+<][->+<]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs.len(), 2);
+        assert_eq!(exprs[1].ty, ExprType::MoveCell { offset: 1 });
+
+        let exprs = parse_tokens(lex_string("+[>+.<-][.]>[.]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs.len(), 4);
+
+        let keep = ParseOptions {
+            drop_dead_loops: false,
+            ..ParseOptions::default()
+        };
+        let tokens = lex_string("+[->+<][->+<]".to_string()).unwrap();
+        assert_eq!(parse_tokens_with(tokens, &keep).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn parse_changes_not_cancelled() {
+        let keep = ParseOptions {
+            cancel_changes: false,
+            ..ParseOptions::default()
+        };
+        let types = |source: &str, options: &ParseOptions| {
+            let tokens = lex_string(source.to_string()).unwrap();
+            parse_tokens_with(tokens, options)
+                .unwrap()
+                .into_iter()
+                .map(|expr| expr.ty)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            types("+++-", &ParseOptions::default()),
+            vec![ExprType::Add(2)]
+        );
+        assert_eq!(
+            types("+++--", &keep),
+            vec![ExprType::Add(3), ExprType::Sub(2)]
+        );
+        assert_eq!(
+            types("[-]++-", &keep),
+            vec![ExprType::Set(2), ExprType::Sub(1)]
+        );
+    }
+
+    #[test]
+    fn parse_switch_tape_folds() {
+        let two_tape = CommandSet::default().with_lang(Lang::TwoTape);
+        let runs = lex_runs_with("+}{}[}-{]".to_string(), two_tape).unwrap();
+        let exprs = parse_token_runs(runs).unwrap();
+        assert_eq!(exprs[1].ty, ExprType::SwitchTape(1));
+        assert_eq!(exprs[1].tokens.len(), 3);
+        match &exprs[2].ty {
+            ExprType::LoopBlock(lb) => assert_eq!(lb.exprs.len(), 3),
+            other => panic!("parse_switch_tape_folds had expr {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_move_io_fused() {
+        let exprs = parse_tokens(lex_string(">.<<<,>>".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs.len(), 2);
+        assert_eq!(exprs[0].ty, ExprType::OutputAt { offset: 1 });
+        assert_eq!(exprs[1].ty, ExprType::InputAt { offset: -2 });
+        assert_eq!(exprs[1].tokens.len(), 5);
+
+        let exprs = parse_tokens(lex_string(">..<".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs[1].ty, ExprType::Output(2));
+    }
 }
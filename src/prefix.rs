@@ -0,0 +1,350 @@
+use std::slice;
+
+use crate::config::{Cell, Config};
+use crate::expr::{ConstPrefix, Expr, ExprType, LoopBlock};
+
+/// The most steps, counting each Expr and each loop iteration, that are run while working out a
+/// constant prefix before giving up on folding any more of it.
+pub const STEP_LIMIT: u64 = 1_000_000;
+
+/// Fold the top-level Exprs at the start of a program whose effect cannot depend on input into a
+/// single EmitConst, by running them ahead of time on an all-zero tape with the data pointer on
+/// cell `start_index`. Folding stops before the first Expr that would read input, overflow a cell,
+/// move off either end of the tape, grow the tape past the report size, output anything other than
+/// a valid character (or a control character, if the Config warns about them), or take more than
+/// STEP_LIMIT steps in total, so the folded program runs exactly as the original would under the
+/// given Config. The result is only equivalent to the original when it is run on a fresh tape.
+pub fn fold_const_prefix(mut exprs: Vec<Expr>, config: &Config, start_index: usize) -> Vec<Expr> {
+    // Folded reads and writes could not be checked, folded output would appear all at once or be
+    // refused as a whole rather than up to the limit, and folded steps would be neither counted nor
+    // watched
+    if config.warn_uninit
+        || config.readonly.is_some()
+        || config.output_delay.is_some()
+        || config.limits.steps.is_some()
+        || config.limits.output.is_some()
+        || config.detect_hangs
+    {
+        return exprs;
+    }
+    let mut tape = ConstTape::new(config, start_index);
+    let mut folded: usize = 0;
+    for expr in &exprs {
+        let mut attempt = tape.clone();
+        if attempt.eval(expr).is_none() {
+            break;
+        }
+        tape = attempt;
+        folded += 1;
+    }
+
+    // Nothing is folded unless the prefix outputs something
+    if tape.bytes.is_empty() {
+        return exprs;
+    }
+
+    let rest = exprs.split_off(folded);
+    let tokens = exprs
+        .iter()
+        .flat_map(|expr| expr.tokens.iter().copied())
+        .collect();
+    let mut result = vec![Expr {
+        ty: ExprType::EmitConst(Box::new(tape.into_prefix())),
+        tokens,
+    }];
+    result.extend(rest);
+    result
+}
+
+/// A ConstTape runs Exprs ahead of time from an all-zero tape, keeping the cells and data pointer
+/// relative to the starting cell along with everything output so far. Each method returns None if
+/// running on would not behave the same as running the program for real.
+#[derive(Debug, Clone)]
+struct ConstTape<'a> {
+    config: &'a Config,
+    start_index: usize,
+    cells: Vec<u32>,
+    index: usize,
+    bytes: Vec<u8>,
+    steps: u64,
+}
+
+impl<'a> ConstTape<'a> {
+    fn new(config: &'a Config, start_index: usize) -> Self {
+        ConstTape {
+            config,
+            start_index,
+            cells: vec![0],
+            index: 0,
+            bytes: vec![],
+            steps: 0,
+        }
+    }
+
+    /// Count a step, giving up once STEP_LIMIT is passed.
+    fn step(&mut self) -> Option<()> {
+        self.steps += 1;
+        (self.steps <= STEP_LIMIT).then_some(())
+    }
+
+    fn current(&self) -> u32 {
+        self.cells[self.index]
+    }
+
+    /// Set the current cell, giving up if the value does not fit in a cell.
+    fn set_current(&mut self, val: u32) -> Option<()> {
+        self.set(self.index, val)
+    }
+
+    /// Set the given cell, giving up if the value does not fit in a cell.
+    fn set(&mut self, index: usize, val: u32) -> Option<()> {
+        ((val as Cell) <= self.config.cell_max()).then(|| self.cells[index] = val)
+    }
+
+    /// Make sure the given cell exists, as long as reaching it would neither fall off a fixed
+    /// tape nor give a tape size warning.
+    fn reach(&mut self, index: usize) -> Option<()> {
+        let absolute = self.start_index.checked_add(index)?;
+        if absolute >= self.config.tape_report
+            || (self.config.no_grow && absolute >= self.config.tape_size)
+        {
+            return None;
+        }
+        if index >= self.cells.len() {
+            self.cells.resize(index + 1, 0);
+        }
+        Some(())
+    }
+
+    fn move_to(&mut self, index: usize) -> Option<()> {
+        self.reach(index)?;
+        self.index = index;
+        Some(())
+    }
+
+    fn output(&mut self, val: u32) -> Option<()> {
+        let c = char::from_u32(self.current())?;
+        if self.config.warn_control_output && c.is_control() && c != '\n' && c != '\t' {
+            return None;
+        }
+        let mut buf = [0; 4];
+        let encoded = c.encode_utf8(&mut buf).as_bytes();
+        for _ in 0..val {
+            self.bytes.extend_from_slice(encoded);
+        }
+        Some(())
+    }
+
+    fn linear_loop(&mut self, targets: &[(i64, i64)]) -> Option<()> {
+        let val = self.current() as i128;
+        if val == 0 {
+            return Some(());
+        }
+        for (offset, factor) in targets {
+            let target = usize::try_from(self.index as i64 + offset).ok()?;
+            self.reach(target)?;
+            let new_val = self.cells[target] as i128 + val * *factor as i128;
+            self.set(target, u32::try_from(new_val).ok()?)?;
+        }
+        self.set_current(0)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Option<()> {
+        self.step()?;
+        match &expr.ty {
+            ExprType::Set(val) => self.set_current(*val)?,
+            ExprType::Add(val) => self.set_current(self.current().checked_add(*val)?)?,
+            ExprType::Sub(val) => self.set_current(self.current().checked_sub(*val)?)?,
+            ExprType::MoveRight(val) => self.move_to(self.index.checked_add(*val as usize)?)?,
+            ExprType::MoveLeft(val) => self.move_to(self.index.checked_sub(*val as usize)?)?,
+            ExprType::Output(val) => self.output(*val)?,
+            ExprType::OutputAt { offset } => {
+                let here = self.index;
+                self.move_to(usize::try_from(here as i64 + offset).ok()?)?;
+                self.output(1)?;
+                self.index = here;
+            }
+            ExprType::Input(_)
+            | ExprType::InputAt { .. }
+            | ExprType::EmitConst(_)
+            | ExprType::SwitchTape(_) => return None,
+            ExprType::ScanRight(val) => {
+                while self.current() != 0 {
+                    self.step()?;
+                    self.move_to(self.index.checked_add(*val as usize)?)?;
+                }
+            }
+            ExprType::ScanLeft(val) => {
+                while self.current() != 0 {
+                    self.step()?;
+                    self.move_to(self.index.checked_sub(*val as usize)?)?;
+                }
+            }
+            ExprType::LinearLoop(targets) => self.linear_loop(targets)?,
+            ExprType::MoveCell { offset } => self.linear_loop(&[(*offset, 1)])?,
+            ExprType::LoopBlock(lb) => self.run_loop(lb)?,
+        }
+        Some(())
+    }
+
+    /// Run the given LoopBlock until its condition cell is zero. Loops nested in it are run with
+    /// an explicit stack of the loops still running rather than by recursing, so deeply nested
+    /// loops do not grow the call stack.
+    fn run_loop(&mut self, lb: &LoopBlock) -> Option<()> {
+        let mut frames: Vec<(&LoopBlock, slice::Iter<Expr>)> = vec![];
+        if self.current() != 0 {
+            self.step()?;
+            frames.push((lb, lb.exprs.iter()));
+        }
+        while let Some((lb, body)) = frames.last_mut() {
+            match body.next() {
+                Some(Expr {
+                    ty: ExprType::LoopBlock(inner),
+                    ..
+                }) => {
+                    self.step()?;
+                    if self.current() != 0 {
+                        self.step()?;
+                        frames.push((inner, inner.exprs.iter()));
+                    }
+                }
+                Some(expr) => self.eval(expr)?,
+                None if self.current() != 0 => {
+                    self.step()?;
+                    *body = lb.exprs.iter();
+                }
+                None => {
+                    frames.pop();
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// The ConstPrefix for everything run so far, leaving out trailing zero cells since the tape
+    /// is zero there already.
+    fn into_prefix(mut self) -> ConstPrefix {
+        while self.cells.last() == Some(&0) {
+            self.cells.pop();
+        }
+        ConstPrefix {
+            bytes: self.bytes,
+            cells: self.cells,
+            pointer: self.index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "unbounded")]
+    use crate::config::CellWidth;
+    use crate::config::Config;
+    use crate::expr::{ConstPrefix, Expr, ExprType};
+    use crate::generator::generate;
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+    use crate::prefix::fold_const_prefix;
+    use crate::progstate::ProgState;
+
+    fn parse_source(source: &str) -> Vec<Expr> {
+        parse_tokens(lex_string(source.to_string()).unwrap()).unwrap()
+    }
+
+    fn run_output(exprs: &[Expr]) -> Vec<u8> {
+        let mut state = ProgState::with_output(vec![]);
+        state.run(exprs).unwrap();
+        state.output().clone()
+    }
+
+    #[test]
+    fn fold_banner() {
+        let exprs = parse_source(&generate("Hi"));
+        let folded = fold_const_prefix(exprs.clone(), &Config::default(), 0);
+        assert_eq!(folded.len(), 1);
+        assert!(matches!(folded[0].ty, ExprType::EmitConst(_)));
+        assert_eq!(run_output(&folded), b"Hi");
+        assert_eq!(run_output(&folded), run_output(&exprs));
+    }
+
+    #[test]
+    fn fold_skipped_with_step_limit() {
+        let exprs = parse_source("++++++++[>++++++++<-]>+.");
+        let mut config = Config::default();
+        config.limits.steps = Some(5);
+        assert_eq!(fold_const_prefix(exprs.clone(), &config, 0), exprs);
+        let config = Config {
+            detect_hangs: true,
+            ..Config::default()
+        };
+        assert_eq!(fold_const_prefix(exprs.clone(), &config, 0), exprs);
+    }
+
+    #[test]
+    fn fold_skipped_with_output_limit() {
+        let exprs = parse_source("++++++++[>++++++++<-]>+.+.");
+        let mut config = Config::default();
+        config.limits.output = Some(1);
+        assert_eq!(fold_const_prefix(exprs.clone(), &config, 0), exprs);
+
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(config);
+        assert!(state.run(&exprs).is_err());
+        assert_eq!(state.output(), b"A");
+    }
+
+    #[cfg(feature = "unbounded")]
+    #[test]
+    fn fold_unbounded_byte_cells() {
+        let exprs = parse_source(&format!("{}.", "+".repeat(300)));
+        let config = Config {
+            unbounded_cells: true,
+            cell_size: CellWidth::Bits8,
+            ..Config::default()
+        };
+        let folded = fold_const_prefix(exprs.clone(), &config, 0);
+        assert!(matches!(folded[0].ty, ExprType::EmitConst(_)));
+
+        let run_with_config = |exprs: &[Expr]| {
+            let mut state = ProgState::with_output(vec![]);
+            state.set_config(config.clone());
+            state.run(exprs).unwrap();
+            state.output().clone()
+        };
+        assert_eq!(run_with_config(&folded), "Ĭ".as_bytes());
+        assert_eq!(run_with_config(&exprs), "Ĭ".as_bytes());
+    }
+
+    #[test]
+    fn fold_deeply_nested() {
+        let depth = 100000;
+        let source = format!("+{}->+<{}>.", "[".repeat(depth), "]".repeat(depth));
+        let folded = fold_const_prefix(parse_source(&source), &Config::default(), 0);
+        assert_eq!(folded.len(), 1);
+        assert_eq!(run_output(&folded), [1]);
+    }
+
+    #[test]
+    fn fold_stops_at_input() {
+        let folded = fold_const_prefix(parse_source("++>+++.,."), &Config::default(), 0);
+        assert_eq!(
+            folded[0].ty,
+            ExprType::EmitConst(Box::new(ConstPrefix {
+                bytes: vec![3],
+                cells: vec![2, 3],
+                pointer: 1,
+            }))
+        );
+        assert_eq!(folded[1].ty, ExprType::Input(1));
+        assert_eq!(folded[2].ty, ExprType::Output(1));
+    }
+
+    #[test]
+    fn fold_nothing_without_output() {
+        let exprs = parse_source("+++>+[-<+>]");
+        assert_eq!(
+            fold_const_prefix(exprs.clone(), &Config::default(), 0),
+            exprs
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::io::{BufReader, Read, Write};
+
+use crate::config::Config;
+use crate::error::BrainfartResult;
+use crate::expr::Expr;
+use crate::lexer::{lex_runs_limited, CommandSet};
+use crate::parser::{parse_token_runs_with, ParseOptions};
+use crate::prefix::fold_const_prefix;
+use crate::progstate::ProgState;
+
+/// A Program is brainfuck source that has been lexed, parsed and optimized once, along with the
+/// Config it runs with, so that it can be run many times, each on a fresh tape with its own input
+/// and output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    exprs: Vec<Expr>,
+    config: Config,
+}
+
+impl Program {
+    /// Compile the given source to run with the default Config.
+    pub fn compile(source: &str) -> BrainfartResult<Self> {
+        Program::compile_with(source, Config::default())
+    }
+
+    /// Compile the given source to run with the given Config, whose source limits are checked
+    /// while lexing.
+    pub fn compile_with(source: &str, config: Config) -> BrainfartResult<Self> {
+        let runs = lex_runs_limited(source.to_string(), CommandSet::default(), config.limits)?;
+        let exprs = parse_token_runs_with(runs, &ParseOptions::for_config(&config))?;
+        let exprs = fold_const_prefix(exprs, &config, 0);
+        Ok(Program { exprs, config })
+    }
+
+    /// The Exprs the program was compiled to.
+    pub fn exprs(&self) -> &[Expr] {
+        &self.exprs
+    }
+
+    /// Run the program on a fresh tape, reading input from the given reader a line at a time as
+    /// stdin is, and writing output to the given sink. Returns the ProgState it finished in, so
+    /// that the output sink and final tape can be looked at.
+    pub fn run_with<W: Write>(
+        &self,
+        input: impl Read + 'static,
+        output: W,
+    ) -> BrainfartResult<ProgState<W>> {
+        let mut state = ProgState::with_output(output);
+        state.set_config(self.config.clone());
+        state.set_input(BufReader::new(input));
+        state.run(&self.exprs)?;
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::config::{Config, Limits};
+    use crate::error::{BrainfartError, LimitKind};
+    use crate::program::Program;
+
+    #[test]
+    fn compile_once_run_twice() {
+        let cat = Program::compile(",[.[-],]").unwrap();
+        let first = cat.run_with(Cursor::new(b"h\ni\n"), vec![]).unwrap();
+        assert_eq!(first.output(), b"hi");
+        let second = cat.run_with(Cursor::new(b"b\nf\n!\n"), vec![]).unwrap();
+        assert_eq!(second.output(), b"bf!");
+        assert_eq!(second.tape_dump().cells, vec![]);
+    }
+
+    #[test]
+    fn step_limit_counts_constant_prefix() {
+        let config = Config {
+            limits: Limits {
+                steps: Some(4),
+                ..Limits::default()
+            },
+            ..Config::default()
+        };
+        let program = Program::compile_with("++++++++[>++++++++<-]>+.", config).unwrap();
+        assert!(matches!(
+            program.run_with(Cursor::new(b""), vec![]),
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Steps,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn compile_with_limits() {
+        let config = Config {
+            limits: Limits {
+                source_bytes: Some(3),
+                ..Limits::default()
+            },
+            ..Config::default()
+        };
+        assert!(matches!(
+            Program::compile_with("+++.", config),
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Source,
+                ..
+            })
+        ));
+    }
+}
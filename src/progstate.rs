@@ -1,84 +1,1021 @@
-use crate::error::{BrainfartError, BrainfartResult};
-use crate::expr::{Expr, ExprType, LoopBlock};
+use crate::checkpoint::Checkpoint;
+use crate::config::{ArithMode, Cell, CellWidth, Config, InvalidOutput, ReadOnlyStart};
+use crate::coverage::Coverage;
+use crate::error::{BrainfartError, BrainfartResult, BrainfartWarning, LimitKind};
+use crate::expr::{ConstPrefix, Expr, ExprType};
+use crate::token::{Token, TokenType};
+use crate::trace::TraceStep;
 
-use std::io;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Stdout, Write};
+use std::mem;
+use std::slice;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// A ProgState represents the state/context of the program, with a list of the commands to go
 /// through, a table of the current data stored by the program, the locations of the current
-/// command and current data pointer, as well as a stack to keep track of loops.
-#[derive(Debug)]
-pub struct ProgState {
-    data: Vec<u32>,
+/// command and current data pointer, as well as a stack to keep track of loops. Output produced by
+/// the program is written to the `output` sink, and `config` holds the policies used while running.
+/// A host embedding the interpreter can register hooks to observe overflows and output. Input is
+/// read a line at a time from `input`, which is stdin unless replaced. In the two-tape language
+/// `data` and `data_index` hold the active tape, and the other is kept in `other_tape` until it is
+/// switched to.
+pub struct ProgState<W: Write = Stdout> {
+    data: Vec<Cell>,
     data_index: usize,
+    other_tape: (Vec<Cell>, usize),
+    other_written: Vec<u64>,
+    tape: usize,
+    output: W,
+    input: Box<dyn BufRead>,
+    config: Config,
+    overflow_hook: Option<OverflowHook>,
+    output_hook: Option<OutputHook>,
+    checkpoint_hook: Option<CheckpointHook>,
+    trace_hook: Option<TraceHook>,
+    progress_hook: Option<ProgressHook>,
+    progress_every: u64,
+    progress_started: Option<Instant>,
+    checkpoint_every: u64,
+    next_checkpoint: u64,
+    warnings: Vec<BrainfartWarning>,
+    tape_warned: bool,
+    control_warned: bool,
+    written: Vec<u64>,
+    uninit_warned: bool,
+    profile: Option<Profile>,
+    heatmap: Option<Heatmap>,
+    coverage: Option<Coverage>,
+    max_loop_depth: usize,
+    grow_chunk: usize,
+    hang_states: Vec<Option<HashSet<u64>>>,
+    input_channel: Option<Receiver<u8>>,
+    input_progress: usize,
+    steps: u64,
+    output_count: u64,
+    started: Option<Instant>,
 }
 
-impl ProgState {
+/// How many steps run between checks of the time limit, so that the clock is not read on every
+/// step.
+const TIME_CHECK_INTERVAL: u64 = 4096;
+
+/// An Execution is a run of a program that can be paused and picked up again with
+/// `ProgState::resume`, holding the loop bodies being run and the position reached in each.
+#[derive(Debug, Clone)]
+pub struct Execution<'a> {
+    frames: Vec<(&'a [Expr], usize)>,
+}
+
+impl<'a> Execution<'a> {
+    /// Produce an Execution that starts at the first of the given Exprs.
+    pub fn new(exprs: &'a [Expr]) -> Self {
+        Execution {
+            frames: vec![(exprs, 0)],
+        }
+    }
+
+    /// Produce an Execution of the given Exprs that has reached the given position in each loop
+    /// body being run, outermost first, as recorded in a Checkpoint. Returns None if the positions
+    /// do not fit the Exprs.
+    fn at(exprs: &'a [Expr], positions: &[usize]) -> Option<Self> {
+        let mut frames = vec![];
+        let mut body = exprs;
+        for (i, &position) in positions.iter().enumerate() {
+            if position > body.len() {
+                return None;
+            }
+            frames.push((body, position));
+            if i + 1 < positions.len() {
+                // A loop body is run from the LoopBlock just before the position in its parent
+                match &body.get(position.checked_sub(1)?)?.ty {
+                    ExprType::LoopBlock(lb) => body = &lb.exprs,
+                    _ => return None,
+                }
+            }
+        }
+        Some(Execution { frames })
+    }
+
+    /// Whether every Expr has been run.
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// How a call to `ProgState::resume` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// The program ran to the end
+    Finished,
+    /// The program paused and can be resumed once the reason is dealt with
+    Suspended { reason: SuspendReason },
+}
+
+/// How a call to `ProgState::step` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The Expr ran, and the next one to step is at the given position
+    Ran { next: usize },
+    /// The position is past the last Expr, so there is nothing left to run
+    Finished,
+}
+
+/// Why an Execution was paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspendReason {
+    /// A `,` was reached while no input was waiting on the input channel
+    NeedInput,
+}
+
+/// A Profile counts how many times each kind of Expr has run, keyed by `ExprType::name`.
+pub type Profile = BTreeMap<&'static str, u64>;
+
+/// A Heatmap counts how many times the program read or wrote each cell, indexed by cell. Each Expr
+/// counts as one access to each cell it touches, so `+++` is a single write.
+pub type Heatmap = Vec<u64>;
+
+/// The direction in which a cell's value left the range it can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellOverflow {
+    /// The cell was incremented past its maximum value
+    Over,
+    /// The cell was decremented below zero
+    Under,
+}
+
+/// A callback run with the index of a cell whenever that cell overflows or underflows, before the
+/// configured ArithMode is applied.
+pub type OverflowHook = Box<dyn FnMut(usize, CellOverflow)>;
+
+/// A callback run with each byte after it has been written to the output sink.
+pub type OutputHook = Box<dyn FnMut(u8)>;
+
+/// A callback run with a Checkpoint of the run every so many steps.
+pub type CheckpointHook = Box<dyn FnMut(&Checkpoint)>;
+
+/// A callback run with a TraceStep after each Expr runs.
+pub type TraceHook = Box<dyn FnMut(&TraceStep)>;
+
+/// A callback run with the Progress of the run every so many steps.
+pub type ProgressHook = Box<dyn FnMut(&Progress)>;
+
+/// A Progress tells how far a long run has got: the steps run so far, where the data pointer is,
+/// and the time since the progress hook was registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub steps: u64,
+    pub pointer: usize,
+    pub elapsed: Duration,
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PROGRESS {} steps, pointer {}, {:.1?} elapsed",
+            self.steps, self.pointer, self.elapsed
+        )
+    }
+}
+
+/// A TapeSnapshot is a copy of a ProgState's cells and data pointer at some point in time, taken
+/// with `ProgState::snapshot` and returned to with `ProgState::restore`, along with the other tape
+/// and which of the two is active.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeSnapshot {
+    data: Vec<Cell>,
+    data_index: usize,
+    other_tape: (Vec<Cell>, usize),
+    tape: usize,
+}
+
+/// A TapeDump is the final state of a ProgState's tape in a form meant for other programs to read:
+/// the cells up to the last nonzero one, the data pointer, and the width of each cell in bits. With
+/// the `serde` feature it can be serialized, such as to JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TapeDump {
+    pub cells: Vec<Cell>,
+    pub pointer: usize,
+    pub cell_width: u32,
+}
+
+impl Default for ProgState {
     /// Generate the default ProgState, with an empty cell array and the data pointer pointing to
-    /// the first cell.
-    pub fn default() -> Self {
-        let mut data: Vec<u32> = vec![0];
+    /// the first cell, writing output to stdout.
+    fn default() -> Self {
+        ProgState::with_output(io::stdout())
+    }
+}
+
+impl ProgState {
+    /// Generate a ProgState as `default` does, but with cells of the given width, which sets the
+    /// value cell arithmetic wraps around, saturates or stops at.
+    pub fn with_cell_width(width: CellWidth) -> Self {
+        let mut state = ProgState::default();
+        state.config.cell_size = width;
+        state
+    }
+}
+
+impl<W: Write> ProgState<W> {
+    /// Generate a ProgState with an empty cell array and the data pointer pointing to the first
+    /// cell, writing output to the given sink.
+    pub fn with_output(output: W) -> Self {
+        let mut data: Vec<Cell> = vec![0];
         let data_index = 0;
         data.resize(data.capacity(), 0);
-        ProgState { data, data_index }
+        ProgState {
+            data,
+            data_index,
+            other_tape: (vec![], 0),
+            other_written: vec![],
+            tape: 0,
+            output,
+            input: Box::new(BufReader::new(io::stdin())),
+            config: Config::default(),
+            overflow_hook: None,
+            output_hook: None,
+            checkpoint_hook: None,
+            trace_hook: None,
+            progress_hook: None,
+            progress_every: 0,
+            progress_started: None,
+            checkpoint_every: 0,
+            next_checkpoint: 0,
+            warnings: vec![],
+            tape_warned: false,
+            control_warned: false,
+            written: vec![],
+            uninit_warned: false,
+            profile: None,
+            heatmap: None,
+            coverage: None,
+            max_loop_depth: 0,
+            grow_chunk: 0,
+            hang_states: vec![],
+            input_channel: None,
+            input_progress: 0,
+            steps: 0,
+            output_count: 0,
+            started: None,
+        }
+    }
+
+    /// Replace the reader that input is taken from.
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input_channel = None;
+        self.input = Box::new(input);
+    }
+
+    /// Take input from the given channel instead of a reader, one byte per `,`. When no byte is
+    /// waiting, `resume` suspends the program until the host sends one, while `run` waits for it.
+    /// Once the sending side is dropped, the input has ended.
+    pub fn set_input_channel(&mut self, channel: Receiver<u8>) {
+        self.input_channel = Some(channel);
+    }
+
+    /// The sink that program output is written to.
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    /// The value of the cell under the data pointer.
+    pub fn current(&self) -> Cell {
+        self.data[self.data_index]
+    }
+
+    /// Move the data pointer to the given cell, growing the cell array to contain it if needed.
+    pub fn set_data_index(&mut self, index: usize) {
+        self.data_index = index;
+        self.grow_to_data_index();
+    }
+
+    /// Write the given values into consecutive cells starting at the given cell, growing the cell
+    /// array to contain them if needed.
+    pub fn write_cells(&mut self, start: usize, values: impl IntoIterator<Item = Cell>) {
+        for (i, value) in values.into_iter().enumerate() {
+            self.grow_to(start + i);
+            self.data[start + i] = value;
+            self.mark_written(start + i);
+        }
+    }
+
+    /// Capture the current tape contents and data pointer so they can be restored later.
+    pub fn snapshot(&self) -> TapeSnapshot {
+        TapeSnapshot {
+            data: self.data.clone(),
+            data_index: self.data_index,
+            other_tape: self.other_tape.clone(),
+            tape: self.tape,
+        }
+    }
+
+    /// Describe the active tape's cells, trimmed of the zero cells at its end, and the data
+    /// pointer.
+    pub fn tape_dump(&self) -> TapeDump {
+        TapeDump {
+            cells: trimmed_cells(&self.data).to_vec(),
+            pointer: self.data_index,
+            cell_width: self.config.cell_width(),
+        }
+    }
+
+    /// Return the tape contents and data pointer to the state captured in the given snapshot.
+    pub fn restore(&mut self, snap: &TapeSnapshot) {
+        self.data.clone_from(&snap.data);
+        self.data_index = snap.data_index;
+        self.other_tape.clone_from(&snap.other_tape);
+        self.tape = snap.tape;
+    }
+
+    /// Replace the policies this ProgState runs with.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.fit_tape_size();
+    }
+
+    /// Grow both tapes to the configured tape size if it is fixed. The other tape is left empty if
+    /// it has not been switched to yet.
+    fn fit_tape_size(&mut self) {
+        if !self.config.no_grow {
+            return;
+        }
+        let size = self.config.tape_size;
+        if self.data.len() < size {
+            self.data.resize(size, 0);
+        }
+        let other = &mut self.other_tape.0;
+        if !other.is_empty() && other.len() < size {
+            other.resize(size, 0);
+        }
+    }
+
+    /// Take the warnings given while running so far, such as the tape growing past the configured
+    /// report size.
+    pub fn take_warnings(&mut self) -> Vec<BrainfartWarning> {
+        mem::take(&mut self.warnings)
+    }
+
+    /// Start counting how many times each kind of Expr runs.
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    /// Take the counts gathered since profiling was enabled or last taken, if it is enabled.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.as_mut().map(mem::take)
+    }
+
+    /// Start counting how many times each cell is read or written.
+    pub fn enable_heatmap(&mut self) {
+        self.heatmap = Some(Heatmap::new());
+    }
+
+    /// Take the counts gathered since the heatmap was enabled or last taken, if it is enabled.
+    pub fn take_heatmap(&mut self) -> Option<Heatmap> {
+        self.heatmap.as_mut().map(mem::take)
+    }
+
+    /// Start recording which Exprs run, by their first Token.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// Take the Exprs recorded as run since coverage was enabled or last taken, if it is enabled.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.as_mut().map(mem::take)
+    }
+
+    /// Take the deepest loop nesting entered while running since it was last taken. Loops whose
+    /// bodies never run are not counted.
+    pub fn take_max_loop_depth(&mut self) -> usize {
+        mem::take(&mut self.max_loop_depth)
+    }
+
+    /// Register a callback to run whenever a cell overflows or underflows, replacing any previous
+    /// one.
+    pub fn set_overflow_hook(&mut self, hook: impl FnMut(usize, CellOverflow) + 'static) {
+        self.overflow_hook = Some(Box::new(hook));
+    }
+
+    /// Register a callback to run with each output byte, replacing any previous one.
+    pub fn set_output_hook(&mut self, hook: impl FnMut(u8) + 'static) {
+        self.output_hook = Some(Box::new(hook));
+    }
+
+    /// Register a callback to run after each Expr, replacing any previous one. Loops are traced
+    /// when they are entered or skipped, and the Exprs in their bodies each time they run.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(&TraceStep) + 'static) {
+        self.trace_hook = Some(Box::new(hook));
+    }
+
+    /// Grow the tape in whole multiples of the given number of cells when the pointer moves past
+    /// its end, instead of at least doubling it, so that a program that steadily moves right
+    /// reallocates at a steady pace. Zero goes back to doubling.
+    pub fn set_grow_chunk(&mut self, chunk: usize) {
+        self.grow_chunk = chunk;
+    }
+
+    /// Register a callback to run with the Progress of the run each time another `every` steps have
+    /// run, replacing any previous one. The elapsed time is counted from now.
+    pub fn set_progress_hook(&mut self, every: u64, hook: impl FnMut(&Progress) + 'static) {
+        self.progress_hook = Some(Box::new(hook));
+        self.progress_every = every;
+        self.progress_started = Some(Instant::now());
+    }
+
+    /// Register a callback to run with a Checkpoint once at least `every` steps have run since the
+    /// last one, replacing any previous one. Checkpoints are only taken between Exprs.
+    pub fn set_checkpoint_hook(&mut self, every: u64, hook: impl FnMut(&Checkpoint) + 'static) {
+        self.checkpoint_hook = Some(Box::new(hook));
+        self.checkpoint_every = every;
+        self.next_checkpoint = self.steps + every;
+    }
+
+    /// Take a Checkpoint of the given Execution of a program run with this ProgState.
+    pub fn checkpoint(&self, execution: &Execution) -> Checkpoint {
+        self.checkpoint_frames(&execution.frames)
+    }
+
+    /// Take a Checkpoint of a run that has reached the given frames.
+    fn checkpoint_frames(&self, frames: &[(&[Expr], usize)]) -> Checkpoint {
+        let (other, other_pointer) = &self.other_tape;
+        let other_cells = match other.is_empty() {
+            true => vec![],
+            false => saved_cells(other, *other_pointer).to_vec(),
+        };
+        Checkpoint {
+            cells: saved_cells(&self.data, self.data_index).to_vec(),
+            pointer: self.data_index,
+            tape: self.tape,
+            other_cells,
+            other_pointer: *other_pointer,
+            positions: frames.iter().map(|&(_, position)| position).collect(),
+            steps: self.steps,
+            output_count: self.output_count,
+        }
+    }
+
+    /// Return the tape and counts to the state saved in the given Checkpoint, producing an
+    /// Execution of the given Exprs that picks up where it was taken. The Exprs must be the
+    /// program the Checkpoint was taken from; None is returned if they clearly are not.
+    pub fn restore_checkpoint<'a>(
+        &mut self,
+        checkpoint: &Checkpoint,
+        exprs: &'a [Expr],
+    ) -> Option<Execution<'a>> {
+        let execution = Execution::at(exprs, &checkpoint.positions)?;
+        self.data.clone_from(&checkpoint.cells);
+        self.data.resize(self.data.capacity().max(1), 0);
+        self.set_data_index(checkpoint.pointer);
+        self.other_tape = (checkpoint.other_cells.clone(), checkpoint.other_pointer);
+        if !self.other_tape.0.is_empty() {
+            let len = self.other_tape.0.len().max(checkpoint.other_pointer + 1);
+            self.other_tape.0.resize(len, 0);
+        }
+        self.tape = checkpoint.tape;
+        self.fit_tape_size();
+        self.steps = checkpoint.steps;
+        self.output_count = checkpoint.output_count;
+        self.next_checkpoint = self.steps + self.checkpoint_every;
+        Some(execution)
     }
 
-    /// Run the provided vector of Exprs with the current ProgState.
+    /// Run the provided vector of Exprs with the current ProgState, waiting for any input from the
+    /// input channel.
     pub fn run(&mut self, exprs: &[Expr]) -> BrainfartResult<()> {
+        let loop_free = !exprs
+            .iter()
+            .any(|expr| matches!(expr.ty, ExprType::LoopBlock(_)));
+        if loop_free && self.checkpoint_hook.is_none() {
+            return self.run_straight(exprs);
+        }
+        let mut execution = Execution::new(exprs);
+        self.run_execution(&mut execution, true).map(|_| ())
+    }
+
+    /// Run Exprs that contain no LoopBlock one after the other, as straight-line code such as a
+    /// banner. With no loop bodies to enter, this skips the stack of frames `run_execution` keeps
+    /// and the checks it makes between Exprs.
+    fn run_straight(&mut self, exprs: &[Expr]) -> BrainfartResult<()> {
+        if self.config.limits.time.is_some() {
+            self.started.get_or_insert_with(Instant::now);
+        }
         for expr in exprs {
+            self.count_step(expr.tokens[0])?;
+            self.record_run(expr);
+            match &expr.ty {
+                ExprType::Input(_) | ExprType::InputAt { .. } => {
+                    self.run_any_input(expr, true).map(|_| ())?
+                }
+                _ => self.run_simple(expr)?,
+            }
+            self.trace(expr);
+        }
+        Ok(())
+    }
+
+    /// Continue the given Execution until it finishes, waiting for any input from the input
+    /// channel.
+    pub fn finish(&mut self, execution: &mut Execution) -> BrainfartResult<()> {
+        self.run_execution(execution, true).map(|_| ())
+    }
+
+    /// Continue the given Execution until it finishes or has to wait for input from the input
+    /// channel, in which case it can be resumed again once input has been sent.
+    pub fn resume(&mut self, execution: &mut Execution) -> BrainfartResult<RunStatus> {
+        self.run_execution(execution, false)
+    }
+
+    /// Run the given Execution, waiting for input from the input channel if `blocking` and
+    /// suspending otherwise. Loops are run with an explicit stack of the bodies being run and the
+    /// position reached in each, so deeply nested loops do not grow the call stack.
+    fn run_execution(
+        &mut self,
+        execution: &mut Execution,
+        blocking: bool,
+    ) -> BrainfartResult<RunStatus> {
+        let frames = &mut execution.frames;
+        if self.config.limits.time.is_some() {
+            self.started.get_or_insert_with(Instant::now);
+        }
+
+        while let Some(&(body, position)) = frames.last() {
+            let depth = frames.len() - 1;
+            if position == body.len() {
+                if depth > 0 {
+                    // The LoopBlock being repeated is the last Expr run in the enclosing body
+                    let (parent, parent_position) = frames[depth - 1];
+                    let token = parent[parent_position - 1].tokens[0];
+                    self.check_uninit(self.data_index, token);
+                    if self.data[self.data_index] != 0 {
+                        self.count_step(token)?;
+                        if self.config.detect_hangs {
+                            self.check_hang(depth, token)?;
+                        }
+                        frames[depth].1 = 0;
+                        continue;
+                    }
+                }
+                frames.pop();
+                continue;
+            }
+            if self.checkpoint_hook.is_some() && self.steps >= self.next_checkpoint {
+                self.next_checkpoint = self.steps + self.checkpoint_every;
+                let checkpoint = self.checkpoint_frames(frames);
+                if let Some(hook) = &mut self.checkpoint_hook {
+                    hook(&checkpoint);
+                }
+            }
+            frames[depth].1 += 1;
+
+            let expr = &body[position];
+            self.count_step(expr.tokens[0])?;
+            self.record_run(expr);
+
             let result = match &expr.ty {
-                ExprType::Set(val) => self.run_set(*val),
-                ExprType::Add(val) => self.run_add(*val),
-                ExprType::Sub(val) => self.run_sub(expr, *val),
-                ExprType::MoveRight(val) => self.run_move_right(*val),
-                ExprType::MoveLeft(val) => self.run_move_left(expr, *val),
-                ExprType::Output(val) => self.run_output(*val),
-                ExprType::Input(val) => self.run_input(expr, *val),
-                ExprType::LoopBlock(lb) => self.run_loop_block(lb),
+                ExprType::Input(_) | ExprType::InputAt { .. } => {
+                    if !self.run_any_input(expr, blocking)? {
+                        // The Expr runs again on resuming, so it is only counted then
+                        if let Some(count) = self
+                            .profile
+                            .as_mut()
+                            .and_then(|profile| profile.get_mut(expr.ty.name()))
+                        {
+                            *count -= 1;
+                        }
+                        frames[depth].1 = position;
+                        return Ok(RunStatus::Suspended {
+                            reason: SuspendReason::NeedInput,
+                        });
+                    }
+                    Ok(())
+                }
+                ExprType::LoopBlock(lb) => {
+                    self.check_uninit(self.data_index, expr.tokens[0]);
+                    if self.data[self.data_index] != 0 {
+                        if self.config.detect_hangs {
+                            self.watch_for_hang(depth + 1, &lb.exprs);
+                        }
+                        frames.push((&lb.exprs, 0));
+                        self.max_loop_depth = self.max_loop_depth.max(depth + 1);
+                    }
+                    Ok(())
+                }
+                _ => self.run_simple(expr),
             };
 
-            result?
+            result?;
+            self.trace(expr);
+        }
+
+        Ok(RunStatus::Finished)
+    }
+
+    /// Start watching the loop just entered at the given depth of the run's frames for a state it
+    /// has already been in, recording the state it was entered in. Loops whose bodies do input or
+    /// output are not watched, since they can change what happens next without changing the tape.
+    fn watch_for_hang(&mut self, depth: usize, body: &[Expr]) {
+        let states = (!does_io(body)).then(|| HashSet::from([self.state_hash()]));
+        self.hang_states.resize_with(depth, || None);
+        self.hang_states.push(states);
+    }
+
+    /// Stop a watched loop at the given depth that is about to repeat from a state it was in at the
+    /// top of an earlier iteration: it can only go on repeating the same iterations forever. States
+    /// are compared by their hashes to keep memory down.
+    fn check_hang(&mut self, depth: usize, token: Token) -> BrainfartResult<()> {
+        let hash = self.state_hash();
+        if let Some(Some(states)) = self.hang_states.get_mut(depth) {
+            if !states.insert(hash) {
+                return Err(BrainfartError::InfiniteLoop(token));
+            }
+        }
+        Ok(())
+    }
+
+    /// A hash of everything that decides what a program without input does next: both tapes, up to
+    /// their last nonzero cells, their data pointers, and which is active.
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        trimmed_cells(&self.data).hash(&mut hasher);
+        self.data_index.hash(&mut hasher);
+        trimmed_cells(&self.other_tape.0).hash(&mut hasher);
+        self.other_tape.1.hash(&mut hasher);
+        self.tape.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Run the trace hook, if any, for an Expr that has just run.
+    fn trace(&mut self, expr: &Expr) {
+        if let Some(hook) = &mut self.trace_hook {
+            hook(&TraceStep {
+                name: expr.ty.name(),
+                token: expr.tokens[0],
+                pointer: self.data_index,
+                value: self.data[self.data_index],
+            });
+        }
+    }
+
+    /// Run a single Expr other than an Input or a LoopBlock, which need to know how the program is
+    /// being run.
+    fn run_simple(&mut self, expr: &Expr) -> BrainfartResult<()> {
+        match &expr.ty {
+            ExprType::Set(val) => self.run_set(expr, *val),
+            ExprType::Add(val) => self.run_add(expr, *val),
+            ExprType::Sub(val) => self.run_sub(expr, *val),
+            ExprType::MoveRight(val) => self.run_move_right(expr, *val),
+            ExprType::MoveLeft(val) => self.run_move_left(expr, *val),
+            ExprType::Output(val) => self.run_output(expr, *val),
+            ExprType::OutputAt { offset } => self.run_output_at(expr, *offset),
+            ExprType::ScanRight(val) => self.run_scan_right(expr, *val),
+            ExprType::ScanLeft(val) => self.run_scan_left(expr, *val),
+            ExprType::LinearLoop(targets) => self.run_linear_loop(expr, targets),
+            ExprType::MoveCell { offset } => self.run_move_cell(expr, *offset),
+            ExprType::EmitConst(prefix) => self.run_emit_const(expr, prefix),
+            ExprType::SwitchTape(tape) => {
+                self.switch_tape(*tape);
+                Ok(())
+            }
+            ExprType::Input(_) | ExprType::InputAt { .. } | ExprType::LoopBlock(_) => {
+                unreachable!("inputs and loops are run by the caller")
+            }
+        }
+    }
+
+    /// Run Exprs as they are produced by the given iterator, such as an ExprStream, flushing output
+    /// after each one so that it appears before later Exprs have been read.
+    pub fn run_stream<I>(&mut self, exprs: I) -> BrainfartResult<()>
+    where
+        I: Iterator<Item = BrainfartResult<Expr>>,
+    {
+        for expr_result in exprs {
+            let expr = expr_result?;
+            self.run(slice::from_ref(&expr))?;
+            self.output
+                .flush()
+                .map_err(|_| BrainfartError::Output(expr.tokens[0]))?;
         }
 
         Ok(())
     }
 
-    /// Set the current pointer's location of this ProgState to the given value.
-    fn run_set(&mut self, val: u32) -> BrainfartResult<()> {
-        self.data[self.data_index] = val;
+    /// Run only the top-level Expr at the given position in the given Exprs, such as for a debugger
+    /// driving the program one Expr at a time. A LoopBlock is run until it exits. Input is waited
+    /// for as with `run`. Between steps, a debugger can try out other values with `write_cells`
+    /// and `set_data_index`, and the steps after see them.
+    pub fn step(&mut self, exprs: &[Expr], position: usize) -> BrainfartResult<StepOutcome> {
+        match exprs.get(position) {
+            Some(expr) => {
+                self.run(slice::from_ref(expr))?;
+                Ok(StepOutcome::Ran { next: position + 1 })
+            }
+            None => Ok(StepOutcome::Finished),
+        }
+    }
+
+    /// Set the current pointer's location of this ProgState to the given value. A value too
+    /// large for the cell is handled as if it had been added to zero, with an error pointing at the
+    /// first `+` that takes the cell past its maximum.
+    fn run_set(&mut self, expr: &Expr, val: u32) -> BrainfartResult<()> {
+        self.check_write(self.data_index, expr.tokens[0])?;
+        self.mark_written(self.data_index);
+        let max = self.config.cell_max();
+        let val = val as Cell;
+        if val <= max {
+            self.data[self.data_index] = val;
+            return Ok(());
+        }
+        self.report_overflow(CellOverflow::Over);
+        match self.config.arith {
+            ArithMode::Strict => {
+                let mut reached: Cell = 0;
+                let err_token = expr.tokens.iter().find(|token| {
+                    reached = match token.ty {
+                        TokenType::ValInc => reached + 1,
+                        _ => reached.saturating_sub(1),
+                    };
+                    reached > max
+                });
+                let err_token = *err_token.unwrap_or(&expr.tokens[expr.tokens.len() - 1]);
+                return Err(BrainfartError::ValMaxInc(err_token, max));
+            }
+            ArithMode::Wrapping => self.data[self.data_index] = val & max,
+            ArithMode::Saturating => self.data[self.data_index] = max,
+        }
         Ok(())
     }
 
     /// Add the given value to the current pointer's location of this ProgState.
-    fn run_add(&mut self, val: u32) -> BrainfartResult<()> {
-        self.data[self.data_index] += val;
+    fn run_add(&mut self, expr: &Expr, val: u32) -> BrainfartResult<()> {
+        self.check_write(self.data_index, expr.tokens[0])?;
+        self.mark_written(self.data_index);
+        let max = self.config.cell_max();
+        let curr_val = self.data[self.data_index];
+        match curr_val
+            .checked_add(val as Cell)
+            .filter(|&new_val| new_val <= max)
+        {
+            Some(new_val) => self.data[self.data_index] = new_val,
+            None => {
+                self.report_overflow(CellOverflow::Over);
+                match self.config.arith {
+                    ArithMode::Strict => {
+                        let err_token = expr.tokens[(max - curr_val) as usize];
+                        return Err(BrainfartError::ValMaxInc(err_token, max));
+                    }
+                    ArithMode::Wrapping => {
+                        self.data[self.data_index] = curr_val.wrapping_add(val as Cell) & max;
+                    }
+                    ArithMode::Saturating => self.data[self.data_index] = max,
+                }
+            }
+        }
         Ok(())
     }
 
     /// Subtract the given value from the current pointer's location of this ProgState.
     fn run_sub(&mut self, expr: &Expr, val: u32) -> BrainfartResult<()> {
+        self.check_write(self.data_index, expr.tokens[0])?;
+        self.mark_written(self.data_index);
         let curr_val = self.data[self.data_index];
-        if curr_val < val {
-            let err_token = expr.tokens[curr_val as usize];
-            Err(BrainfartError::ValZeroDec(err_token))
-        } else {
-            self.data[self.data_index] -= val;
-            Ok(())
+        match curr_val.checked_sub(val as Cell) {
+            Some(new_val) => self.data[self.data_index] = new_val,
+            None => {
+                self.report_overflow(CellOverflow::Under);
+                match self.config.arith {
+                    ArithMode::Strict => {
+                        let err_token = expr.tokens[curr_val as usize];
+                        return Err(BrainfartError::ValZeroDec(err_token));
+                    }
+                    ArithMode::Wrapping => {
+                        let max = self.config.cell_max();
+                        self.data[self.data_index] = curr_val.wrapping_sub(val as Cell) & max;
+                    }
+                    ArithMode::Saturating => self.data[self.data_index] = 0,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the overflow hook, if any, for the cell under the data pointer.
+    fn report_overflow(&mut self, overflow: CellOverflow) {
+        self.report_overflow_at(self.data_index, overflow);
+    }
+
+    /// Run the overflow hook, if any, for the cell at the given index.
+    fn report_overflow_at(&mut self, index: usize, overflow: CellOverflow) {
+        if let Some(hook) = &mut self.overflow_hook {
+            hook(index, overflow);
         }
     }
 
     /// Move the data pointer's location to the right the given number of times.
-    fn run_move_right(&mut self, val: u32) -> BrainfartResult<()> {
+    fn run_move_right(&mut self, expr: &Expr, val: u32) -> BrainfartResult<()> {
+        if let Some(size) = self.fixed_size() {
+            if self.data_index + val as usize >= size {
+                let err_token = expr.tokens[size.saturating_sub(self.data_index + 1)];
+                return Err(self.tape_limit(err_token));
+            }
+        }
+
+        let crossing = self.config.tape_report.saturating_sub(self.data_index + 1);
         self.data_index += val as usize;
+        self.grow_to_data_index();
+        self.check_tape_report(
+            self.data_index,
+            expr.tokens[crossing.min(expr.tokens.len() - 1)],
+        );
+        Ok(())
+    }
+
+    /// Record that the given Expr has run in the profile and coverage, if they are enabled.
+    fn record_run(&mut self, expr: &Expr) {
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(expr.ty.name()).or_insert(0) += 1;
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.insert(expr.tokens[0]);
+        }
+    }
+
+    /// Count a step of the program, reporting progress if it is due, and stop the program if that
+    /// passes the step limit or, checked every TIME_CHECK_INTERVAL steps, the time limit.
+    fn count_step(&mut self, token: Token) -> BrainfartResult<()> {
+        self.steps += 1;
+        if let (Some(hook), Some(started)) = (&mut self.progress_hook, self.progress_started) {
+            if self.steps.is_multiple_of(self.progress_every) {
+                hook(&Progress {
+                    steps: self.steps,
+                    pointer: self.data_index,
+                    elapsed: started.elapsed(),
+                });
+            }
+        }
+        let limits = self.config.limits;
+        if let Some(max_steps) = limits.steps {
+            if self.steps > max_steps {
+                return Err(BrainfartError::LimitExceeded {
+                    kind: LimitKind::Steps,
+                    value: max_steps,
+                    token: Some(token),
+                });
+            }
+        }
+        if let (Some(time), Some(started)) = (limits.time, self.started) {
+            if self.steps.is_multiple_of(TIME_CHECK_INTERVAL) && started.elapsed() > time {
+                return Err(BrainfartError::LimitExceeded {
+                    kind: LimitKind::Time,
+                    value: time.as_millis() as u64,
+                    token: Some(token),
+                });
+            }
+        }
+        Ok(())
+    }
 
-        if self.data_index >= self.data.capacity() {
-            let add_space: usize = self.data_index - self.data.len() + 1;
-            self.data.reserve(add_space);
-            self.data.resize(self.data.capacity(), 0);
+    /// Count bytes about to be output, stopping the program instead if they would pass the output
+    /// limit.
+    fn count_output(&mut self, len: usize, token: Token) -> BrainfartResult<()> {
+        self.output_count += len as u64;
+        match self.config.limits.output {
+            Some(max_output) if self.output_count > max_output => {
+                Err(BrainfartError::LimitExceeded {
+                    kind: LimitKind::Output,
+                    value: max_output,
+                    token: Some(token),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Warn, once, when a control character other than a newline or tab is first output, if the
+    /// config asks for it.
+    fn check_control_output(&mut self, c: char, token: Token) {
+        if self.config.warn_control_output
+            && !self.control_warned
+            && c.is_control()
+            && c != '\n'
+            && c != '\t'
+        {
+            self.control_warned = true;
+            self.warnings
+                .push(BrainfartWarning::ControlOutput(token, c as u32));
         }
+    }
 
+    /// Stop the program if the given cell is read-only at this point of the run.
+    fn check_write(&self, index: usize, token: Token) -> BrainfartResult<()> {
+        if let Some(readonly) = self.config.readonly {
+            let started = match readonly.from {
+                ReadOnlyStart::FirstOutput => self.output_count > 0,
+                ReadOnlyStart::Step(step) => self.steps > step,
+            };
+            if started && index < readonly.below {
+                return Err(BrainfartError::ReadOnlyWrite(token, index));
+            }
+        }
         Ok(())
     }
 
+    /// Record that the program wrote the given cell, if the config asks for warnings about cells
+    /// that were never written, and count the access in the heatmap.
+    fn mark_written(&mut self, index: usize) {
+        self.count_access(index);
+        if self.config.warn_uninit {
+            let word = index / 64;
+            if word >= self.written.len() {
+                self.written.resize(word + 1, 0);
+            }
+            self.written[word] |= 1 << (index % 64);
+        }
+    }
+
+    /// Warn, once, when a cell the program never wrote is first read under the data pointer, if
+    /// the config asks for it, and count the access in the heatmap.
+    fn check_uninit(&mut self, index: usize, token: Token) {
+        self.count_access(index);
+        if self.config.warn_uninit && !self.uninit_warned {
+            let written = self
+                .written
+                .get(index / 64)
+                .is_some_and(|word| word & (1 << (index % 64)) != 0);
+            if !written {
+                self.uninit_warned = true;
+                self.warnings
+                    .push(BrainfartWarning::UninitRead(token, index));
+            }
+        }
+    }
+
+    /// Count a read or write of the given cell, if the heatmap is enabled. Scans only look for a
+    /// zero cell, so the cells they pass over are not counted.
+    fn count_access(&mut self, index: usize) {
+        if let Some(heatmap) = &mut self.heatmap {
+            if index >= heatmap.len() {
+                heatmap.resize(index + 1, 0);
+            }
+            heatmap[index] += 1;
+        }
+    }
+
+    /// Warn, once, when the data pointer's location first passes the configured report size.
+    fn check_tape_report(&mut self, index: usize, token: Token) {
+        if !self.tape_warned && index >= self.config.tape_report {
+            self.tape_warned = true;
+            self.warnings
+                .push(BrainfartWarning::TapeSize(token, self.config.tape_report));
+        }
+    }
+
+    /// The error for moving past the end of a tape that cannot grow at the given token.
+    fn tape_limit(&self, token: Token) -> BrainfartError {
+        BrainfartError::LimitExceeded {
+            kind: LimitKind::Tape,
+            value: self.config.tape_size as u64,
+            token: Some(token),
+        }
+    }
+
+    /// The number of cells in the tape if it cannot grow.
+    fn fixed_size(&self) -> Option<usize> {
+        self.config.no_grow.then_some(self.config.tape_size)
+    }
+
+    /// Grow the cell array so that it contains the data pointer's location.
+    fn grow_to_data_index(&mut self) {
+        self.grow_to(self.data_index);
+    }
+
+    /// Grow the cell array so that it contains the given index, by the grow chunk if one is set.
+    fn grow_to(&mut self, index: usize) {
+        if index < self.data.len() {
+            return;
+        }
+        let add_space: usize = index - self.data.len() + 1;
+        match self.grow_chunk {
+            0 => {
+                self.data.reserve(add_space);
+                self.data.resize(self.data.capacity(), 0);
+            }
+            chunk => {
+                let new_len = self.data.len() + add_space.div_ceil(chunk) * chunk;
+                self.data.reserve_exact(new_len - self.data.len());
+                self.data.resize(new_len, 0);
+            }
+        }
+    }
+
     /// Move the data pointer's location to the left the given number of times.
     fn run_move_left(&mut self, expr: &Expr, val: u32) -> BrainfartResult<()> {
         let dec_val = val as usize;
@@ -91,52 +1028,1627 @@ impl ProgState {
         }
     }
 
-    /// Output the value at the current pointer's location the given number of times.
-    fn run_output(&mut self, val: u32) -> BrainfartResult<()> {
-        let char_val = self.data[self.data_index];
-        match char::from_u32(char_val) {
-            Some(c) => {
-                for _ in 0..val {
-                    print!("{}", c);
-                }
-            }
-            None => {
-                print!(" ");
+    /// Move the data pointer's location right by the given step until it reaches a cell holding
+    /// zero, growing the cell array if no such cell is found within it.
+    fn run_scan_right(&mut self, expr: &Expr, step: u32) -> BrainfartResult<()> {
+        let step = step as usize;
+        let cells = self.data[self.data_index..].iter().step_by(step);
+        let found = cells.clone().position(|&val| val == 0);
+        let moves = found.unwrap_or_else(|| cells.count());
+        if let Some(size) = self.fixed_size() {
+            if self.data_index + moves * step >= size {
+                let last = match size.checked_sub(self.data_index + 1) {
+                    Some(room) => self.data_index + room / step * step,
+                    None => size - 1,
+                };
+                let err_token = expr.tokens[size - last - 1];
+                return Err(self.tape_limit(err_token));
             }
-        };
+        }
+        self.data_index += moves * step;
+        self.grow_to_data_index();
+        self.check_tape_report(self.data_index, expr.tokens[0]);
         Ok(())
     }
 
-    /// Input a user-entered value into the current pointer's location the given number of times.
-    fn run_input(&mut self, expr: &Expr, val: u32) -> BrainfartResult<()> {
-        for _ in 0..val {
-            let mut input_string = String::new();
-            let read_result = io::stdin().read_line(&mut input_string);
-            match read_result {
-                Ok(_) => {
-                    let input = input_string.chars().next().unwrap();
-                    let cell_val = input as u32;
-                    self.data[self.data_index] = cell_val;
-                }
-                Err(_) => {
-                    let token = expr.tokens.get(0).unwrap();
-                    return Err(BrainfartError::Io(*token));
-                }
+    /// Move the data pointer's location left by the given step until it reaches a cell holding
+    /// zero, failing if the pointer would move past the first cell.
+    fn run_scan_left(&mut self, expr: &Expr, step: u32) -> BrainfartResult<()> {
+        let step = step as usize;
+        let mut cells = self.data[..=self.data_index].iter().rev().step_by(step);
+        match cells.position(|&val| val == 0) {
+            Some(moves) => {
+                self.data_index -= moves * step;
+                Ok(())
+            }
+            None => {
+                self.data_index %= step;
+                let err_token = expr.tokens[self.data_index];
+                Err(BrainfartError::PointZeroDec(err_token))
             }
         }
-        Ok(())
     }
 
-    /// Run the expressions contained in the LoopBlock, and keep looping while the current pointer
-    /// location does not equal zero after every iteration.
-    fn run_loop_block(&mut self, lb: &LoopBlock) -> BrainfartResult<()> {
-        loop {
-            if self.data[self.data_index] == 0 {
-                break;
-            }
-            let result = self.run(&lb.exprs);
-            result?
+    /// Output the value at the current pointer's location the given number of times, masked to the
+    /// configured cell width. Values that are not valid characters are handled according to the
+    /// configured InvalidOutput policy.
+    fn run_output(&mut self, expr: &Expr, val: u32) -> BrainfartResult<()> {
+        self.output_cell(self.data_index, &expr.tokens[..val as usize])
+    }
+
+    /// Output the value of the cell at the given offset from the pointer's location once, without
+    /// moving the pointer.
+    fn run_output_at(&mut self, expr: &Expr, offset: i64) -> BrainfartResult<()> {
+        let index = self.offset_index(expr, offset)?;
+        let token = body_token(expr, offset, TokenType::Output);
+        self.output_cell(index, slice::from_ref(&token))
+    }
+
+    /// Output the value of the cell at the given index once for each of the given tokens, which
+    /// errors point at. The cell is written as the UTF-8 encoding of the Unicode scalar value it
+    /// holds, or as the Config's InvalidOutput says if it holds none. With an OutputDelay, the sink
+    /// is flushed and the delay waited out after each one.
+    // Cell is already a u32 without the unbounded feature.
+    #[cfg_attr(
+        not(feature = "unbounded"),
+        allow(clippy::useless_conversion, clippy::unnecessary_cast)
+    )]
+    fn output_cell(&mut self, index: usize, tokens: &[Token]) -> BrainfartResult<()> {
+        self.check_uninit(index, tokens[0]);
+        let char_val = self.data[index] & self.config.cell_max();
+        let mut buf = [0; 4];
+        let c = u32::try_from(char_val).ok().and_then(char::from_u32);
+        if let Some(c) = c {
+            self.check_control_output(c, tokens[0]);
+        }
+        let bytes: &[u8] = match (c, self.config.invalid_output) {
+            (Some(c), _) => c.encode_utf8(&mut buf).as_bytes(),
+            (None, InvalidOutput::Space) => b" ",
+            (None, InvalidOutput::Replace) => {
+                char::REPLACEMENT_CHARACTER.encode_utf8(&mut buf).as_bytes()
+            }
+            (None, InvalidOutput::Bytes) => {
+                buf = (char_val as u32).to_le_bytes();
+                &buf
+            }
+            (None, InvalidOutput::Error) => {
+                return Err(BrainfartError::InvalidChar(tokens[0], char_val))
+            }
+        };
+
+        let Some(delay) = self.config.output_delay else {
+            return self.write_output(bytes, tokens);
+        };
+        for &token in tokens {
+            self.write_output(bytes, slice::from_ref(&token))?;
+            self.output
+                .flush()
+                .map_err(|_| BrainfartError::Output(token))?;
+            thread::sleep(delay.after(bytes));
+        }
+        Ok(())
+    }
+
+    /// Write the given bytes once for each of the given tokens, which errors point at. The
+    /// repeated bytes are handed to the sink in as few writes as it accepts.
+    fn write_output(&mut self, bytes: &[u8], tokens: &[Token]) -> BrainfartResult<()> {
+        let fit = match self.config.limits.output {
+            Some(max_output) => {
+                let left = max_output.saturating_sub(self.output_count) / bytes.len() as u64;
+                tokens.len().min(left as usize)
+            }
+            None => tokens.len(),
+        };
+        let repeated;
+        let all_bytes = match fit {
+            1 => bytes,
+            _ => {
+                repeated = bytes.repeat(fit);
+                &repeated
+            }
+        };
+
+        let mut written = 0;
+        while written < all_bytes.len() {
+            let token = tokens[written / bytes.len()];
+            match self.output.write(&all_bytes[written..]) {
+                Ok(0) => return Err(BrainfartError::Output(token)),
+                Ok(len) => written += len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => return Err(BrainfartError::Output(token)),
+            }
+        }
+        self.output_count += all_bytes.len() as u64;
+        if let Some(hook) = &mut self.output_hook {
+            all_bytes.iter().for_each(|&byte| hook(byte));
+        }
+
+        match tokens.get(fit) {
+            Some(&token) => self.count_output(bytes.len(), token),
+            None => Ok(()),
+        }
+    }
+
+    /// Input a user-entered value into the current pointer's location the given number of times.
+    /// At the end of input the cell is left unchanged. If an EOF flag cell is configured, it is set
+    /// to 1 after each read that hit the end of input and to 0 otherwise.
+    /// Returns false without finishing if input has to be waited for and `blocking` is not set;
+    /// running the Expr again picks up from the read it stopped at.
+    fn run_input(&mut self, expr: &Expr, val: u32, blocking: bool) -> BrainfartResult<bool> {
+        self.input_cell(self.data_index, &expr.tokens[..val as usize], blocking)
+    }
+
+    /// Input a user-entered value into the cell at the given offset from the pointer's location
+    /// once, without moving the pointer, like `run_input` otherwise.
+    fn run_input_at(&mut self, expr: &Expr, offset: i64, blocking: bool) -> BrainfartResult<bool> {
+        let index = self.offset_index(expr, offset)?;
+        let token = body_token(expr, offset, TokenType::Input);
+        self.input_cell(index, slice::from_ref(&token), blocking)
+    }
+
+    /// Run an Input or an InputAt, returning whether it finished as `run_input` does.
+    fn run_any_input(&mut self, expr: &Expr, blocking: bool) -> BrainfartResult<bool> {
+        match &expr.ty {
+            ExprType::Input(val) => self.run_input(expr, *val, blocking),
+            ExprType::InputAt { offset } => self.run_input_at(expr, *offset, blocking),
+            _ => unreachable!("only inputs read input"),
+        }
+    }
+
+    /// Input a user-entered value into the cell at the given index once for each of the given
+    /// tokens, which errors point at, picking up from the read an earlier call stopped at.
+    fn input_cell(
+        &mut self,
+        index: usize,
+        tokens: &[Token],
+        blocking: bool,
+    ) -> BrainfartResult<bool> {
+        for (i, &token) in tokens.iter().enumerate().skip(self.input_progress) {
+            let read_result = match self.read_input(blocking) {
+                Some(read_result) => read_result,
+                None => {
+                    self.input_progress = i;
+                    return Ok(false);
+                }
+            };
+            match read_result {
+                Ok(input) => {
+                    if let Some(input) = input {
+                        self.check_write(index, token)?;
+                        let cell_val = input as Cell & self.config.cell_max();
+                        self.data[index] = cell_val;
+                        self.mark_written(index);
+                    }
+                    if let Some(flag_index) = self.config.eof_flag_cell {
+                        self.grow_to(flag_index);
+                        self.check_write(flag_index, token)?;
+                        self.data[flag_index] = input.is_none() as Cell;
+                        self.mark_written(flag_index);
+                    }
+                }
+                Err(_) => return Err(BrainfartError::Io(token)),
+            }
+        }
+        self.input_progress = 0;
+        Ok(true)
+    }
+
+    /// Read the next input character, or None for the end of input. From a reader, this is the
+    /// first whole character of the next line, however many bytes of UTF-8 it takes, so that `,`
+    /// and `.` round-trip any character; the input channel gives one byte per read instead.
+    /// Returns None instead if the input channel has nothing waiting and `blocking` is not set.
+    fn read_input(&mut self, blocking: bool) -> Option<io::Result<Option<char>>> {
+        match &self.input_channel {
+            Some(channel) if blocking => Some(Ok(channel.recv().ok().map(char::from))),
+            Some(channel) => match channel.try_recv() {
+                Ok(byte) => Some(Ok(Some(char::from(byte)))),
+                Err(TryRecvError::Disconnected) => Some(Ok(None)),
+                Err(TryRecvError::Empty) => None,
+            },
+            None => {
+                let mut input_string = String::new();
+                Some(
+                    self.input
+                        .read_line(&mut input_string)
+                        .map(|_| input_string.chars().next()),
+                )
+            }
+        }
+    }
+
+    /// Make the tape with the given index the active one, swapping it in from `other_tape`. The
+    /// second tape starts out like the first, empty or fixed at the tape size, the first time it
+    /// is switched to.
+    fn switch_tape(&mut self, tape: usize) {
+        if tape == self.tape {
+            return;
+        }
+        if self.other_tape.0.is_empty() {
+            let mut data: Vec<Cell> = vec![0];
+            data.resize(data.capacity(), 0);
+            if self.config.no_grow {
+                data.resize(self.config.tape_size, 0);
+            }
+            self.other_tape = (data, 0);
+        }
+        mem::swap(&mut self.data, &mut self.other_tape.0);
+        mem::swap(&mut self.data_index, &mut self.other_tape.1);
+        mem::swap(&mut self.written, &mut self.other_written);
+        self.tape = tape;
+    }
+
+    /// Write the precomputed output of a ConstPrefix and copy its cells onto the tape from the
+    /// current cell on, then move the data pointer to where the prefix left it. Output that ends a
+    /// line is flushed straight away, so a constant line shows up at once even through a buffered
+    /// sink.
+    fn run_emit_const(&mut self, expr: &Expr, prefix: &ConstPrefix) -> BrainfartResult<()> {
+        self.count_output(prefix.bytes.len(), expr.tokens[0])?;
+        self.output
+            .write_all(&prefix.bytes)
+            .map_err(|_| BrainfartError::Output(expr.tokens[0]))?;
+        if prefix.bytes.ends_with(b"\n") {
+            self.output
+                .flush()
+                .map_err(|_| BrainfartError::Output(expr.tokens[0]))?;
+        }
+        if let Some(hook) = &mut self.output_hook {
+            prefix.bytes.iter().for_each(|&byte| hook(byte));
+        }
+
+        let start = self.data_index;
+        for index in start..start + prefix.cells.len() {
+            self.check_write(index, expr.tokens[0])?;
+        }
+        self.grow_to(start + prefix.cells.len());
+        for (cell, &val) in self.data[start..].iter_mut().zip(&prefix.cells) {
+            *cell = val as Cell;
+        }
+        (start..start + prefix.cells.len()).for_each(|index| self.mark_written(index));
+        self.data_index += prefix.pointer;
+        self.grow_to_data_index();
+        Ok(())
+    }
+
+    /// The index of the cell at the given offset from the pointer's location, for an Expr whose
+    /// tokens move there, growing the cell array to contain it. Errors point at the move in the
+    /// Expr's tokens that would have left the tape.
+    fn offset_index(&mut self, expr: &Expr, offset: i64) -> BrainfartResult<usize> {
+        let index = self.data_index as i64 + offset;
+        if index < 0 {
+            let err_token = body_token(expr, -(self.data_index as i64) - 1, TokenType::PointDec);
+            return Err(BrainfartError::PointZeroDec(err_token));
+        }
+
+        let index = index as usize;
+        if let Some(size) = self.fixed_size() {
+            if index >= size {
+                let reach = (size - self.data_index) as i64;
+                let err_token = body_token(expr, reach, TokenType::PointInc);
+                return Err(self.tape_limit(err_token));
+            }
+        }
+        self.grow_to(index);
+        self.check_tape_report(index, body_token(expr, offset, TokenType::PointInc));
+        Ok(index)
+    }
+
+    /// Add the current cell times each factor to the cell at each offset, then set the current cell
+    /// to zero. Errors point at the token in the loop body that would have caused them.
+    fn run_linear_loop(&mut self, expr: &Expr, targets: &[(i64, i64)]) -> BrainfartResult<()> {
+        self.check_uninit(self.data_index, expr.tokens[0]);
+        let val = self.data[self.data_index];
+        if val == 0 {
+            return Ok(());
+        }
+
+        for &(offset, factor) in targets {
+            let index = self.offset_index(expr, offset)?;
+            let write_ty = if factor < 0 {
+                TokenType::ValDec
+            } else {
+                TokenType::ValInc
+            };
+            self.check_write(index, body_token(expr, offset, write_ty))?;
+            self.mark_written(index);
+
+            let max = self.config.cell_max();
+            let curr_val = self.data[index];
+            let change = val.checked_mul(factor.unsigned_abs() as Cell);
+            let new_val = match change {
+                Some(change) if factor < 0 => curr_val.checked_sub(change),
+                Some(change) => curr_val
+                    .checked_add(change)
+                    .filter(|&new_val| new_val <= max),
+                None => None,
+            };
+            match new_val {
+                Some(new_val) => self.data[index] = new_val,
+                None => {
+                    let overflow = if factor < 0 {
+                        CellOverflow::Under
+                    } else {
+                        CellOverflow::Over
+                    };
+                    self.report_overflow_at(index, overflow);
+                    match (self.config.arith, overflow) {
+                        (ArithMode::Strict, CellOverflow::Under) => {
+                            let err_token = body_token(expr, offset, TokenType::ValDec);
+                            return Err(BrainfartError::ValZeroDec(err_token));
+                        }
+                        (ArithMode::Strict, CellOverflow::Over) => {
+                            let err_token = body_token(expr, offset, TokenType::ValInc);
+                            return Err(BrainfartError::ValMaxInc(err_token, max));
+                        }
+                        (ArithMode::Wrapping, _) => {
+                            let change = val.wrapping_mul(factor.unsigned_abs() as Cell);
+                            self.data[index] = match overflow {
+                                CellOverflow::Under => curr_val.wrapping_sub(change) & max,
+                                CellOverflow::Over => curr_val.wrapping_add(change) & max,
+                            };
+                        }
+                        (ArithMode::Saturating, CellOverflow::Under) => self.data[index] = 0,
+                        (ArithMode::Saturating, CellOverflow::Over) => self.data[index] = max,
+                    }
+                }
+            }
+        }
+
+        self.check_write(self.data_index, body_token(expr, 0, TokenType::ValDec))?;
+        self.data[self.data_index] = 0;
+        self.mark_written(self.data_index);
+        Ok(())
+    }
+
+    /// Add the current cell to the cell at the offset, then set the current cell to zero, as the
+    /// LinearLoop with a single factor of 1 does.
+    fn run_move_cell(&mut self, expr: &Expr, offset: i64) -> BrainfartResult<()> {
+        self.run_linear_loop(expr, &[(offset, 1)])
+    }
+}
+
+/// Whether any of the given Exprs, including those inside loops, does input or output.
+fn does_io(exprs: &[Expr]) -> bool {
+    let mut pending: Vec<&[Expr]> = vec![exprs];
+    while let Some(body) = pending.pop() {
+        for expr in body {
+            match &expr.ty {
+                ExprType::Output(_)
+                | ExprType::Input(_)
+                | ExprType::OutputAt { .. }
+                | ExprType::InputAt { .. }
+                | ExprType::EmitConst(_) => return true,
+                ExprType::LoopBlock(lb) => pending.push(&lb.exprs),
+                _ => (),
+            }
+        }
+    }
+    false
+}
+
+/// The cells of a tape up to its last nonzero one.
+fn trimmed_cells(data: &[Cell]) -> &[Cell] {
+    let len = data.iter().rposition(|&val| val != 0).map_or(0, |i| i + 1);
+    &data[..len]
+}
+
+/// The cells of a tape worth saving in a Checkpoint: those up to the last nonzero one or the data
+/// pointer, whichever is further.
+fn saved_cells(data: &[Cell], data_index: usize) -> &[Cell] {
+    let len = data
+        .iter()
+        .rposition(|&val| val != 0)
+        .map_or(0, |i| i + 1)
+        .max(data_index + 1);
+    &data[..len]
+}
+
+/// Find the first token in a folded loop's body of the given type that is at the given offset from
+/// where the body starts, falling back to the body's first token.
+fn body_token(expr: &Expr, offset: i64, ty: TokenType) -> Token {
+    let mut curr_offset: i64 = 0;
+    for token in &expr.tokens {
+        match token.ty {
+            TokenType::PointInc => curr_offset += 1,
+            TokenType::PointDec => curr_offset -= 1,
+            _ => (),
+        }
+        if curr_offset == offset && token.ty == ty {
+            return *token;
+        }
+    }
+    expr.tokens[0]
+}
+
+impl<W: Write + Debug> Debug for ProgState<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgState")
+            .field("data", &self.data)
+            .field("data_index", &self.data_index)
+            .field("output", &self.output)
+            .field("config", &self.config)
+            .field("overflow_hook", &self.overflow_hook.is_some())
+            .field("output_hook", &self.output_hook.is_some())
+            .field("trace_hook", &self.trace_hook.is_some())
+            .field("warnings", &self.warnings)
+            .field("profile", &self.profile)
+            .field("max_loop_depth", &self.max_loop_depth)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::Checkpoint;
+    use crate::config::{
+        ArithMode, Cell, CellWidth, Config, InvalidOutput, Limits, OutputDelay, ReadOnly,
+        ReadOnlyStart,
+    };
+    use crate::error::{BrainfartError, BrainfartWarning, LimitKind};
+    use crate::expr::{Expr, ExprType};
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use crate::generator::generate;
+    use crate::lexer::{lex_string, CommandSet, Lang, Lexer};
+    use crate::parser::{parse_tokens, parse_tokens_with, ExprStream, ParseOptions};
+    use crate::prefix::fold_const_prefix;
+    use crate::progstate::{
+        CellOverflow, Execution, ProgState, RunStatus, StepOutcome, SuspendReason,
+    };
+    use crate::token::{Token, TokenType};
+
+    /// Run a program that sets the first cell to a surrogate code point and outputs it twice,
+    /// returning the bytes written.
+    fn output_surrogate(invalid_output: InvalidOutput) -> Result<Vec<u8>, BrainfartError> {
+        let exprs = vec![
+            Expr {
+                ty: ExprType::Set(0xD800),
+                tokens: vec![Token::from(TokenType::ValInc, 1, 1)],
+            },
+            Expr {
+                ty: ExprType::Output(2),
+                tokens: vec![
+                    Token::from(TokenType::Output, 1, 2),
+                    Token::from(TokenType::Output, 1, 3),
+                ],
+            },
+        ];
+        let mut output: Vec<u8> = vec![];
+        let mut state = ProgState::with_output(&mut output);
+        state.set_config(Config {
+            invalid_output,
+            ..Config::default()
+        });
+        state.run(&exprs)?;
+        Ok(output)
+    }
+
+    /// An output sink whose contents can be inspected while a ProgState is writing to it.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unicode_round_trip() {
+        let exprs = parse_tokens(lex_string(",.>,.>,.".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_input(Cursor::new("é\n€uro\n😀\n"));
+        state.run(&exprs).unwrap();
+        assert_eq!(state.data[..3], [0xE9, 0x20AC, 0x1F600]);
+        assert_eq!(state.output(), "é€😀".as_bytes());
+    }
+
+    #[test]
+    fn run_stream_output_before_end() {
+        let output = SharedOutput::default();
+        let seen = output.clone();
+        let fast = "++++++++[>++++++++<-]>+.>".chars();
+        let slow = "+."
+            .chars()
+            .inspect(move |_| assert_eq!(*seen.0.borrow(), b"A"));
+
+        let mut state = ProgState::with_output(output.clone());
+        state
+            .run_stream(ExprStream::new(Lexer::new(fast.chain(slow))))
+            .unwrap();
+        assert_eq!(*output.0.borrow(), b"A\x01");
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let setup = parse_tokens(lex_string("++>+++>".to_string()).unwrap()).unwrap();
+        let mutate = parse_tokens(lex_string("[-]<<+>>>>>+".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.run(&setup).unwrap();
+
+        let snap = state.snapshot();
+        state.run(&mutate).unwrap();
+        assert_ne!(state.snapshot(), snap);
+
+        state.restore(&snap);
+        assert_eq!(state.snapshot(), snap);
+    }
+
+    fn run_source<W: Write>(state: &mut ProgState<W>, source: &str) -> Result<(), BrainfartError> {
+        state.run(&parse_tokens(lex_string(source.to_string()).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn scan_right_step() {
+        let mut state = ProgState::with_output(vec![]);
+        run_source(&mut state, "+>>+>+<<<[>>]").unwrap();
+        assert_eq!(state.data_index, 4);
+        run_source(&mut state, "<<<<[>>>]").unwrap();
+        assert_eq!(state.data_index, 6);
+    }
+
+    #[test]
+    fn scan_right_grows() {
+        let mut state = ProgState::with_output(vec![]);
+        let len = state.data.len();
+        state.data.iter_mut().for_each(|val| *val = 1);
+        run_source(&mut state, "[>>>]+").unwrap();
+        assert_eq!(state.data_index, len.div_ceil(3) * 3);
+        assert_eq!(state.current(), 1);
+    }
+
+    #[test]
+    fn scan_left_step() {
+        let mut state = ProgState::with_output(vec![]);
+        run_source(&mut state, ">+>>+>+>>+[<<]").unwrap();
+        assert_eq!(state.data_index, 2);
+    }
+
+    #[test]
+    fn scan_left_past_start() {
+        let mut state = ProgState::with_output(vec![]);
+        match run_source(&mut state, "+>>>+>>>+[<<<]") {
+            Err(BrainfartError::PointZeroDec(tok)) => {
+                assert_eq!(tok, Token::from(TokenType::PointDec, 1, 11))
+            }
+            other => panic!("scan_left_past_start had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn current_cell() {
+        let exprs =
+            parse_tokens(lex_string("+++>++++++[<+++++++>-]<".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.run(&exprs).unwrap();
+        assert_eq!(state.current(), 45);
+    }
+
+    #[test]
+    fn start_index_move_left() {
+        let exprs = parse_tokens(lex_string("<<<<<+".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_data_index(5);
+        state.run(&exprs).unwrap();
+
+        let exprs = parse_tokens(lex_string("<".to_string()).unwrap()).unwrap();
+        match state.run(&exprs) {
+            Err(BrainfartError::PointZeroDec(tok)) => {
+                assert_eq!(tok, Token::from(TokenType::PointDec, 1, 1))
+            }
+            other => panic!("start_index_move_left had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_output_space() {
+        assert_eq!(output_surrogate(InvalidOutput::Space).unwrap(), b"  ");
+    }
+
+    #[test]
+    fn invalid_output_replace() {
+        assert_eq!(
+            output_surrogate(InvalidOutput::Replace).unwrap(),
+            "\u{FFFD}\u{FFFD}".as_bytes()
+        );
+    }
+
+    #[test]
+    fn invalid_output_bytes() {
+        assert_eq!(
+            output_surrogate(InvalidOutput::Bytes).unwrap(),
+            [0x00, 0xD8, 0x00, 0x00, 0x00, 0xD8, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn invalid_output_error() {
+        match output_surrogate(InvalidOutput::Error) {
+            Err(BrainfartError::InvalidChar(tok, val)) => {
+                assert_eq!(tok, Token::from(TokenType::Output, 1, 2));
+                assert_eq!(val, 0xD800);
+            }
+            other => panic!("invalid_output_error had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn byte_cells_mask_output() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            cell_size: CellWidth::Bits8,
+            ..Config::default()
+        });
+        state.data[0] = 300;
+        run_source(&mut state, ".").unwrap();
+        assert_eq!(state.output(), &[44]);
+    }
+
+    #[test]
+    fn overflow_hook_wrapping() {
+        let events = Rc::new(RefCell::new(vec![]));
+        let seen = events.clone();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            arith: ArithMode::Wrapping,
+            ..Config::default()
+        });
+        state.set_overflow_hook(move |index, overflow| seen.borrow_mut().push((index, overflow)));
+
+        run_source(&mut state, "+>>-<+").unwrap();
+        state.set_data_index(3);
+        state.data[3] = u32::MAX as Cell;
+        run_source(&mut state, "++").unwrap();
+
+        assert_eq!(state.current(), 1);
+        assert_eq!(state.data[2], u32::MAX as Cell);
+        assert_eq!(
+            *events.borrow(),
+            vec![(2, CellOverflow::Under), (3, CellOverflow::Over)]
+        );
+    }
+
+    #[cfg(feature = "unbounded")]
+    #[test]
+    fn unbounded_cells() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            unbounded_cells: true,
+            ..Config::default()
+        });
+        state.data[0] = u32::MAX as Cell;
+        run_source(&mut state, "[->++<]>+").unwrap();
+        assert_eq!(state.current(), 2 * u32::MAX as Cell + 1);
+        assert_eq!(state.tape_dump().cell_width, 128);
+
+        state.data[0] = u32::MAX as Cell;
+        run_source(&mut state, "<+").unwrap();
+        assert_eq!(state.current(), 1 << 32);
+    }
+
+    #[test]
+    fn add_overflow_strict() {
+        let mut state = ProgState::with_output(vec![]);
+        state.data[0] = u32::MAX as Cell - 1;
+        match run_source(&mut state, "+++") {
+            Err(BrainfartError::ValMaxInc(tok, _)) => {
+                assert_eq!(tok, Token::from(TokenType::ValInc, 1, 2))
+            }
+            other => panic!("add_overflow_strict had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn output_hook_bytes() {
+        let bytes = Rc::new(RefCell::new(vec![]));
+        let seen = bytes.clone();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_output_hook(move |byte| seen.borrow_mut().push(byte));
+        state.data[0] = 'é' as Cell;
+        run_source(&mut state, "..").unwrap();
+        assert_eq!(*bytes.borrow(), "éé".as_bytes());
+        assert_eq!(*bytes.borrow(), *state.output());
+    }
+
+    #[test]
+    fn tape_report_once() {
+        let mut state = ProgState::with_output(vec![]);
+        run_source(&mut state, &">".repeat(29999)).unwrap();
+        assert_eq!(state.take_warnings(), vec![]);
+
+        run_source(&mut state, ">>>+[-<]<<>>>>").unwrap();
+        assert_eq!(
+            state.take_warnings(),
+            vec![BrainfartWarning::TapeSize(
+                Token::from(TokenType::PointInc, 1, 1),
+                30000
+            )]
+        );
+        run_source(&mut state, &">".repeat(100)).unwrap();
+        assert_eq!(state.take_warnings(), vec![]);
+    }
+
+    #[test]
+    fn sub_error_token() {
+        let mut state = ProgState::with_output(vec![]);
+        state.data[0] = 2;
+        match run_source(&mut state, "\n --\n--") {
+            Err(BrainfartError::ValZeroDec(tok)) => {
+                assert_eq!(tok, Token::from(TokenType::ValDec, 3, 1))
+            }
+            other => panic!("sub_error_token had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn move_left_error_token() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_data_index(2);
+        match run_source(&mut state, "< <<<") {
+            Err(BrainfartError::PointZeroDec(tok)) => {
+                assert_eq!(tok, Token::from(TokenType::PointDec, 1, 4))
+            }
+            other => panic!("move_left_error_token had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn add_error_token() {
+        let mut state = ProgState::with_output(vec![]);
+        state.data[0] = u32::MAX as Cell - 3;
+        match run_source(&mut state, "++ +++-+") {
+            Err(BrainfartError::ValMaxInc(tok, _)) => {
+                assert_eq!(tok, Token::from(TokenType::ValInc, 1, 5))
+            }
+            other => panic!("add_error_token had result {:?}", other),
+        }
+    }
+
+    /// An output sink that fails once it has accepted the given number of bytes.
+    struct FailAfter(usize);
+
+    impl Write for FailAfter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::other("sink full"));
+            }
+            let len = buf.len().min(self.0);
+            self.0 -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// An output sink that records the length of each write it is given.
+    #[derive(Default)]
+    struct WriteLens(Vec<usize>);
+
+    impl Write for WriteLens {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_single_write() {
+        let source = format!("{}{}", "+".repeat(65), ".".repeat(1000));
+        let exprs = parse_tokens(lex_string(source).unwrap()).unwrap();
+        assert_eq!(exprs[1].ty, ExprType::Output(1000));
+        let mut state = ProgState::with_output(WriteLens::default());
+        state.run(&exprs).unwrap();
+        assert_eq!(state.output().0, vec![1000]);
+    }
+
+    /// An output sink that keeps the bytes written to it and how many there were at each flush.
+    #[derive(Default)]
+    struct Flushes {
+        bytes: Vec<u8>,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed_at.push(self.bytes.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn emit_const_line_flushes() {
+        let exprs = parse_tokens(lex_string(generate("OK\n")).unwrap()).unwrap();
+        let exprs = fold_const_prefix(exprs, &Config::default(), 0);
+        let [Expr {
+            ty: ExprType::EmitConst(prefix),
+            ..
+        }] = &exprs[..]
+        else {
+            panic!("emit_const_line_flushes folded to {:?}", exprs);
+        };
+        assert_eq!(prefix.bytes, b"OK\n");
+
+        let mut state = ProgState::with_output(Flushes::default());
+        state.run(&exprs).unwrap();
+        assert_eq!(state.output().bytes, b"OK\n");
+        assert_eq!(state.output().flushed_at, vec![3]);
+    }
+
+    #[test]
+    fn sub_from_zero_every_arith_mode() {
+        for (arith, result) in [
+            (ArithMode::Strict, None),
+            (ArithMode::Wrapping, Some(u32::MAX as Cell)),
+            (ArithMode::Saturating, Some(0)),
+        ] {
+            let mut state = ProgState::with_output(vec![]);
+            state.set_config(Config {
+                arith,
+                ..Config::default()
+            });
+            match run_source(&mut state, "-") {
+                Ok(()) => assert_eq!(Some(state.current()), result),
+                Err(e) => {
+                    assert_eq!(result, None);
+                    assert!(matches!(e, BrainfartError::ValZeroDec(_)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapping_byte_cells() {
+        let byte_cells = |arith| Config {
+            cell_size: CellWidth::Bits8,
+            arith,
+            ..Config::default()
+        };
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(byte_cells(ArithMode::Wrapping));
+        run_source(&mut state, &"+".repeat(256)).unwrap();
+        assert_eq!(state.current(), 0);
+        run_source(&mut state, "-").unwrap();
+        assert_eq!(state.current(), 255);
+
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(byte_cells(ArithMode::Strict));
+        assert!(matches!(
+            run_source(&mut state, "-"),
+            Err(BrainfartError::ValZeroDec(_))
+        ));
+        assert!(matches!(
+            run_source(&mut state, &"+".repeat(256)),
+            Err(BrainfartError::ValMaxInc(tok, 255)) if tok.col == 256
+        ));
+    }
+
+    #[test]
+    fn strict_byte_cells_set_overflow_token() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            cell_size: CellWidth::Bits8,
+            ..Config::default()
+        });
+        let source = format!("[-]{}-", "+".repeat(257));
+        assert!(matches!(
+            run_source(&mut state, &source),
+            Err(BrainfartError::ValMaxInc(tok, 255)) if tok.col == 259
+        ));
+    }
+
+    #[test]
+    fn strict_byte_cells_overflow_not_cancelled() {
+        let config = Config {
+            cell_size: CellWidth::Bits8,
+            ..Config::default()
+        };
+        let source = format!("{}-", "+".repeat(256));
+        let tokens = lex_string(source).unwrap();
+        let exprs = parse_tokens_with(tokens, &ParseOptions::for_config(&config)).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(config);
+        assert!(matches!(
+            state.run(&exprs),
+            Err(BrainfartError::ValMaxInc(tok, 255)) if tok.col == 256
+        ));
+    }
+
+    #[test]
+    fn cell_widths() {
+        for (width, expected) in [
+            (CellWidth::Bits8, 0),
+            (CellWidth::Bits16, 256),
+            (CellWidth::Bits32, 256),
+        ] {
+            let mut state = ProgState::with_cell_width(width);
+            assert_eq!(state.config.arith, ArithMode::Strict);
+            state.set_config(Config {
+                arith: ArithMode::Wrapping,
+                ..state.config.clone()
+            });
+            run_source(&mut state, &"+".repeat(256)).unwrap();
+            assert_eq!(state.current(), expected);
+            assert_eq!(state.tape_dump().cell_width, width.bits());
+        }
+
+        let mut state = ProgState::with_cell_width(CellWidth::Bits8);
+        assert!(matches!(
+            run_source(&mut state, &"+".repeat(256)),
+            Err(BrainfartError::ValMaxInc(_, 255))
+        ));
+    }
+
+    #[test]
+    fn strict_config_byte_cells() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config::strict());
+        run_source(&mut state, "->[-]++++++++++++++++[<++++++++++++++++>-]<+").unwrap();
+        assert_eq!(state.current(), 0);
+        run_source(&mut state, &format!("[-]{}", "+".repeat(300))).unwrap();
+        assert_eq!(state.current(), 44);
+        assert_eq!(state.tape_dump().cell_width, 8);
+    }
+
+    #[test]
+    fn saturating_arith() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            arith: ArithMode::Saturating,
+            ..Config::default()
+        });
+        state.write_cells(0, [u32::MAX as Cell - 1, 3]);
+        run_source(&mut state, "+++>[-<+>>----<]>").unwrap();
+        assert_eq!(state.data[..3], [u32::MAX as Cell, 0, 0]);
+    }
+
+    #[test]
+    fn clear_from_max_every_arith_mode() {
+        for arith in [
+            ArithMode::Strict,
+            ArithMode::Wrapping,
+            ArithMode::Saturating,
+        ] {
+            for unbounded_cells in [false, true] {
+                let config = Config {
+                    arith,
+                    unbounded_cells,
+                    ..Config::default()
+                };
+                let mut state = ProgState::with_output(vec![]);
+                state.write_cells(0, [config.cell_max()]);
+                state.set_config(config);
+                run_source(&mut state, "[-]").unwrap();
+                assert_eq!(state.current(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn heatmap_hottest_cell() {
+        let mut state = ProgState::with_output(vec![]);
+        state.enable_heatmap();
+        run_source(&mut state, "+>+>+++[.-]").unwrap();
+        let heatmap = state.take_heatmap().unwrap();
+        let hottest = (0..heatmap.len()).max_by_key(|&i| heatmap[i]);
+        assert_eq!(hottest, Some(2));
+        assert_eq!(heatmap, [1, 1, 11]);
+        assert_eq!(state.take_heatmap(), Some(vec![]));
+    }
+
+    #[test]
+    fn progress_every_steps() {
+        let reports = Rc::new(RefCell::new(vec![]));
+        let seen = reports.clone();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_progress_hook(10, move |progress| seen.borrow_mut().push(*progress));
+        run_source(&mut state, "++++++[>++++++++<-.]>+.").unwrap();
+
+        let reports = reports.borrow();
+        assert_eq!(
+            reports.iter().map(|p| p.steps).collect::<Vec<_>>(),
+            (1..=state.steps / 10).map(|i| i * 10).collect::<Vec<_>>()
+        );
+        assert!(reports.len() >= 3);
+        assert!(reports.iter().all(|p| p.pointer <= 1));
+        assert_eq!(state.output(), &[5, 4, 3, 2, 1, 0, b'1']);
+        assert!(reports[0]
+            .to_string()
+            .starts_with("PROGRESS 10 steps, pointer "));
+    }
+
+    #[test]
+    fn output_delay() {
+        let delay = OutputDelay {
+            duration: Duration::from_millis(1),
+            per_line: false,
+        };
+        assert_eq!(delay.after(b"ab\n"), Duration::from_millis(3));
+        let per_line = OutputDelay {
+            per_line: true,
+            ..delay
+        };
+        assert_eq!(per_line.after(b"ab\n"), Duration::from_millis(1));
+        assert_eq!(per_line.after(b"ab"), Duration::ZERO);
+
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            output_delay: Some(delay),
+            ..Config::default()
+        });
+        let started = Instant::now();
+        run_source(&mut state, &format!("{}...", "+".repeat(65))).unwrap();
+        assert_eq!(state.output(), b"AAA");
+        assert!(started.elapsed() >= Duration::from_millis(3));
+    }
+
+    #[test]
+    fn output_error_token() {
+        let exprs = parse_tokens(lex_string("+....".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(FailAfter(2));
+        match state.run(&exprs) {
+            Err(BrainfartError::Output(tok)) => {
+                assert_eq!(tok, Token::from(TokenType::Output, 1, 4))
+            }
+            other => panic!("output_error_token had result {:?}", other),
+        }
+    }
+
+    /// A ProgState whose tape is fixed at 10 cells.
+    fn fixed_tape_state() -> ProgState<Vec<u8>> {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            no_grow: true,
+            tape_size: 10,
+            ..Config::default()
+        });
+        state
+    }
+
+    #[test]
+    fn no_grow_move_right() {
+        let mut state = fixed_tape_state();
+        run_source(&mut state, ">>>>>>>>>").unwrap();
+        assert_eq!(state.data_index, 9);
+
+        let mut state = fixed_tape_state();
+        match run_source(&mut state, ">>>>>>>>\n>>>") {
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Tape,
+                value: 10,
+                token: Some(tok),
+            }) => {
+                assert_eq!(tok, Token::from(TokenType::PointInc, 2, 2))
+            }
+            other => panic!("no_grow_move_right had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_grow_scan_right() {
+        let mut state = fixed_tape_state();
+        state.data.iter_mut().for_each(|val| *val = 1);
+        match run_source(&mut state, ">[>>>]") {
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Tape,
+                value: 10,
+                token: Some(tok),
+            }) => {
+                assert_eq!(tok, Token::from(TokenType::PointInc, 1, 5))
+            }
+            other => panic!("no_grow_scan_right had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_loop() {
+        let exprs = parse_tokens(lex_string("+++++[->+>+<<]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs[1].ty, ExprType::LinearLoop(vec![(1, 1), (2, 1)]));
+
+        let mut state = ProgState::with_output(vec![]);
+        state.enable_profile();
+        state.run(&exprs).unwrap();
+        assert_eq!(state.data[..3], [0, 5, 5]);
+        assert_eq!(state.take_profile().unwrap()["duplicate loop"], 1);
+    }
+
+    #[test]
+    fn move_cell() {
+        let exprs = parse_tokens(lex_string("+++>++<[->+<]".to_string()).unwrap()).unwrap();
+        assert_eq!(exprs[4].ty, ExprType::MoveCell { offset: 1 });
+
+        let mut state = ProgState::with_output(vec![]);
+        state.enable_profile();
+        state.run(&exprs).unwrap();
+        assert_eq!(state.data[..2], [0, 5]);
+        assert_eq!(state.data_index, 0);
+        assert_eq!(state.take_profile().unwrap()["move cell"], 1);
+
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            arith: ArithMode::Strict,
+            ..Config::default()
+        });
+        state.write_cells(0, [2, Cell::MAX - 1]);
+        match state.run(&exprs[4..]) {
+            Err(BrainfartError::ValMaxInc(tok, _)) => {
+                assert_eq!(tok, Token::from(TokenType::ValInc, 1, 11))
+            }
+            other => panic!("move_cell had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detect_hangs() {
+        let run = |source: &str| {
+            let exprs = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+            let mut state = ProgState::with_output(vec![]);
+            let mut config = Config {
+                detect_hangs: true,
+                ..Config::default()
+            };
+            config.limits.steps = Some(10_000);
+            state.set_config(config);
+            state.run(&exprs).map(|_| state.data[..3].to_vec())
+        };
+
+        for source in ["+[]", "+[>[-]+<]"] {
+            match run(source) {
+                Err(BrainfartError::InfiniteLoop(tok)) => {
+                    assert_eq!(tok, Token::from(TokenType::IfZero, 1, 2), "{}", source)
+                }
+                other => panic!("detect_hangs had result {:?} for {}", other, source),
+            }
+        }
+        assert_eq!(run("++[>+++[>+<-]<-]").unwrap(), [0, 0, 6]);
+        assert!(matches!(
+            run("+[.]"),
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Steps,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn grow_chunk() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_grow_chunk(4096);
+        let len = state.data.len();
+        let source = format!("+++{}++", ">".repeat(len));
+        state
+            .run(&parse_tokens(lex_string(source).unwrap()).unwrap())
+            .unwrap();
+        assert_eq!(state.data.len(), len + 4096);
+        assert_eq!(state.data[0], 3);
+        assert_eq!(state.data[len], 2);
+
+        state.set_data_index(len + 5000);
+        assert_eq!(state.data.len(), len + 2 * 4096);
+        assert_eq!(state.data[len], 2);
+    }
+
+    #[test]
+    fn step_one_expr() {
+        let exprs = parse_tokens(lex_string("+++>++[-<+>]".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        let mut positions = vec![];
+        let mut position = 0;
+        while let StepOutcome::Ran { next } = state.step(&exprs, position).unwrap() {
+            positions.push(state.data_index);
+            position = next;
+        }
+
+        assert_eq!(positions, vec![0, 1, 1, 1]);
+        assert_eq!(position, exprs.len());
+        assert_eq!(state.data[..2], [5, 0]);
+    }
+
+    #[test]
+    fn step_after_poking() {
+        let exprs = parse_tokens(lex_string("+++>++[-<+>]<.".to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        let StepOutcome::Ran { next } = state.step(&exprs, 0).unwrap() else {
+            panic!("step_after_poking finished early");
+        };
+        state.write_cells(0, [60]);
+        state.set_data_index(1);
+        let mut position = next + 1;
+        while let StepOutcome::Ran { next } = state.step(&exprs, position).unwrap() {
+            position = next;
+        }
+
+        assert_eq!(state.output(), b">");
+    }
+
+    #[test]
+    fn linear_loop_neighbors() {
+        for (source, cells, folded) in [
+            (">+++[-<++>]", vec![6, 0], true),
+            (">>++[-<+<+++>>]", vec![6, 2, 0], true),
+            ("+>++[-<+>>]", vec![2, 1, 0], false),
+            (">+>+>+[-<]", vec![0, 0, 0, 0], false),
+        ] {
+            let exprs = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+            let last = &exprs.last().unwrap().ty;
+            assert_eq!(
+                matches!(last, ExprType::LinearLoop(_)),
+                folded,
+                "{}",
+                source
+            );
+
+            let mut state = ProgState::with_output(vec![]);
+            state.run(&exprs).unwrap();
+            assert_eq!(state.data[..cells.len()], cells, "{}", source);
+        }
+    }
+
+    #[test]
+    fn linear_loop_error_token() {
+        let mut state = ProgState::with_output(vec![]);
+        match run_source(&mut state, ">+[-<<+>>]") {
+            Err(BrainfartError::PointZeroDec(tok)) => {
+                assert_eq!(tok, Token::from(TokenType::PointDec, 1, 6))
+            }
+            other => panic!("linear_loop_error_token had result {:?}", other),
+        }
+
+        let mut state = ProgState::with_output(vec![]);
+        match run_source(&mut state, "++>+<[->>+<-<]") {
+            Err(BrainfartError::ValZeroDec(tok)) => {
+                assert_eq!(tok, Token::from(TokenType::ValDec, 1, 12))
+            }
+            other => panic!("linear_loop_error_token had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn linear_loop_wrapping() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            arith: ArithMode::Wrapping,
+            ..Config::default()
+        });
+        run_source(&mut state, "+++[->--<]").unwrap();
+        assert_eq!(state.data[1], u32::MAX as Cell - 5);
+    }
+
+    #[test]
+    fn eof_flag_cell() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            eof_flag_cell: Some(5),
+            ..Config::default()
+        });
+        state.set_input(&b"a\nb\n"[..]);
+
+        run_source(&mut state, ",>,").unwrap();
+        assert_eq!(state.data[..2], ['a' as Cell, 'b' as Cell]);
+        assert_eq!(state.data[5], 0);
+
+        run_source(&mut state, ">,").unwrap();
+        assert_eq!(state.current(), 0);
+        assert_eq!(state.data[5], 1);
+    }
+
+    #[test]
+    fn deeply_nested_loops() {
+        let depth = 10000;
+        let source = format!("+{}->+<{}", "[".repeat(depth), "]".repeat(depth));
+        let exprs = parse_tokens(lex_string(source).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.run(&exprs).unwrap();
+        assert_eq!(state.data[..2], [0, 1]);
+    }
+
+    #[test]
+    fn tape_dump_trimmed() {
+        let mut state = ProgState::with_output(vec![]);
+        run_source(&mut state, "++>>+++>>>").unwrap();
+        let dump = state.tape_dump();
+        assert_eq!(dump.cells, vec![2, 0, 3]);
+        assert_eq!(dump.pointer, 5);
+        assert_eq!(dump.cell_width, 32);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn tape_dump_json() {
+        let mut state = ProgState::with_output(vec![]);
+        run_source(&mut state, "+++[->++<]>>+<").unwrap();
+        let json = serde_json::to_string(&state.tape_dump()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["pointer"], 1);
+        assert_eq!(value["cells"], serde_json::json!([0, 6, 1]));
+        assert_eq!(value["cell_width"], 32);
+    }
+
+    #[test]
+    fn control_output_warning() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            warn_control_output: true,
+            ..Config::default()
+        });
+        run_source(&mut state, &format!("{}.", "+".repeat(72))).unwrap();
+        assert_eq!(state.take_warnings(), vec![]);
+
+        run_source(&mut state, "[-]..").unwrap();
+        assert_eq!(
+            state.take_warnings(),
+            vec![BrainfartWarning::ControlOutput(
+                Token::from(TokenType::Output, 1, 4),
+                0
+            )]
+        );
+    }
+
+    #[test]
+    fn max_loop_depth() {
+        let mut state = ProgState::with_output(vec![]);
+        run_source(&mut state, "+[>+[-.]<-]").unwrap();
+        assert_eq!(state.take_max_loop_depth(), 2);
+
+        run_source(&mut state, "+[>[-.]<-]").unwrap();
+        assert_eq!(state.take_max_loop_depth(), 1);
+    }
+
+    #[test]
+    fn resume_after_input() {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_input_channel(receiver);
+        let exprs = parse_tokens(lex_string("+[,.]".to_string()).unwrap()).unwrap();
+        let mut execution = Execution::new(&exprs);
+
+        assert_eq!(
+            state.resume(&mut execution).unwrap(),
+            RunStatus::Suspended {
+                reason: SuspendReason::NeedInput
+            }
+        );
+        assert_eq!(state.output(), b"");
+
+        sender.send(b'A').unwrap();
+        assert_eq!(
+            state.resume(&mut execution).unwrap(),
+            RunStatus::Suspended {
+                reason: SuspendReason::NeedInput
+            }
+        );
+        assert_eq!(state.output(), b"A");
+
+        sender.send(0).unwrap();
+        assert_eq!(state.resume(&mut execution).unwrap(), RunStatus::Finished);
+        assert!(execution.is_finished());
+        assert_eq!(state.output(), b"A\0");
+    }
+
+    #[test]
+    fn sandbox_stops_runaway() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config::default().sandboxed());
+        match run_source(&mut state, "+[.]") {
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Output,
+                value,
+                token: Some(tok),
+            }) => {
+                assert_eq!(tok, Token::from(TokenType::Output, 1, 3));
+                assert_eq!(state.output().len() as u64, value);
+            }
+            other => panic!("sandbox_stops_runaway had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            limits: Limits {
+                steps: Some(100),
+                ..Limits::default()
+            },
+            ..Config::default()
+        });
+        match run_source(&mut state, "+[]") {
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Steps,
+                value: 100,
+                token: Some(tok),
+            }) => {
+                assert_eq!(tok, Token::from(TokenType::IfZero, 1, 2))
+            }
+            other => panic!("step_limit had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_cells_preloads() {
+        let mut state = ProgState::with_output(vec![]);
+        state.write_cells(2, "hi".chars().map(|c| c as Cell));
+        run_source(&mut state, ">>[.>]").unwrap();
+        assert_eq!(state.output(), b"hi");
+    }
+
+    #[test]
+    fn time_limit() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            limits: Limits {
+                time: Some(Duration::ZERO),
+                ..Limits::default()
+            },
+            ..Config::default()
+        });
+        match run_source(&mut state, "+[>+<]") {
+            Err(BrainfartError::LimitExceeded {
+                kind: LimitKind::Time,
+                value: 0,
+                token: Some(_),
+            }) => (),
+            other => panic!("time_limit had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn uninit_read_warning() {
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(Config {
+            warn_uninit: true,
+            ..Config::default()
+        });
+        run_source(&mut state, "+>[-]<.").unwrap();
+        assert_eq!(state.take_warnings(), vec![]);
+
+        run_source(&mut state, ">>.").unwrap();
+        assert_eq!(
+            state.take_warnings(),
+            vec![BrainfartWarning::UninitRead(
+                Token::from(TokenType::Output, 1, 3),
+                2
+            )]
+        );
+    }
+
+    #[test]
+    fn checkpoint_resume() {
+        let source = "++++++++[>++++[>++>+++<<-]>+<<-]>>.>+.<<[-]>[>>+<<-]>>.";
+        let exprs = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+        let mut uninterrupted = ProgState::with_output(vec![]);
+        uninterrupted.run(&exprs).unwrap();
+
+        let checkpoints: Rc<RefCell<Vec<Checkpoint>>> = Rc::default();
+        let saved = checkpoints.clone();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_checkpoint_hook(10, move |checkpoint| {
+            saved.borrow_mut().push(checkpoint.clone())
+        });
+        state.run(&exprs).unwrap();
+        assert_eq!(state.output(), uninterrupted.output());
+
+        let checkpoints = checkpoints.borrow();
+        assert!(checkpoints
+            .iter()
+            .any(|checkpoint| checkpoint.positions.len() > 1));
+        for checkpoint in checkpoints.iter() {
+            let checkpoint: Checkpoint = checkpoint.to_string().parse().unwrap();
+            let mut resumed = ProgState::with_output(vec![]);
+            let mut execution = resumed.restore_checkpoint(&checkpoint, &exprs).unwrap();
+            resumed.finish(&mut execution).unwrap();
+
+            assert_eq!(resumed.tape_dump(), uninterrupted.tape_dump());
+            let mut output = state.output()[..checkpoint.output_count as usize].to_vec();
+            output.extend(resumed.output());
+            assert_eq!(&output, uninterrupted.output());
+        }
+    }
+
+    #[test]
+    fn readonly_write_error() {
+        let config = Config {
+            readonly: Some(ReadOnly {
+                below: 2,
+                from: ReadOnlyStart::FirstOutput,
+            }),
+            ..Config::default()
+        };
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(config.clone());
+        run_source(&mut state, "+++>++>.+<<.>>+.").unwrap();
+
+        let mut state = ProgState::with_output(vec![]);
+        state.set_config(config);
+        match run_source(&mut state, "+++>++>.<<\n.>-") {
+            Err(BrainfartError::ReadOnlyWrite(token, index)) => {
+                assert_eq!(token, Token::from(TokenType::ValDec, 2, 3));
+                assert_eq!(index, 1);
+            }
+            other => panic!("readonly_write_error had result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn two_tape_isolation() {
+        let two_tape = CommandSet::default().with_lang(Lang::TwoTape);
+        let parse = |source: &str| -> Vec<Expr> {
+            ExprStream::new(Lexer::with_commands(source.chars(), two_tape))
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        let mut state = ProgState::with_output(vec![]);
+        let exprs = parse("+++>++}+++++>+{");
+        let mut execution = Execution::new(&exprs);
+        state.finish(&mut execution).unwrap();
+        let first = state.tape_dump();
+        assert_eq!((first.cells.as_slice(), first.pointer), (&[3, 2][..], 1));
+
+        let checkpoint = state.checkpoint(&execution);
+        state.run(&parse("}")).unwrap();
+        let second = state.tape_dump();
+        assert_eq!((second.cells.as_slice(), second.pointer), (&[5, 1][..], 1));
+
+        let mut resumed = ProgState::with_output(vec![]);
+        resumed.restore_checkpoint(&checkpoint, &exprs).unwrap();
+        resumed.run(&parse("}")).unwrap();
+        assert_eq!(resumed.tape_dump(), second);
+    }
+
+    #[test]
+    fn loop_free_fast_path() {
+        let source = format!("{}.+.>++[-]>{}.<<.,.", "+".repeat(72), "+".repeat(33));
+        let exprs = parse_tokens(lex_string(source).unwrap()).unwrap();
+        assert!(!exprs
+            .iter()
+            .any(|expr| matches!(expr.ty, ExprType::LoopBlock(_))));
+
+        let mut fast = ProgState::with_output(vec![]);
+        fast.set_input(io::Cursor::new("z"));
+        fast.enable_profile();
+        fast.run(&exprs).unwrap();
+
+        let mut general = ProgState::with_output(vec![]);
+        general.set_input(io::Cursor::new("z"));
+        general.enable_profile();
+        general.finish(&mut Execution::new(&exprs)).unwrap();
+
+        assert_eq!(fast.output(), b"HI!Iz");
+        assert_eq!(fast.output(), general.output());
+        assert_eq!(fast.tape_dump(), general.tape_dump());
+        assert_eq!(fast.take_profile(), general.take_profile());
+    }
+
+    #[test]
+    fn output_at_neighbor() {
+        let mut state = ProgState::with_output(vec![]);
+        run_source(&mut state, &format!(">{}<+>.<", "+".repeat(65))).unwrap();
+        assert_eq!(state.output(), b"A");
+        let dump = state.tape_dump();
+        assert_eq!((dump.cells.as_slice(), dump.pointer), (&[1, 65][..], 0));
+
+        match run_source(&mut ProgState::with_output(vec![]), "<.>") {
+            Err(BrainfartError::PointZeroDec(token)) => {
+                assert_eq!(token, Token::from(TokenType::PointDec, 1, 1))
+            }
+            other => panic!("output_at_neighbor had result {:?}", other),
         }
-        Ok(())
     }
 }
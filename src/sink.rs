@@ -0,0 +1,315 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+
+#[cfg(feature = "hash")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "hash")]
+use crate::progstate::TapeDump;
+
+/// The FNV-1a 64-bit offset basis, the starting value of the rolling hash.
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+/// The FNV-1a 64-bit prime, multiplied in for each byte.
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A CountingSink is an output sink that discards program output, keeping only the number of bytes
+/// written and a rolling FNV-1a hash of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountingSink {
+    pub count: u64,
+    pub hash: u64,
+}
+
+impl Default for CountingSink {
+    fn default() -> Self {
+        CountingSink {
+            count: 0,
+            hash: FNV_OFFSET,
+        }
+    }
+}
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+        self.count += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Display for CountingSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes, hash {:016x}", self.count, self.hash)
+    }
+}
+
+/// An RleSink is an output sink that keeps program output run-length encoded, as each byte and how
+/// many times in a row it was written, however the writes were split up. It is displayed with one
+/// run per line, as the count followed by the byte quoted and escaped, such as `100 'A'`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RleSink {
+    pub runs: Vec<(u8, u64)>,
+}
+
+impl Write for RleSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            match self.runs.last_mut() {
+                Some((prev, count)) if *prev == byte => *count += 1,
+                _ => self.runs.push((byte, 1)),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Display for RleSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, (byte, count)) in self.runs.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{} '{}'", count, byte.escape_ascii())?;
+        }
+        Ok(())
+    }
+}
+
+/// How many bytes a HexdumpSink shows on each line.
+const HEXDUMP_WIDTH: usize = 16;
+
+/// A HexdumpSink is an output sink that keeps program output to display it as a hex dump, for
+/// programs whose output is binary. Each line shows the offset of its first byte, up to 16 bytes in
+/// hex and the same bytes as ASCII, with `.` standing for anything unprintable, such as
+/// `00000000  41 42 0a  ...  |AB.|`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HexdumpSink {
+    pub bytes: Vec<u8>,
+}
+
+impl Write for HexdumpSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Display for HexdumpSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.bytes.chunks(HEXDUMP_WIDTH).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:08x}  ", i * HEXDUMP_WIDTH)?;
+            for j in 0..HEXDUMP_WIDTH {
+                if j == HEXDUMP_WIDTH / 2 {
+                    write!(f, " ")?;
+                }
+                match line.get(j) {
+                    Some(byte) => write!(f, "{:02x} ", byte)?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            let text: String = line
+                .iter()
+                .map(|&byte| match byte {
+                    b' '..=b'~' => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            write!(f, " |{}|", text)?;
+        }
+        Ok(())
+    }
+}
+
+/// A HashingSink is an output sink that discards program output, keeping only a SHA-256 digest of
+/// it, which is displayed in hex. Requires the `hash` feature.
+#[cfg(feature = "hash")]
+#[derive(Debug, Clone, Default)]
+pub struct HashingSink {
+    hasher: Sha256,
+    count: u64,
+}
+
+#[cfg(feature = "hash")]
+impl HashingSink {
+    /// A HashingSink whose digest also covers the given final tape after the output written so far,
+    /// so that it characterizes a whole run. The output's length comes before the tape, so that
+    /// output bytes cannot be mistaken for cells. Each cell is hashed as 16 little-endian bytes
+    /// however wide the build stores it, so that the digest is the same with the `unbounded`
+    /// feature as without it.
+    pub fn with_tape(&self, tape: &TapeDump) -> HashingSink {
+        let mut hasher = self.hasher.clone();
+        hasher.update(self.count.to_le_bytes());
+        hasher.update((tape.cells.len() as u64).to_le_bytes());
+        for cell in &tape.cells {
+            let bytes = cell.to_le_bytes();
+            hasher.update(bytes);
+            hasher.update(&[0; 16][bytes.len()..]);
+        }
+        hasher.update((tape.pointer as u64).to_le_bytes());
+        HashingSink {
+            hasher,
+            count: self.count,
+        }
+    }
+}
+
+#[cfg(feature = "hash")]
+impl Write for HashingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.count += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "hash")]
+impl Display for HashingSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for byte in self.hasher.clone().finalize() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+    use crate::progstate::ProgState;
+    use crate::sink::{CountingSink, HexdumpSink, RleSink};
+
+    #[test]
+    fn counting_sink_matches_output() {
+        let source = include_str!("../examples/hello.bf").to_string();
+        let exprs = parse_tokens(lex_string(source).unwrap()).unwrap();
+
+        let mut output: Vec<u8> = vec![];
+        ProgState::with_output(&mut output).run(&exprs).unwrap();
+        let mut counted = ProgState::with_output(CountingSink::default());
+        counted.run(&exprs).unwrap();
+
+        let mut expected = CountingSink::default();
+        expected.write_all(&output).unwrap();
+        assert_eq!(counted.output().count, output.len() as u64);
+        assert_eq!(counted.output(), &expected);
+    }
+
+    #[test]
+    fn counting_sink_display() {
+        let mut sink = CountingSink::default();
+        sink.write_all(b"a").unwrap();
+        assert_eq!(format!("{}", sink), "1 bytes, hash af63dc4c8601ec8c");
+    }
+
+    #[test]
+    fn rle_sink_runs() {
+        let mut state = ProgState::with_output(RleSink::default());
+        let source = format!("{}{}+.>++++++++++.", "+".repeat(65), ".".repeat(100));
+        state
+            .run(&parse_tokens(lex_string(source).unwrap()).unwrap())
+            .unwrap();
+        assert_eq!(
+            state.output().runs,
+            vec![(b'A', 100), (b'B', 1), (b'\n', 1)]
+        );
+        assert_eq!(format!("{}", state.output()), "100 'A'\n1 'B'\n1 '\\n'");
+    }
+
+    #[test]
+    fn hexdump_sink_lines() {
+        let mut state = ProgState::with_output(HexdumpSink::default());
+        let source = format!("{}.+.", "+".repeat(65));
+        state
+            .run(&parse_tokens(lex_string(source).unwrap()).unwrap())
+            .unwrap();
+        let dump = state.output().to_string();
+        assert!(dump.contains("41 42"));
+        assert!(dump.ends_with("|AB|"));
+
+        let mut sink = HexdumpSink::default();
+        sink.write_all(b"0123456789abcdef\n").unwrap();
+        assert_eq!(
+            sink.to_string(),
+            concat!(
+                "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n",
+                "00000010  0a                                                |.|"
+            )
+        );
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn hashing_sink_digest() {
+        use crate::sink::HashingSink;
+
+        let source = include_str!("../examples/hello.bf").to_string();
+        let exprs = parse_tokens(lex_string(source).unwrap()).unwrap();
+        let digests: Vec<String> = (0..2)
+            .map(|_| {
+                let mut state = ProgState::with_output(HashingSink::default());
+                state.run(&exprs).unwrap();
+                state.output().to_string()
+            })
+            .collect();
+        assert_eq!(digests[0], digests[1]);
+        assert_eq!(
+            digests[0],
+            "03ba204e50d126e4674c005e04d82e84c21366780af1f43bd54a37816b6ab340"
+        );
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn hashing_sink_with_tape() {
+        use crate::sink::HashingSink;
+
+        let digest = |source: &str| {
+            let exprs = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+            let mut state = ProgState::with_output(HashingSink::default());
+            state.run(&exprs).unwrap();
+            state.output().with_tape(&state.tape_dump()).to_string()
+        };
+        assert_eq!(
+            digest("+++."),
+            "c6daa02f9b5adddf891119f45393ae65ea38fa525de6c4a3403cd5db13e0671b"
+        );
+        let expected = digest("++++[>++++++++<-]>+.");
+        assert_eq!(digest(">+++++++++++++++++++++++++++++++++."), expected);
+        assert_ne!(digest(">+++++++++++++++++++++++++++++++++.+"), expected);
+        assert_ne!(digest(">+++++++++++++++++++++++++++++++++.<"), expected);
+
+        let output_only = ProgState::with_output(HashingSink::default());
+        assert_ne!(
+            output_only
+                .output()
+                .with_tape(&output_only.tape_dump())
+                .to_string(),
+            output_only.output().to_string()
+        );
+    }
+}
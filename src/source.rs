@@ -0,0 +1,247 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+
+/// Open the program at the given path for reading. Files ending in `.gz` are decompressed as they
+/// are read when the `gzip` feature is enabled, and rejected otherwise.
+pub fn open_source(path: &str) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    if path.ends_with(".gz") {
+        open_gzip(file)
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// The path that stands for a program read from stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// Read the whole program at the given path into a String, decompressing it if needed. The program
+/// at STDIN_PATH is read from stdin, leaving out any input after it.
+pub fn read_source(path: &str) -> io::Result<String> {
+    if path == STDIN_PATH {
+        return read_stdin_program().map(|(program, _)| program);
+    }
+    let mut contents = String::new();
+    open_source(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// The paths to run when none are given: the program piped into stdin, or nothing if stdin is a
+/// terminal, since a program is not typed in by hand.
+pub fn default_paths(stdin_is_terminal: bool) -> Vec<String> {
+    match stdin_is_terminal {
+        true => vec![],
+        false => vec![STDIN_PATH.to_string()],
+    }
+}
+
+/// Read all of stdin as a program followed by its input, split as by `split_program`.
+pub fn read_stdin_program() -> io::Result<(String, Option<String>)> {
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents)?;
+    Ok(split_program(contents))
+}
+
+/// Split the given text on its first `!` into a program and the input to run it on, as bff does
+/// for a program and its input piped in together. Without a `!`, it is all program.
+pub fn split_program(mut contents: String) -> (String, Option<String>) {
+    match contents.find('!') {
+        Some(at) => {
+            let input = contents[at + 1..].to_string();
+            contents.truncate(at);
+            (contents, Some(input))
+        }
+        None => (contents, None),
+    }
+}
+
+/// Replace each directory among the given paths with the `.bf` files directly inside it, in sorted
+/// order. Other paths are kept as they are.
+pub fn expand_dirs(paths: &[String]) -> io::Result<Vec<String>> {
+    let mut expanded: Vec<String> = vec![];
+    for path in paths {
+        let is_dir = fs::metadata(path).is_ok_and(|metadata| metadata.is_dir());
+        if !is_dir {
+            expanded.push(path.clone());
+            continue;
+        }
+
+        let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<_>>()?;
+        entries.retain(|entry| entry.is_file() && entry.extension().is_some_and(|ext| ext == "bf"));
+        entries.sort();
+        expanded.extend(
+            entries
+                .into_iter()
+                .map(|entry| entry.to_string_lossy().into_owned()),
+        );
+    }
+    Ok(expanded)
+}
+
+/// How many columns apart the tab stops are when showing a line of source, unless `--tab-width` is
+/// given.
+pub const TAB_WIDTH: usize = 8;
+
+/// Show the given line of the source, numbered, with a caret under the given column. Lines and
+/// columns are counted as the lexer counts them, with each character one column and each `\n` or
+/// `\r` ending a line. Tabs are expanded to the next tab stop, the given width apart, so the caret
+/// lines up under the character it points at however the line mixes tabs and spaces. Returns None
+/// if the source has no such line.
+pub fn source_context(source: &str, line: u32, col: u32, tab_width: usize) -> Option<String> {
+    let text = source
+        .split(['\n', '\r'])
+        .nth(line.checked_sub(1)? as usize)?;
+    let tab_width = tab_width.max(1);
+    let mut shown = String::new();
+    let mut width = 0;
+    let mut caret = None;
+    for (i, c) in text.chars().enumerate() {
+        if i + 1 == col as usize {
+            caret = Some(width);
+        }
+        if c == '\t' {
+            let stop = (width / tab_width + 1) * tab_width;
+            shown.extend(std::iter::repeat_n(' ', stop - width));
+            width = stop;
+        } else {
+            shown.push(c);
+            width += 1;
+        }
+    }
+
+    let number = line.to_string();
+    Some(format!(
+        "{} | {}\n{} | {}^",
+        number,
+        shown,
+        " ".repeat(number.len()),
+        " ".repeat(caret.unwrap_or(width))
+    ))
+}
+
+#[cfg(feature = "gzip")]
+fn open_gzip(file: File) -> io::Result<Box<dyn BufRead>> {
+    let decoder = flate2::read::GzDecoder::new(file);
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn open_gzip(_file: File) -> io::Result<Box<dyn BufRead>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading gzip-compressed programs requires the gzip feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::source::{default_paths, expand_dirs, read_source, source_context, split_program};
+
+    /// A path in the temporary directory unique to this test process.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("brainfart-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn read_plain_source() {
+        let path = temp_path("plain.bf");
+        fs::write(&path, "+++.").unwrap();
+        assert_eq!(read_source(path.to_str().unwrap()).unwrap(), "+++.");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn default_paths_piped() {
+        assert_eq!(default_paths(false), vec!["-"]);
+        assert_eq!(default_paths(true), Vec::<String>::new());
+    }
+
+    #[test]
+    fn split_program_on_first_bang() {
+        assert_eq!(
+            split_program(",.!Z!".to_string()),
+            (",.".to_string(), Some("Z!".to_string()))
+        );
+        assert_eq!(split_program("+.".to_string()), ("+.".to_string(), None));
+    }
+
+    #[test]
+    fn source_context_tabs() {
+        let source = "+++\n\t  >\t[<]\n";
+        assert_eq!(
+            source_context(source, 2, 6, 4).unwrap(),
+            "2 |       > [<]\n  |         ^"
+        );
+        assert_eq!(
+            source_context(source, 2, 4, 8).unwrap(),
+            "2 |           >     [<]\n  |           ^"
+        );
+        assert_eq!(source_context(source, 4, 1, 8), None);
+    }
+
+    #[test]
+    fn expand_dir_sorted() {
+        let dir = temp_path("expand");
+        fs::create_dir(&dir).unwrap();
+        for name in ["b.bf", "a.bf", "notes.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let paths = vec!["first.bf".to_string(), dir.to_string_lossy().into_owned()];
+        let expanded = expand_dirs(&paths).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            expanded,
+            vec![
+                "first.bf".to_string(),
+                dir.join("a.bf").to_string_lossy().into_owned(),
+                dir.join("b.bf").to_string_lossy().into_owned(),
+            ]
+        );
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn read_gzip_unsupported() {
+        let path = temp_path("unsupported.bf.gz");
+        fs::write(&path, "+++.").unwrap();
+        let err = read_source(path.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn read_gzip_source() {
+        use std::io::Write;
+
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        use crate::lexer::lex_string;
+        use crate::parser::parse_tokens;
+        use crate::progstate::ProgState;
+
+        let plain = include_str!("../examples/hello.bf");
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(plain.as_bytes()).unwrap();
+        let path = temp_path("hello.bf.gz");
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let contents = read_source(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents, plain);
+
+        let mut output: Vec<u8> = vec![];
+        let exprs = parse_tokens(lex_string(contents).unwrap()).unwrap();
+        ProgState::with_output(&mut output).run(&exprs).unwrap();
+        assert_eq!(output, b"Hello World!\n");
+    }
+}
@@ -1,6 +1,8 @@
+use std::cmp::Ordering;
+
 /// A TokenType is a valid "command" in bf that either changes the state of the program or performs
 /// an input/output side-effect.
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum TokenType {
     /// A Token that represents incrementing the pointer position
     PointInc,
@@ -20,10 +22,17 @@ pub enum TokenType {
     /// A Token that closes the "while not zero" loop at the pointer location, jumping to its
     /// corresponding IfZero token if the pointer location's value is zero
     IfNonZero,
+    /// A Token that represents making the first tape the one the other commands work on, in the
+    /// two-tape language
+    TapeDec,
+    /// A Token that represents making the second tape the one the other commands work on, in the
+    /// two-tape language
+    TapeInc,
 }
 
-/// A Token stores a TokenType and where it was encountered in the source file
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// A Token stores a TokenType and where it was encountered in the source file. Tokens are ordered
+/// by their position, line first, so diagnostics can be sorted and deduplicated.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Token {
     pub ty: TokenType,
     pub line: u32,
@@ -36,3 +45,83 @@ impl Token {
         Token { ty, line, col }
     }
 }
+
+impl Ord for Token {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.line, self.col, self.ty).cmp(&(other.line, other.col, other.ty))
+    }
+}
+
+impl PartialOrd for Token {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A TokenRun stands for `count` Tokens of the same type written consecutively on one line, the
+/// first of which is `token`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TokenRun {
+    pub token: Token,
+    pub count: u32,
+}
+
+impl TokenRun {
+    /// Expand the run into the individual Tokens it stands for. Columns stop at `u32::MAX` as they
+    /// do in the Lexer.
+    pub fn tokens(self) -> impl Iterator<Item = Token> {
+        let first = self.token;
+        (0..self.count).map(move |i| Token::from(first.ty, first.line, first.col.saturating_add(i)))
+    }
+}
+
+/// The line and column of every Token of the given type, in the order given, such as for an editor
+/// to highlight every input or jump between loops.
+pub fn positions_of(tokens: &[Token], ty: TokenType) -> Vec<(u32, u32)> {
+    tokens
+        .iter()
+        .filter(|tok| tok.ty == ty)
+        .map(|tok| (tok.line, tok.col))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::lexer::lex_string;
+    use crate::token::{positions_of, Token, TokenType};
+
+    #[test]
+    fn positions_of_loops() {
+        let tokens = lex_string("+[>[-]<-]\n>>[.>]\n[+]".to_string()).unwrap();
+        assert_eq!(
+            positions_of(&tokens, TokenType::IfZero),
+            vec![(1, 2), (1, 4), (2, 3), (3, 1)]
+        );
+        assert_eq!(positions_of(&tokens, TokenType::Input), vec![]);
+    }
+
+    #[test]
+    fn tokens_sort_by_position() {
+        let tokens = [
+            Token::from(TokenType::Output, 2, 1),
+            Token::from(TokenType::ValInc, 1, 5),
+            Token::from(TokenType::Output, 2, 1),
+            Token::from(TokenType::PointInc, 1, 12),
+        ];
+        let sorted: Vec<Token> = tokens
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            sorted,
+            vec![
+                Token::from(TokenType::ValInc, 1, 5),
+                Token::from(TokenType::PointInc, 1, 12),
+                Token::from(TokenType::Output, 2, 1),
+            ]
+        );
+    }
+}
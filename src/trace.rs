@@ -0,0 +1,136 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::config::Cell;
+use crate::token::Token;
+
+/// A TraceStep describes one Expr that a ProgState has just run: its name, the token it starts
+/// at, and where the data pointer and the value under it ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceStep {
+    pub name: &'static str,
+    pub token: Token,
+    pub pointer: usize,
+    pub value: Cell,
+}
+
+/// The format a TraceStep is written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// A line of text for people to read, with the position of the Expr
+    #[default]
+    Text,
+    /// Comma-separated instruction, pointer and value, after a header row
+    Csv,
+    /// A JSON object per line with the same fields as Csv
+    Jsonl,
+}
+
+impl TraceFormat {
+    /// The line to write before any steps, if the format has one.
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            TraceFormat::Csv => Some("instruction,pointer,value"),
+            TraceFormat::Text | TraceFormat::Jsonl => None,
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "csv" => Ok(TraceFormat::Csv),
+            "jsonl" => Ok(TraceFormat::Jsonl),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TraceStep {
+    /// Display the TraceStep as a single line in the given format.
+    pub fn display(&self, format: TraceFormat) -> TraceLine<'_> {
+        TraceLine { step: self, format }
+    }
+}
+
+/// A TraceStep paired with the format to display it in, from `TraceStep::display`.
+pub struct TraceLine<'a> {
+    step: &'a TraceStep,
+    format: TraceFormat,
+}
+
+impl Display for TraceLine<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let step = self.step;
+        match self.format {
+            TraceFormat::Text => write!(
+                f,
+                "TRACE line {} col {}: {}, pointer {}, value {}",
+                step.token.line, step.token.col, step.name, step.pointer, step.value
+            ),
+            TraceFormat::Csv => write!(f, "{},{},{}", step.name, step.pointer, step.value),
+            TraceFormat::Jsonl => write!(
+                f,
+                "{{\"instruction\":\"{}\",\"pointer\":{},\"value\":{}}}",
+                step.name, step.pointer, step.value
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::lexer::lex_string;
+    use crate::parser::parse_tokens;
+    use crate::progstate::ProgState;
+    use crate::trace::TraceFormat;
+
+    /// The lines of the trace of the given source in the given format, header first.
+    fn trace_lines(source: &str, format: TraceFormat) -> Vec<String> {
+        let lines = Rc::new(RefCell::new(vec![]));
+        lines.borrow_mut().extend(format.header().map(String::from));
+        let seen = lines.clone();
+        let exprs = parse_tokens(lex_string(source.to_string()).unwrap()).unwrap();
+        let mut state = ProgState::with_output(vec![]);
+        state.set_trace_hook(move |step| seen.borrow_mut().push(step.display(format).to_string()));
+        state.run(&exprs).unwrap();
+        let lines = lines.borrow().clone();
+        lines
+    }
+
+    #[test]
+    fn trace_csv() {
+        let lines = trace_lines("++>+", TraceFormat::Csv);
+        assert_eq!(
+            lines,
+            vec![
+                "instruction,pointer,value",
+                "add,0,2",
+                "move right,1,0",
+                "add,1,1"
+            ]
+        );
+        assert!(lines.iter().all(|line| line.split(',').count() == 3));
+    }
+
+    #[test]
+    fn trace_text_and_jsonl() {
+        assert_eq!(
+            trace_lines("+[-]", TraceFormat::Text),
+            vec![
+                "TRACE line 1 col 1: add, pointer 0, value 1",
+                "TRACE line 1 col 3: set, pointer 0, value 0",
+            ]
+        );
+        assert_eq!(
+            trace_lines("++", TraceFormat::Jsonl),
+            vec!["{\"instruction\":\"add\",\"pointer\":0,\"value\":2}"]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::mpsc::Receiver;
+
+#[cfg(feature = "watch")]
+use std::path::Path;
+#[cfg(feature = "watch")]
+use std::sync::mpsc;
+
+#[cfg(feature = "watch")]
+use notify::{recommended_watcher, Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// The terminal escape sequence that clears the screen and moves the cursor to its top left.
+pub const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Call `run` once, then again each time a change arrives on the given channel, until the sending
+/// side is dropped. Changes that arrive together, such as the several an editor makes while saving
+/// a file, lead to a single run. Returns how many times `run` was called.
+pub fn watch_loop(changes: Receiver<()>, mut run: impl FnMut()) -> usize {
+    let mut runs = 0;
+    loop {
+        run();
+        runs += 1;
+        if changes.recv().is_err() {
+            return runs;
+        }
+        while changes.try_recv().is_ok() {}
+    }
+}
+
+/// Start watching the given files, sending a change on the returned channel each time one of them
+/// is written to or replaced. The directories holding the files are watched rather than the files
+/// themselves, so that a file an editor saves by replacing it is still followed. The files are only
+/// watched as long as the returned watcher is kept. Requires the `watch` feature.
+#[cfg(feature = "watch")]
+pub fn watch_files(paths: &[String]) -> notify::Result<(RecommendedWatcher, Receiver<()>)> {
+    let names: Vec<_> = paths
+        .iter()
+        .filter_map(|path| Path::new(path).file_name().map(|name| name.to_owned()))
+        .collect();
+    let (sender, changes) = mpsc::channel();
+    let mut watcher = recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let changed = event.kind.is_modify() || event.kind.is_create();
+        let watched = event.paths.iter().any(|path| {
+            path.file_name()
+                .is_some_and(|name| names.iter().any(|n| n == name))
+        });
+        if changed && watched {
+            let _ = sender.send(());
+        }
+    })?;
+    for path in paths {
+        let dir = match Path::new(path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok((watcher, changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use crate::watch::watch_loop;
+
+    #[test]
+    fn watch_loop_reruns_on_change() {
+        let (sender, changes) = mpsc::channel();
+        let mut sender = Some(sender);
+        let mut runs = 0;
+        let total = watch_loop(changes, || {
+            runs += 1;
+            match runs {
+                // A simulated save after the first run
+                1 => sender.as_ref().unwrap().send(()).unwrap(),
+                // Changes made together lead to a single run
+                2 => (0..3).for_each(|_| sender.as_ref().unwrap().send(()).unwrap()),
+                _ => sender = None,
+            }
+        });
+        assert_eq!(total, 3);
+        assert_eq!(runs, 3);
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watch_files_sees_write() {
+        use std::env;
+        use std::fs;
+        use std::time::Duration;
+
+        use crate::watch::watch_files;
+
+        let path = env::temp_dir().join(format!("brainfart-watch-{}.bf", std::process::id()));
+        fs::write(&path, "+.").unwrap();
+        let (_watcher, changes) = watch_files(&[path.to_string_lossy().into_owned()]).unwrap();
+        fs::write(&path, "++.").unwrap();
+        let changed = changes.recv_timeout(Duration::from_secs(10));
+        fs::remove_file(&path).unwrap();
+        assert!(changed.is_ok());
+    }
+}
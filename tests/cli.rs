@@ -0,0 +1,540 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Write the given source to a uniquely named temporary file, run the bft binary on it with the
+/// given flags, and remove the file.
+fn run_program(name: &str, source: &str, flags: &[&str]) -> Output {
+    let path = env::temp_dir().join(format!("brainfart-{}-{}.bf", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bft"))
+        .args(flags)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+#[test]
+fn exit_from_cell() {
+    let output = run_program(
+        "exit-from-cell",
+        "++++++[>+++++++<-]>",
+        &["--exit-from-cell"],
+    );
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn exit_from_cell_wraps() {
+    let source = "++++++++++++++++[>++++++++++++++++<-]>+";
+    let output = run_program("exit-from-cell-wraps", source, &["--exit-from-cell"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn exit_without_flag() {
+    let output = run_program("exit-without-flag", "++++++[>+++++++<-]>", &[]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn directory_sorted() {
+    let dir = env::temp_dir().join(format!("brainfart-dir-{}", std::process::id()));
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("b.bf"), "++++++++[>++++++++<-]>++.").unwrap();
+    fs::write(dir.join("a.bf"), "++++++++[>++++++++<-]>+.").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bft"))
+        .arg(&dir)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.stdout, b"AB");
+}
+
+#[test]
+fn chain_shares_tape() {
+    let first = env::temp_dir().join(format!("brainfart-chain-a-{}.bf", std::process::id()));
+    fs::write(&first, "++++++++[>++++++++<-]>+").unwrap();
+    let output = run_program("chain-b", ".+.", &["--chain", first.to_str().unwrap()]);
+    fs::remove_file(&first).unwrap();
+    assert_eq!(output.stdout, b"AB");
+}
+
+#[test]
+fn strict_canonical_semantics() {
+    // `+[+]` only ends once the cell wraps around to zero, and 191 decrements from there leave 65
+    let source = format!(
+        "+[+]{}.{}{}.",
+        "-".repeat(191),
+        ">".repeat(29999),
+        "+".repeat(66)
+    );
+    let output = run_program("strict", &source, &["--strict"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"AB");
+
+    let output = run_program("strict-left", "<+", &["--strict"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run_program("strict-right", &">".repeat(30000), &["--strict"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn cell_width_overflow_message() {
+    let output = run_program(
+        "cell-width-overflow",
+        &"+".repeat(300),
+        &["--cell-width", "8"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "ERROR line 1 col 256: Attempted to increment value past 255\n"
+    );
+}
+
+#[cfg(feature = "unbounded")]
+#[test]
+fn unbounded_cells_wrap_sub() {
+    let path = env::temp_dir().join(format!(
+        "brainfart-unbounded-wrap-{}.bf",
+        std::process::id()
+    ));
+    fs::write(&path, ",-.").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bft"))
+        .args(["--unbounded-cells", "--wrap"])
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"D\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"C");
+}
+
+#[cfg(not(feature = "unbounded"))]
+#[test]
+fn unbounded_cells_need_feature() {
+    let output = run_program("unbounded-feature", "+.", &["--unbounded-cells"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "ERROR: --unbounded-cells requires the unbounded feature\n"
+    );
+}
+
+#[test]
+fn constant_prefix_runs_instrumented() {
+    let source = "++++++++[>++++++++<-]>+.";
+    let output = run_program("prefix-max-steps", source, &["--max-steps", "4"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let output = run_program("prefix-trace", source, &["--trace"]);
+    assert!(output.status.success());
+    let trace = String::from_utf8_lossy(&output.stderr);
+    assert!(trace.contains("linear loop"));
+    assert!(!trace.contains("const output"));
+}
+
+#[test]
+fn deeply_nested_loops() {
+    // Every pass the program goes through by default, from parsing to running, has to handle
+    // loops nested this deep without overflowing the stack
+    let depth = 10000;
+    let source = format!(
+        "+{}->+<{}>{}.",
+        "[".repeat(depth),
+        "]".repeat(depth),
+        "+".repeat(64)
+    );
+    let output = run_program("deeply-nested", &source, &[]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+}
+
+#[test]
+fn deterministic_repeatable() {
+    let path = env::temp_dir().join(format!("brainfart-deterministic-{}.bf", std::process::id()));
+    fs::write(&path, ",.>,.>,[.,]-.").unwrap();
+    let run = || {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bft"))
+            .arg("--deterministic")
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"a\nb\n").unwrap();
+        child.wait_with_output().unwrap()
+    };
+    let first = run();
+    let second = run();
+    fs::remove_file(&path).unwrap();
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+    assert_eq!(first.stdout, b"ab ");
+}
+
+#[test]
+fn input_list_runs() {
+    let list = env::temp_dir().join(format!("brainfart-inputs-{}.txt", std::process::id()));
+    fs::write(&list, "a\nb\nc\n").unwrap();
+    let output = run_program(
+        "input-list",
+        ",+.",
+        &["--input-list", list.to_str().unwrap()],
+    );
+    fs::remove_file(&list).unwrap();
+    assert_eq!(output.stdout, b"b\nc\nd\n");
+}
+
+#[test]
+fn argv_echoed() {
+    let output = run_program("argv", ">[.>]", &["--argv", "hello", "--argv-cell", "1"]);
+    assert_eq!(output.stdout, b"hello");
+}
+
+#[test]
+fn diff_programs() {
+    let dir = env::temp_dir().join(format!("brainfart-diff-{}", std::process::id()));
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("a.bf"), "++++++++[>++++++++<-]>+.+.").unwrap();
+    fs::write(dir.join("b.bf"), "+++++[>+++++++++++++<-]>.+.").unwrap();
+    fs::write(dir.join("c.bf"), "+++++[>+++++++++++++<-]>.++.").unwrap();
+    let diff = |second: &str| {
+        Command::new(env!("CARGO_BIN_EXE_bft"))
+            .arg("--diff")
+            .arg(dir.join("a.bf"))
+            .arg(dir.join(second))
+            .output()
+            .unwrap()
+    };
+    let same = diff("b.bf");
+    let different = diff("c.bf");
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(same.stdout, b"equivalent\n");
+    assert_eq!(same.status.code(), Some(0));
+    assert_eq!(different.stdout, b"output differs at byte 1: 'B' vs 'C'\n");
+    assert_eq!(different.status.code(), Some(1));
+}
+
+#[test]
+fn diagnostics_stay_off_stdout() {
+    let source = format!("{}.<", "+".repeat(65));
+    let output = run_program("diagnostics-stderr", &source, &[]);
+    assert_eq!(output.stdout, b"A");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("ERROR line 1 col 67"));
+}
+
+#[test]
+fn merge_streams() {
+    let source = format!("{}.<", "+".repeat(65));
+    let output = run_program("merge-streams", &source, &["--merge-streams"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("AERROR line 1 col 67"));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn keep_going() {
+    let dir = env::temp_dir();
+    let bad = dir.join(format!(
+        "brainfart-keep-going-bad-{}.bf",
+        std::process::id()
+    ));
+    let good = dir.join(format!(
+        "brainfart-keep-going-good-{}.bf",
+        std::process::id()
+    ));
+    fs::write(&bad, "<").unwrap();
+    fs::write(&good, format!("{}.", "+".repeat(65))).unwrap();
+    let run = |flags: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_bft"))
+            .args(flags)
+            .arg(&bad)
+            .arg(&good)
+            .output()
+            .unwrap()
+    };
+
+    let stopped = run(&[]);
+    let kept_going = run(&["--keep-going"]);
+    fs::remove_file(&bad).unwrap();
+    fs::remove_file(&good).unwrap();
+
+    assert_eq!(stopped.status.code(), Some(1));
+    assert!(stopped.stdout.is_empty());
+    assert_eq!(kept_going.status.code(), Some(1));
+    assert_eq!(kept_going.stdout, b"A");
+    let stderr = String::from_utf8_lossy(&kept_going.stderr);
+    assert!(stderr.contains("keep-going-bad"));
+    assert!(stderr.ends_with("ERROR: 1 file failed\n"));
+}
+
+#[test]
+fn error_format_json() {
+    let output = run_program("error-json", "+]", &["--error-format", "json"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "{\"kind\":\"unmatched_close_bracket\",\"line\":1,\"col\":2,\
+         \"message\":\"Encountered unmatched closing bracket ]\"}\n"
+    );
+}
+
+#[test]
+fn bench() {
+    let output = run_program("bench", "++++++++[>++++++++<-]>+.", &["--bench"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let ran = stderr
+        .trim_end()
+        .split("ran in ")
+        .nth(1)
+        .expect("--bench reports how long the run took");
+    assert!(stderr.starts_with("BENCH "));
+    assert!(ran.chars().next().unwrap().is_ascii_digit());
+    assert_ne!(ran, "0ns");
+}
+
+#[test]
+fn tape_bound() {
+    let output = run_program("tape-bound", ">>>", &["--tape-bound"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with(": reaches at most cell 3\n"));
+
+    let output = run_program(
+        "tape-bound-start",
+        ">>>",
+        &["--tape-bound", "--start-index", "2"],
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with(": reaches at most cell 5\n"));
+
+    let output = run_program("tape-bound-unbounded", "[>]", &["--tape-bound"]);
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with(": unbounded\n"));
+}
+
+#[test]
+fn heatmap() {
+    let output = run_program("heatmap", "+>+>+++[.-]", &["--heatmap"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.lines().collect::<Vec<_>>(),
+        vec![
+            format!("HEATMAP cell 0: 1 {}", "#".repeat(4)),
+            format!("HEATMAP cell 1: 1 {}", "#".repeat(4)),
+            format!("HEATMAP cell 2: 11 {}", "#".repeat(40)),
+        ]
+    );
+}
+
+#[test]
+fn program_and_input_from_stdin() {
+    // Without a file, a program piped into stdin is run as if `-` were given
+    for args in [&["-"][..], &[]] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bft"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b",.!Z").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"Z");
+    }
+}
+
+#[test]
+fn minimize() {
+    let output = run_program("minimize", "+>++<[->+<]>>+<<<", &["--minimize"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with(".bf: <\n"));
+}
+
+#[test]
+fn progress() {
+    let source = format!("++++++[>++++++++<,-]>{}.", "+".repeat(17));
+    let output = run_program("progress", &source, &["--progress-every", "5"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.lines().count() >= 3);
+    assert!(stderr.lines().all(|line| line.starts_with("PROGRESS ")));
+}
+
+#[test]
+fn coverage() {
+    let output = run_program("coverage", "+.>[-<.>]\n+.", &["--coverage"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x01\x01");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .collect::<Vec<_>>(),
+        vec![
+            "COVERAGE line 1 col 5: never ran",
+            "COVERAGE line 1 col 6: never ran",
+            "COVERAGE 2 instructions never ran",
+        ]
+    );
+}
+
+#[test]
+fn coverage_dead_loop() {
+    let output = run_program("coverage-dead-loop", "+[-][.]", &["--coverage"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .collect::<Vec<_>>(),
+        vec![
+            "COVERAGE line 1 col 6: never ran",
+            "COVERAGE 1 instructions never ran",
+        ]
+    );
+}
+
+#[test]
+fn cells_from_env() {
+    let path = env::temp_dir().join(format!(
+        "brainfart-cells-from-env-{}.bf",
+        std::process::id()
+    ));
+    fs::write(&path, ">.<.").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bft"))
+        .args(["--cells-from-env", "BRAINFART_TEST_CELLS"])
+        .arg(&path)
+        .env("BRAINFART_TEST_CELLS", "hi")
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"ih");
+
+    let output = run_program(
+        "cells-from-env-unset",
+        ".",
+        &["--cells-from-env", "BRAINFART_TEST_UNSET"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn no_io_extras() {
+    let output = run_program(
+        "no-io-extras",
+        "+++.",
+        &["--no-io-extras", "--input-list", "inputs.txt"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim_end(),
+        "ERROR: --input-list cannot be used with --no-io-extras"
+    );
+
+    let output = run_program("no-io-extras-plain", "+++.", &["--no-io-extras"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x03");
+}
+
+#[test]
+fn sandbox_refuses_io_extras() {
+    let output = run_program(
+        "sandbox-io-extras",
+        "+++.",
+        &["--sandbox", "--input-list", "inputs.txt"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim_end(),
+        "ERROR: --input-list cannot be used with --sandbox"
+    );
+}
+
+#[test]
+fn detect_hangs() {
+    let output = run_program(
+        "detect-hangs",
+        "+[>[-]+<]",
+        &["--detect-hangs", "--max-steps", "1000000"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("line 1 col 2: Loop can never terminate once entered"));
+}
+
+#[test]
+fn infinite_loop_in_skipped_loop_runs() {
+    let source = format!(",[>[-]+[+]<-]{}.", "+".repeat(49));
+    let output = run_program("skipped-infinite-loop", &source, &[]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"1");
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn emit_llvm_outputs_low_byte() {
+    let source = format!("{}.", "+".repeat(200));
+    let output = run_program("emit-llvm-low-byte", &source, &["--emit-llvm"]);
+    assert!(output.status.success());
+    let Ok(mut lli) = Command::new("lli")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+    else {
+        return;
+    };
+    lli.stdin.take().unwrap().write_all(&output.stdout).unwrap();
+    let lowered = lli.wait_with_output().unwrap();
+    assert!(lowered.status.success());
+    assert_eq!(lowered.stdout, [200]);
+}
+
+#[test]
+fn show_source_context() {
+    let output = run_program(
+        "show-source-context",
+        "+++\n\t  <<<<",
+        &["--show-source-context"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2 col 4"), "{}", stderr);
+    assert!(stderr.ends_with("2 |           <<<<\n  |           ^\n"));
+
+    let output = run_program(
+        "show-source-context-width",
+        "+++\n\t  <<<<",
+        &["--show-source-context", "--tab-width", "2"],
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with("2 |     <<<<\n  |     ^\n"));
+}
+
+#[test]
+fn unicode_round_trip() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bft"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(",.>,.!ü\n😀\n".as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, "ü😀".as_bytes());
+}